@@ -21,6 +21,9 @@ use vfscore::{
 
 pub struct ExtFsProviderImpl;
 impl ExtDevProvider for ExtFsProviderImpl {
+    fn current_time(&self) -> VfsTimeSpec {
+        VfsTimeSpec::new(0, 0)
+    }
     fn rdev2device(&self, _rdev: u64) -> Option<Arc<dyn VfsInode>> {
         None
     }
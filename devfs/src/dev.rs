@@ -1,4 +1,5 @@
 use alloc::{string::String, sync::Arc, vec::Vec};
+use core::task::Waker;
 
 use unifs::{
     inode::{basic_file_stat, UniFsInodeSame},
@@ -9,10 +10,11 @@ use vfscore::{
     file::VfsFile,
     impl_file_inode_default,
     inode::{InodeAttr, VfsInode},
+    lock::{VfsFileLock, VfsLockOwner, VfsLockType},
     superblock::VfsSuperBlock,
     utils::{
-        VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsPollEvents, VfsRenameFlag, VfsTime,
-        VfsTimeSpec,
+        VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsPollEvents, VfsRenameFlag,
+        VfsTimeSpec, VfsTimes,
     },
     VfsResult,
 };
@@ -64,6 +66,9 @@ impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> VfsFile for DevFs
     fn poll(&self, event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
         self.real_dev()?.poll(event)
     }
+    fn poll_register(&self, event: VfsPollEvents, waker: &Waker) -> VfsResult<VfsPollEvents> {
+        self.real_dev()?.poll_register(event, waker)
+    }
 
     fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
         self.real_dev()?.ioctl(_cmd, _arg)
@@ -75,6 +80,21 @@ impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> VfsFile for DevFs
     fn fsync(&self) -> VfsResult<()> {
         self.real_dev()?.fsync()
     }
+    fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        self.basic.get_lock(lock)
+    }
+    fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.basic.set_lock(lock)
+    }
+    fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.basic.flock(owner, ty)
+    }
+    fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.basic.release_posix(pid)
+    }
+    fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.basic.release_flock(ofd)
+    }
 }
 
 impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> VfsInode for DevFsDevInode<T, R> {
@@ -87,8 +107,10 @@ impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> VfsInode for DevF
         self.basic.inner.lock().perm
     }
 
-    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
-        todo!()
+    fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
+        // the size of a device can't be changed, and truncating it is ignored like O_TRUNC
+        self.basic.set_attr(&attr);
+        Ok(())
     }
 
     fn get_attr(&self) -> VfsResult<VfsFileStat> {
@@ -118,12 +140,9 @@ impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> VfsInode for DevF
         Err(VfsError::NoSys)
     }
 
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
-        match time {
-            VfsTime::ModifiedTime(t) => self.basic.inner.lock().mtime = t,
-            VfsTime::AccessTime(t) => self.basic.inner.lock().atime = t,
-        }
-        self.basic.inner.lock().ctime = now;
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        let inner = &mut *self.basic.inner.lock();
+        times.apply(&mut inner.atime, &mut inner.mtime, &mut inner.ctime, now);
         Ok(())
     }
 }
@@ -4,14 +4,14 @@ use alloc::{
 };
 use core::ops::Deref;
 
-use unifs::inode::UniFsDirInode;
+use unifs::inode::{UniFsDirChildren, UniFsDirInode};
 use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_dir_inode_default,
     inode::InodeAttr,
     utils::{
-        VfsDirEntry, VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime,
+        VfsDirEntry, VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTimes,
     },
 };
 
@@ -28,9 +28,14 @@ impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> DevFsDirInode<T,
     ) -> Self {
         Self(UniFsDirInode {
             basic: UniFsInodeSame::new(sb, provider, inode_number, perm),
-            children: lock_api::Mutex::new(Vec::new()),
+            children: lock_api::Mutex::new(UniFsDirChildren::new()),
         })
     }
+    /// Update mtime and ctime after the children are changed
+    fn modified(&self) {
+        let basic = &self.0.basic;
+        basic.update_mtime(basic.provider.current_time());
+    }
 }
 
 impl<T: Send + Sync + 'static, R: VfsRawMutex + 'static> Deref for DevFsDirInode<T, R> {
@@ -41,8 +46,8 @@ impl<T: Send + Sync + 'static, R: VfsRawMutex + 'static> Deref for DevFsDirInode
 }
 
 impl<T: Send + Sync + 'static, R: VfsRawMutex + 'static> VfsFile for DevFsDirInode<T, R> {
-    fn readdir(&self, start_index: usize) -> VfsResult<Option<VfsDirEntry>> {
-        self.0.readdir(start_index)
+    fn readdir(&self, cookie: u64) -> VfsResult<Option<VfsDirEntry>> {
+        self.0.readdir(cookie)
     }
 }
 
@@ -93,7 +98,8 @@ impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> VfsInode for DevF
             }
         };
         sb.insert_inode(inode_number, inode.clone());
-        self.children.lock().push((name.to_string(), inode_number));
+        self.children.lock().push(name.to_string(), inode_number);
+        self.modified();
         Ok(inode)
     }
 
@@ -103,15 +109,9 @@ impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> VfsInode for DevF
 
     fn unlink(&self, name: &str) -> VfsResult<()> {
         let sb = self.basic.sb.upgrade().unwrap();
-        let mut children = self.children.lock();
-        children
-            .iter()
-            .position(|(n, _)| n == name)
-            .map_or(Err(VfsError::NoEntry), |index| {
-                let (_, inode_number) = children.remove(index);
-                sb.remove_inode(inode_number);
-                Ok(())
-            })?;
+        let inode_number = self.children.lock().remove(name).ok_or(VfsError::NoEntry)?;
+        sb.remove_inode(inode_number);
+        self.modified();
         Ok(())
     }
     fn symlink(&self, _name: &str, _sy_name: &str) -> VfsResult<Arc<dyn VfsInode>> {
@@ -164,7 +164,7 @@ impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> VfsInode for DevF
         self.0.rename_to(old_name, &new_parent.0, new_name, flag)
     }
 
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
-        self.0.update_time(time, now)
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        self.0.set_times(times, now)
     }
 }
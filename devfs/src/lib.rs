@@ -14,8 +14,9 @@ use vfscore::{
     dentry::VfsDentry,
     fstype::{FileSystemFlags, VfsFsType},
     inode::VfsInode,
+    lock::VfsLockList,
     superblock::VfsSuperBlock,
-    utils::{VfsNodePerm, VfsTimeSpec},
+    utils::{VfsMountFlags, VfsNodePerm, VfsTimeSpec},
     VfsResult,
 };
 
@@ -37,13 +38,14 @@ impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> DevFs<T, R> {
 impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> VfsFsType for DevFs<T, R> {
     fn mount(
         self: Arc<Self>,
-        _flags: u32,
+        flags: u32,
         ab_mnt: &str,
         _dev: Option<Arc<dyn VfsInode>>,
         _data: &[u8],
     ) -> VfsResult<Arc<dyn VfsDentry>> {
         if self.0.sb.lock().is_none() {
             let sb = UniFsSuperBlock::new(&(self.clone() as Arc<dyn VfsFsType>));
+            sb.set_mount_flags(VfsMountFlags::from_bits_truncate(flags));
             let root = Arc::new(DevFsDirInode::new(
                 0,
                 self.0.provider.clone(),
@@ -100,7 +102,10 @@ impl<T: DevKernelProvider + 'static, R: VfsRawMutex + 'static> DevInodeSameNew<T
                 mtime: time,
                 ctime: time,
                 perm,
+                uid: 0,
+                gid: 0,
             }),
+            locks: lock_api::Mutex::new(VfsLockList::new()),
         }
     }
 }
@@ -3,14 +3,14 @@ use alloc::{
     vec::Vec,
 };
 
-use unifs::inode::UniFsDirInode;
+use unifs::inode::{UniFsDirChildren, UniFsDirInode};
 use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_dir_inode_default,
     inode::InodeAttr,
     utils::{
-        VfsDirEntry, VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime,
+        VfsDirEntry, VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTimes,
     },
 };
 
@@ -27,7 +27,7 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> DynFsDirInode<T
     ) -> Self {
         Self(UniFsDirInode {
             basic: UniFsInodeSame::new(sb, provider, inode_number, perm),
-            children: lock_api::Mutex::new(Vec::new()),
+            children: lock_api::Mutex::new(UniFsDirChildren::new()),
         })
     }
 
@@ -62,10 +62,8 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> DynFsDirInode<T
             _ => return Err(VfsError::NoSys),
         };
         sb.insert_inode(inode_number, res.clone());
-        self.0
-            .children
-            .lock()
-            .push((name.to_string(), inode_number));
+        self.0.children.lock().push(name.to_string(), inode_number);
+        self.modified();
         Ok(res)
     }
     pub fn add_file_manually(
@@ -82,23 +80,29 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> DynFsDirInode<T
     }
 
     pub fn remove_manually(&self, name: &str) -> VfsResult<()> {
-        let mut children = self.0.children.lock();
-        let index = children
-            .iter()
-            .position(|(n, _)| n == name)
+        let inode_number = self
+            .0
+            .children
+            .lock()
+            .remove(name)
             .ok_or(VfsError::NoEntry)?;
-        let (_, inode_number) = children.remove(index);
         let sb = self.0.basic.sb.upgrade().unwrap();
         sb.remove_inode(inode_number);
         sb.inode_count
             .fetch_sub(1, core::sync::atomic::Ordering::SeqCst);
+        self.modified();
         Ok(())
     }
+    /// Update mtime and ctime after the children are changed
+    fn modified(&self) {
+        let basic = &self.0.basic;
+        basic.update_mtime(basic.provider.current_time());
+    }
 }
 
 impl<T: Send + Sync + 'static, R: VfsRawMutex + 'static> VfsFile for DynFsDirInode<T, R> {
-    fn readdir(&self, start_index: usize) -> VfsResult<Option<VfsDirEntry>> {
-        self.0.readdir(start_index)
+    fn readdir(&self, cookie: u64) -> VfsResult<Option<VfsDirEntry>> {
+        self.0.readdir(cookie)
     }
 }
 
@@ -175,7 +179,7 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> VfsInode for Dy
         Err(VfsError::NoSys)
     }
 
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
-        self.0.update_time(time, now)
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        self.0.set_times(times, now)
     }
 }
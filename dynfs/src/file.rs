@@ -1,4 +1,5 @@
 use alloc::{string::String, sync::Arc, vec::Vec};
+use core::task::Waker;
 
 use unifs::{
     inode::{basic_file_stat, UniFsInodeSame},
@@ -8,11 +9,12 @@ use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_file_inode_default,
-    inode::{InodeAttr, VfsInode},
+    inode::{InodeAttr, InodeAttrMask, VfsInode},
+    lock::{VfsFileLock, VfsLockOwner, VfsLockType},
     superblock::VfsSuperBlock,
     utils::{
-        VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsPollEvents, VfsRenameFlag, VfsTime,
-        VfsTimeSpec,
+        VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsPollEvents, VfsRenameFlag,
+        VfsTimeSpec, VfsTimes,
     },
     VfsResult,
 };
@@ -52,6 +54,9 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> VfsFile for Dyn
     fn poll(&self, event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
         self.real_inode()?.poll(event)
     }
+    fn poll_register(&self, event: VfsPollEvents, waker: &Waker) -> VfsResult<VfsPollEvents> {
+        self.real_inode()?.poll_register(event, waker)
+    }
 
     fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
         self.real_inode()?.ioctl(_cmd, _arg)
@@ -63,6 +68,21 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> VfsFile for Dyn
     fn fsync(&self) -> VfsResult<()> {
         self.real_inode()?.fsync()
     }
+    fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        self.basic.get_lock(lock)
+    }
+    fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.basic.set_lock(lock)
+    }
+    fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.basic.flock(owner, ty)
+    }
+    fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.basic.release_posix(pid)
+    }
+    fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.basic.release_flock(ofd)
+    }
 }
 
 impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> VfsInode for DynFsFileInode<T, R> {
@@ -75,7 +95,15 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> VfsInode for Dy
         self.basic.inner.lock().perm
     }
 
-    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+    fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
+        if attr.mask.contains(InodeAttrMask::SIZE) {
+            self.truncate(attr.size)?;
+        }
+        let times = attr.times();
+        if !times.is_omit() {
+            self.real_inode()?.set_times(times, attr.ctime)?;
+        }
+        self.basic.set_attr(&attr);
         Ok(())
     }
 
@@ -104,7 +132,7 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> VfsInode for Dy
     fn truncate(&self, _len: u64) -> VfsResult<()> {
         Err(VfsError::PermissionDenied)
     }
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
-        self.real_inode()?.update_time(time, now)
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        self.real_inode()?.set_times(times, now)
     }
 }
@@ -15,8 +15,9 @@ use vfscore::{
     dentry::VfsDentry,
     fstype::{FileSystemFlags, VfsFsType},
     inode::VfsInode,
+    lock::VfsLockList,
     superblock::VfsSuperBlock,
-    utils::{VfsNodePerm, VfsTimeSpec},
+    utils::{VfsMountFlags, VfsNodePerm, VfsTimeSpec},
     VfsResult,
 };
 
@@ -35,7 +36,7 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> DynFs<T, R> {
 impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> VfsFsType for DynFs<T, R> {
     fn mount(
         self: Arc<Self>,
-        _flags: u32,
+        flags: u32,
         ab_mnt: &str,
         _dev: Option<Arc<dyn VfsInode>>,
         _data: &[u8],
@@ -44,6 +45,7 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> VfsFsType for D
         let mut this = self.0.sb.lock();
         if this.is_none() {
             let sb = UniFsSuperBlock::new(&fs);
+            sb.set_mount_flags(VfsMountFlags::from_bits_truncate(flags));
             let root = Arc::new(DynFsDirInode::new(
                 0,
                 self.0.provider.clone(),
@@ -100,7 +102,10 @@ impl<T: DynFsKernelProvider + 'static, R: VfsRawMutex + 'static> UniInodeSameNew
                 mtime: time,
                 ctime: time,
                 perm,
+                uid: 0,
+                gid: 0,
             }),
+            locks: lock_api::Mutex::new(VfsLockList::new()),
         }
     }
 }
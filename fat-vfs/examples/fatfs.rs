@@ -140,8 +140,6 @@ fn main() -> Result<(), Box<dyn Error>> {
     // /
     // |-- root.txt
     // |--d1
-    //    |--.
-    //    |--..
     // |--f2
     // |--f3
     print_fs_tree(&mut FakeWriter, root.clone(), "".to_string(), true)?;
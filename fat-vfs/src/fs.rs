@@ -1,7 +1,9 @@
 use alloc::{
     collections::BTreeMap,
+    format,
     string::{String, ToString},
     sync::Weak,
+    vec::Vec,
 };
 
 use fatfs::FileSystem;
@@ -12,6 +14,8 @@ use vfscore::{
     error::VfsError,
     fstype::{FileSystemFlags, VfsFsType},
     inode::VfsInode,
+    lock::VfsLockList,
+    page::VfsFrameAllocator,
     superblock::{SuperType, VfsSuperBlock},
     utils::{VfsFsStat, VfsNodeType},
     VfsResult,
@@ -53,7 +57,14 @@ impl<T: FatFsProvider + 'static, R: VfsRawMutex + 'static> VfsFsType for FatFs<T
             return sb.root_dentry(ab_mnt);
         }
         let fat_dev = FatDevice::new(dev);
-        let sb = FatFsSuperBlock::<R>::new(&(self.clone() as Arc<dyn VfsFsType>), fat_dev, ab_mnt);
+        let provider = self.provider.clone();
+        let sb = FatFsSuperBlock::<R>::new(
+            &(self.clone() as Arc<dyn VfsFsType>),
+            fat_dev,
+            ab_mnt,
+            self.provider.frame_allocator(),
+            Arc::new(move || provider.current_time()),
+        );
         // we use dev_ino as the key to store the superblock
         self.fs_container
             .lock()
@@ -94,10 +105,20 @@ pub struct FatFsSuperBlock<R: VfsRawMutex> {
     root: Mutex<R, Option<Arc<dyn VfsInode>>>,
     fs: FileSystem<FatDevice, DefaultTimeProvider, LossyOemCpConverter>,
     mnt_info: Mutex<R, BTreeMap<String, Arc<dyn VfsDentry>>>,
+    pub(crate) frame_allocator: Arc<dyn VfsFrameAllocator>,
+    clock: Arc<dyn Fn() -> VfsTimeSpec + Send + Sync>,
+    /// The advisory locks of the files by path, FAT has no inode numbers
+    locks: Mutex<R, BTreeMap<String, Arc<Mutex<R, VfsLockList>>>>,
 }
 
 impl<R: VfsRawMutex + 'static> FatFsSuperBlock<R> {
-    pub fn new(fs_type: &Arc<dyn VfsFsType>, device: FatDevice, ab_mnt: &str) -> Arc<Self> {
+    pub fn new(
+        fs_type: &Arc<dyn VfsFsType>,
+        device: FatDevice,
+        ab_mnt: &str,
+        frame_allocator: Arc<dyn VfsFrameAllocator>,
+        clock: Arc<dyn Fn() -> VfsTimeSpec + Send + Sync>,
+    ) -> Arc<Self> {
         let fs = FileSystem::new(device.clone(), fatfs::FsOptions::new()).unwrap();
         let root_disk_dir = Arc::new(Mutex::new(fs.root_dir()));
         let sb = Arc::new(Self {
@@ -106,12 +127,17 @@ impl<R: VfsRawMutex + 'static> FatFsSuperBlock<R> {
             root: Mutex::new(None),
             fs,
             mnt_info: Mutex::new(BTreeMap::new()),
+            frame_allocator,
+            clock,
+            locks: Mutex::new(BTreeMap::new()),
         });
         let root_inode = Arc::new(FatFsDirInode::new(
             &root_disk_dir.clone(),
             root_disk_dir,
             &sb,
             "rwxrwxrwx".into(),
+            None,
+            String::new(),
         ));
         sb.root.lock().replace(root_inode.clone());
         let parent = Weak::<UniFsDentry<R>>::new();
@@ -132,6 +158,55 @@ impl<R: VfsRawMutex + 'static> FatFsSuperBlock<R> {
             |x| Ok(x.clone()),
         )
     }
+
+    pub(crate) fn current_time(&self) -> VfsTimeSpec {
+        (self.clock)()
+    }
+
+    /// The advisory locks of the file at `path`, they are created the first time
+    ///
+    /// The path of the root is empty, and the path of a child is the path of its parent, '/' and
+    /// its name.
+    pub(crate) fn lock_list(&self, path: &str) -> Arc<Mutex<R, VfsLockList>> {
+        self.locks
+            .lock()
+            .entry(path.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(VfsLockList::new())))
+            .clone()
+    }
+
+    /// Drop the locks of `path` and of the files below it after it is removed
+    pub(crate) fn remove_locks(&self, path: &str) {
+        let mut locks = self.locks.lock();
+        for key in Self::lock_keys(&locks, path) {
+            locks.remove(&key);
+        }
+    }
+
+    /// Move the locks of `old` and of the files below it to `new` after a rename
+    pub(crate) fn rename_locks(&self, old: &str, new: &str) {
+        let mut locks = self.locks.lock();
+        // the file replaced by the rename is removed
+        for key in Self::lock_keys(&locks, new) {
+            locks.remove(&key);
+        }
+        for key in Self::lock_keys(&locks, old) {
+            let list = locks.remove(&key).unwrap();
+            locks.insert(format!("{}{}", new, &key[old.len()..]), list);
+        }
+    }
+
+    /// The paths of `path` and of the files below it which have locks
+    fn lock_keys(locks: &BTreeMap<String, Arc<Mutex<R, VfsLockList>>>, path: &str) -> Vec<String> {
+        let below = format!("{}/", path);
+        locks
+            .range(path.to_string()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(path))
+            .filter(|key| key.as_str() == path || key.starts_with(&below))
+            .cloned()
+            .collect()
+    }
 }
 
 impl<R: VfsRawMutex + 'static> VfsSuperBlock for FatFsSuperBlock<R> {
@@ -158,7 +233,8 @@ impl<R: VfsRawMutex + 'static> VfsSuperBlock for FatFsSuperBlock<R> {
             f_files: 0,
             f_ffree: 0,
             f_fsid: [0, 0],
-            f_namelen: 255,
+            // 255 UTF-16 characters of long file name, counted in bytes like Linux
+            f_namelen: 255 * 6,
             f_frsize: 0,
             f_flags: 0,
             f_spare: [0; 4],
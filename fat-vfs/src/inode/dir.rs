@@ -1,19 +1,23 @@
 use alloc::{
     collections::BTreeMap,
+    format,
     string::{String, ToString},
     sync::Weak,
     vec::Vec,
 };
 
 use fatfs::{Error, Seek};
+use unifs::inode::UniFsDirChildren;
 use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_dir_inode_default,
-    inode::{InodeAttr, VfsInode},
+    inode::{InodeAttr, InodeAttrMask, VfsInode},
+    lock::{VfsFileLock, VfsLockOwner, VfsLockType},
     superblock::VfsSuperBlock,
     utils::{
-        VfsDirEntry, VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime,
+        VfsDirEntry, VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsStatx,
+        VfsStatxMask, VfsTimes,
     },
     VfsResult,
 };
@@ -30,6 +34,13 @@ pub struct FatFsDirInode<R: VfsRawMutex> {
     dir: Arc<Mutex<R, FatDir>>,
     attr: FatFsInodeSame<R>,
     inode_cache: Mutex<R, BTreeMap<String, Arc<dyn VfsInode>>>,
+    /// The creation time in the dir entry, none for the root directory
+    btime: Option<VfsTimeSpec>,
+    /// The cookies of readdir, loaded from the directory by the first readdir and then kept in
+    /// sync with the children, since fatfs doesn't expose the offsets of the entries
+    children: Mutex<R, Option<UniFsDirChildren<VfsNodeType>>>,
+    /// The path from the root, which keys the locks of the children
+    path: Mutex<R, String>,
 }
 
 impl<R: VfsRawMutex + 'static> FatFsDirInode<R> {
@@ -38,15 +49,78 @@ impl<R: VfsRawMutex + 'static> FatFsDirInode<R> {
         dir: Arc<Mutex<R, FatDir>>,
         sb: &Arc<FatFsSuperBlock<R>>,
         perm: VfsNodePerm,
+        btime: Option<VfsTimeSpec>,
+        path: String,
     ) -> Self {
         Self {
             parent: Arc::downgrade(parent),
             dir,
-            attr: FatFsInodeSame::new(sb, perm),
+            attr: FatFsInodeSame::new(sb, perm, &path),
             inode_cache: Mutex::new(BTreeMap::new()),
+            btime,
+            children: Mutex::new(None),
+            path: Mutex::new(path),
         }
     }
 
+    fn child_path(&self, name: &str) -> String {
+        format!("{}/{}", self.path.lock(), name)
+    }
+
+    /// Update the paths of this directory and of its cached subdirectories after it is renamed
+    fn moved_to(&self, path: String) {
+        for (name, inode) in self.inode_cache.lock().iter() {
+            if let Ok(dir) = inode.clone().downcast_arc::<FatFsDirInode<R>>() {
+                dir.moved_to(format!("{}/{}", path, name));
+            }
+        }
+        *self.path.lock() = path;
+    }
+
+    fn load_children(&self) -> VfsResult<UniFsDirChildren<VfsNodeType>> {
+        let mut children = UniFsDirChildren::new();
+        for entry in self.dir.lock().iter() {
+            let entry = entry.map_err(|_| VfsError::IoError)?;
+            let ty = if entry.is_dir() {
+                VfsNodeType::Dir
+            } else {
+                VfsNodeType::File
+            };
+            children.push(entry.file_name(), ty);
+        }
+        Ok(children)
+    }
+
+    /// Give the new child a cookie if the cookies are loaded
+    fn child_added(&self, name: &str, ty: VfsNodeType) {
+        if let Some(children) = self.children.lock().as_mut() {
+            children.push(name.to_string(), ty);
+        }
+    }
+
+    fn entry_type(&self, name: &str) -> VfsResult<VfsNodeType> {
+        let entry = self
+            .dir
+            .lock()
+            .iter()
+            .filter_map(|e| e.ok())
+            .find(|e| e.file_name() == name)
+            .ok_or(VfsError::NoEntry)?;
+        if entry.is_dir() {
+            Ok(VfsNodeType::Dir)
+        } else {
+            Ok(VfsNodeType::File)
+        }
+    }
+
+    /// Drop the cookie of the removed child, return its type if the cookies are loaded
+    fn child_removed(&self, name: &str) -> Option<VfsNodeType> {
+        self.children
+            .lock()
+            .as_mut()
+            .and_then(|children| children.remove(name))
+    }
+
     fn delete_file(&self, name: &str, ty: VfsNodeType) -> VfsResult<()> {
         let mut inode_cache = self.inode_cache.lock();
         let dir = self.dir.lock();
@@ -88,37 +162,52 @@ impl<R: VfsRawMutex + 'static> FatFsDirInode<R> {
             Error::NotFound | Error::InvalidInput => VfsError::NoEntry,
             _ => VfsError::IoError,
         })?;
+        drop(dir);
+        self.child_removed(name);
+        if let Some(sb) = self.attr.sb.upgrade() {
+            sb.remove_locks(&self.child_path(name));
+        }
+        self.attr.update_mtime();
         Ok(())
     }
 }
 
 impl<R: VfsRawMutex + 'static> VfsFile for FatFsDirInode<R> {
-    fn readdir(&self, start_index: usize) -> VfsResult<Option<VfsDirEntry>> {
-        let entry = self.dir.lock().iter().nth(start_index);
-        if let Some(entry) = entry {
-            match entry {
-                Ok(entry) => {
-                    let ty = if entry.is_dir() {
-                        VfsNodeType::Dir
-                    } else {
-                        VfsNodeType::File
-                    };
-                    let entry = VfsDirEntry {
-                        ino: 1,
-                        ty,
-                        name: entry.file_name(),
-                    };
-                    Ok(Some(entry))
-                }
-                Err(_e) => Err(VfsError::IoError),
-            }
-        } else {
-            Ok(None)
+    fn readdir(&self, cookie: u64) -> VfsResult<Option<VfsDirEntry>> {
+        let mut children = self.children.lock();
+        if children.is_none() {
+            *children = Some(self.load_children()?);
         }
+        let entry = children
+            .as_ref()
+            .unwrap()
+            .next_from(cookie)
+            .map(|(cookie, name, ty)| VfsDirEntry {
+                ino: 1,
+                ty,
+                name: name.to_string(),
+                off: cookie + 1,
+            });
+        Ok(entry)
     }
     fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
         Err(VfsError::NoTTY)
     }
+    fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        self.attr.get_lock(lock)
+    }
+    fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.attr.set_lock(lock)
+    }
+    fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.attr.flock(owner, ty)
+    }
+    fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.attr.release_posix(pid)
+    }
+    fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.attr.release_flock(ofd)
+    }
 }
 
 impl<R: VfsRawMutex + 'static> VfsInode for FatFsDirInode<R> {
@@ -144,35 +233,60 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsDirInode<R> {
         }
         match ty {
             VfsNodeType::Dir => {
-                let new_dir = self
-                    .dir
-                    .lock()
-                    .create_dir(name)
-                    .map_err(|_| VfsError::IoError)?;
+                let dir = self.dir.lock();
+                let new_dir = dir.create_dir(name).map_err(|e| match e {
+                    Error::InvalidFileNameLength => VfsError::NameTooLong,
+                    _ => VfsError::IoError,
+                })?;
                 let new_dir = Arc::new(Mutex::new(new_dir));
+                let btime = dir
+                    .iter()
+                    .filter_map(|e| e.ok())
+                    .find(|e| e.is_dir() && e.file_name() == name)
+                    .map(|e| date_time_to_timespec(e.created()));
+                drop(dir);
 
-                let inode =
-                    FatFsDirInode::new(&self.dir, new_dir, &self.attr.sb.upgrade().unwrap(), perm);
+                let inode = FatFsDirInode::new(
+                    &self.dir,
+                    new_dir,
+                    &self.attr.sb.upgrade().unwrap(),
+                    perm,
+                    btime,
+                    self.child_path(name),
+                );
                 let inode = Arc::new(inode);
                 inode_cache.insert(name.to_string(), inode.clone());
+                self.child_added(name, ty);
+                self.attr.update_mtime();
                 Ok(inode)
             }
             VfsNodeType::File => {
-                let file = self
-                    .dir
-                    .lock()
-                    .create_file(name)
-                    .map_err(|_| VfsError::IoError)?;
+                let dir = self.dir.lock();
+                let file = dir.create_file(name).map_err(|e| match e {
+                    Error::InvalidFileNameLength => VfsError::NameTooLong,
+                    _ => VfsError::IoError,
+                })?;
                 let file = Arc::new(Mutex::new(file));
+                let btime = dir
+                    .iter()
+                    .filter_map(|e| e.ok())
+                    .find(|e| e.is_file() && e.file_name() == name)
+                    .map(|e| date_time_to_timespec(e.created()));
+                drop(dir);
+
                 let inode = FatFsFileInode::new(
                     &self.dir,
                     file,
                     &self.attr.sb.upgrade().unwrap(),
                     name.to_string(),
                     perm,
+                    btime,
+                    &self.child_path(name),
                 );
                 let inode = Arc::new(inode);
                 inode_cache.insert(name.to_string(), inode.clone());
+                self.child_added(name, ty);
+                self.attr.update_mtime();
                 Ok(inode)
             }
             _ => Err(VfsError::Invalid),
@@ -219,6 +333,8 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsDirInode<R> {
                     new_dir,
                     &self.attr.sb.upgrade().unwrap(),
                     VfsNodePerm::default_dir(),
+                    Some(date_time_to_timespec(entry.created())),
+                    self.child_path(name),
                 );
                 let inode = Arc::new(inode);
                 inode_cache.insert(name.to_string(), inode.clone());
@@ -231,6 +347,7 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsDirInode<R> {
                 _ => VfsError::IoError,
             })?;
             let file = Arc::new(Mutex::new(file));
+            let btime = Some(date_time_to_timespec(entry.created()));
             drop(dir);
             let inode = FatFsFileInode::new(
                 &self.dir,
@@ -238,6 +355,8 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsDirInode<R> {
                 &self.attr.sb.upgrade().unwrap(),
                 name.to_string(),
                 VfsNodePerm::default_file(),
+                btime,
+                &self.child_path(name),
             );
             let inode = Arc::new(inode);
             inode_cache.insert(name.to_string(), inode.clone());
@@ -249,7 +368,11 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsDirInode<R> {
         self.delete_file(name, VfsNodeType::Dir)
     }
 
-    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+    fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
+        if attr.mask.contains(InodeAttrMask::SIZE) {
+            return Err(VfsError::IsDir);
+        }
+        self.attr.set_attr(&attr);
         Ok(())
     }
 
@@ -261,8 +384,8 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsDirInode<R> {
             st_ino: 1,
             st_mode: mode,
             st_nlink: 1,
-            st_uid: 0,
-            st_gid: 0,
+            st_uid: attr.uid,
+            st_gid: attr.gid,
             st_rdev: 0,
             __pad: 0,
             st_size: 4096,
@@ -276,6 +399,14 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsDirInode<R> {
         })
     }
 
+    fn get_statx(&self, _mask: VfsStatxMask) -> VfsResult<VfsStatx> {
+        let mut statx = VfsStatx::from(self.get_attr()?);
+        if let Some(btime) = self.btime {
+            statx.set_btime(btime);
+        }
+        Ok(statx)
+    }
+
     impl_dir_inode_default!();
 
     fn list_xattr(&self) -> VfsResult<Vec<String>> {
@@ -304,6 +435,7 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsDirInode<R> {
                 dir.rename(old_name, &*dir, new_name).map_err(|e| match e {
                     Error::NotFound => VfsError::NoEntry,
                     Error::AlreadyExists => VfsError::EExist,
+                    Error::InvalidFileNameLength => VfsError::NameTooLong,
                     _ => VfsError::IoError,
                 })?;
             } else {
@@ -311,21 +443,40 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsDirInode<R> {
                     .map_err(|e| match e {
                         Error::NotFound => VfsError::NoEntry,
                         Error::AlreadyExists => VfsError::EExist,
+                        Error::InvalidFileNameLength => VfsError::NameTooLong,
                         _ => VfsError::IoError,
                     })?;
             };
-            self.inode_cache.lock().remove(old_name);
+            drop(dir);
+            let moved = self.inode_cache.lock().remove(old_name);
             new_parent.inode_cache.lock().remove(new_name);
+            let new_path = new_parent.child_path(new_name);
+            if let Some(sb) = self.attr.sb.upgrade() {
+                sb.rename_locks(&self.child_path(old_name), &new_path);
+            }
+            // the renamed inode may still be used by a dentry
+            let moved = moved.and_then(|inode| inode.downcast_arc::<FatFsDirInode<R>>().ok());
+            if let Some(dir) = moved {
+                dir.moved_to(new_path);
+            }
+            let ty = self.child_removed(old_name);
+            if let Some(children) = new_parent.children.lock().as_mut() {
+                let ty = match ty {
+                    Some(ty) => ty,
+                    None => new_parent.entry_type(new_name)?,
+                };
+                children.push(new_name.to_string(), ty);
+            }
+            self.attr.update_mtime();
+            if !Arc::ptr_eq(&self.dir, &new_parent.dir) {
+                new_parent.attr.update_mtime();
+            }
         }
         Ok(())
     }
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
-        let mut attr = self.attr.inner.lock();
-        match time {
-            VfsTime::AccessTime(t) => attr.atime = t,
-            VfsTime::ModifiedTime(t) => attr.mtime = t,
-        }
-        attr.ctime = now;
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        let attr = &mut *self.attr.inner.lock();
+        times.apply(&mut attr.atime, &mut attr.mtime, &mut attr.ctime, now);
         Ok(())
     }
 }
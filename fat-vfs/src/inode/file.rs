@@ -5,9 +5,14 @@ use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_file_inode_default,
-    inode::{InodeAttr, VfsInode},
+    inode::{InodeAttr, InodeAttrMask, VfsInode},
+    lock::{VfsFileLock, VfsLockOwner, VfsLockType},
+    page::{VfsFrame, VfsPageCache},
     superblock::VfsSuperBlock,
-    utils::{VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime},
+    utils::{
+        VfsFallocFlags, VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag,
+        VfsStatx, VfsStatxAttr, VfsStatxMask, VfsTimes,
+    },
     VfsResult,
 };
 
@@ -21,6 +26,10 @@ pub struct FatFsFileInode<R: VfsRawMutex> {
     #[allow(unused)]
     name: String,
     size: Mutex<R, u64>,
+    /// The creation time in the dir entry
+    btime: Option<VfsTimeSpec>,
+    read_only: bool,
+    cache: VfsPageCache<R>,
 }
 
 impl<R: VfsRawMutex + 'static> FatFsFileInode<R>
@@ -33,32 +42,38 @@ where
         sb: &Arc<FatFsSuperBlock<R>>,
         name: String,
         perm: VfsNodePerm,
+        btime: Option<VfsTimeSpec>,
+        path: &str,
     ) -> Self {
-        let size = parent
+        let entry = parent
             .lock()
             .iter()
             .find(|x| {
                 x.as_ref()
                     .is_ok_and(|x| x.is_file() && x.file_name() == name)
             })
-            .map(|e| e.unwrap().len())
-            .unwrap_or(0);
+            .map(|e| e.unwrap());
+        let size = entry.as_ref().map(|e| e.len()).unwrap_or(0);
+        let read_only = entry
+            .as_ref()
+            .is_some_and(|e| e.attributes().contains(fatfs::FileAttributes::READ_ONLY));
         Self {
             name,
             parent: Arc::downgrade(parent),
             file,
-            attr: FatFsInodeSame::new(sb, perm),
+            attr: FatFsInodeSame::new(sb, perm, path),
             size: Mutex::new(size),
+            btime,
+            read_only,
+            cache: VfsPageCache::new(sb.frame_allocator.clone()),
         }
     }
     pub fn raw_file(&self) -> Arc<Mutex<R, FatFile>> {
         self.file.clone()
     }
-}
 
-impl<R: VfsRawMutex + 'static> VfsFile for FatFsFileInode<R> {
-    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
-        let mut file = self.file.lock();
+    /// Read the locked file without the page cache
+    fn read_raw(&self, file: &mut FatFile, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
         let fat_offset = file.offset();
         if offset != fat_offset as u64 {
             file.seek(fatfs::SeekFrom::Start(offset))
@@ -76,11 +91,12 @@ impl<R: VfsRawMutex + 'static> VfsFile for FatFsFileInode<R> {
         }
         Ok(count)
     }
-    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+
+    /// Write the locked file without the page cache
+    fn write_raw(&self, file: &mut FatFile, offset: u64, buf: &[u8]) -> VfsResult<usize> {
         if buf.is_empty() {
             return Ok(0);
         }
-        let mut file = self.file.lock();
         if offset > *self.size.lock() {
             let empty = vec![0; (offset - *self.size.lock()) as usize];
             file.seek(fatfs::SeekFrom::Start(*self.size.lock()))
@@ -98,6 +114,86 @@ impl<R: VfsRawMutex + 'static> VfsFile for FatFsFileInode<R> {
         }
         Ok(buf.len())
     }
+}
+
+impl<R: VfsRawMutex + 'static> VfsFile for FatFsFileInode<R> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let count = self.read_raw(&mut self.file.lock(), offset, buf)?;
+        // the pages written through mmap may not be written back yet
+        self.cache.read_cached(offset, &mut buf[..count]);
+        Ok(count)
+    }
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let len = self.write_raw(&mut self.file.lock(), offset, buf)?;
+        self.cache.update(offset, buf);
+        self.attr.update_mtime();
+        Ok(len)
+    }
+    fn read_vectored_at(&self, offset: u64, bufs: &mut [&mut [u8]]) -> VfsResult<usize> {
+        let mut file = self.file.lock();
+        let mut count = 0;
+        for buf in bufs.iter_mut() {
+            let pos = offset + count as u64;
+            let len = match self.read_raw(&mut file, pos, buf) {
+                Ok(len) => len,
+                Err(e) if count == 0 => return Err(e),
+                Err(_) => break,
+            };
+            self.cache.read_cached(pos, &mut buf[..len]);
+            count += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(count)
+    }
+    fn write_vectored_at(&self, offset: u64, bufs: &[&[u8]]) -> VfsResult<usize> {
+        let mut file = self.file.lock();
+        let mut count = 0;
+        for buf in bufs {
+            let pos = offset + count as u64;
+            let len = match self.write_raw(&mut file, pos, buf) {
+                Ok(len) => len,
+                Err(e) if count == 0 => return Err(e),
+                Err(_) => break,
+            };
+            self.cache.update(pos, &buf[..len]);
+            count += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        if count > 0 {
+            self.attr.update_mtime();
+        }
+        Ok(count)
+    }
+    fn get_page(&self, offset: u64) -> VfsResult<Arc<dyn VfsFrame>> {
+        if offset >= *self.size.lock() {
+            return Err(VfsError::Invalid);
+        }
+        self.cache.get_page(offset, |offset, buf| {
+            self.read_raw(&mut self.file.lock(), offset, buf)?;
+            Ok(())
+        })
+    }
+    fn set_page_dirty(&self, offset: u64) -> VfsResult<()> {
+        self.cache.set_page_dirty(offset)
+    }
+    fn writeback(&self, offset: u64, len: u64) -> VfsResult<()> {
+        self.cache.writeback(offset, len, |offset, data| {
+            // the part of the page after the end of file is not written
+            let size = *self.size.lock();
+            if offset < size {
+                let len = ((size - offset) as usize).min(data.len());
+                self.write_raw(&mut self.file.lock(), offset, &data[..len])?;
+            }
+            Ok(())
+        })
+    }
     fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
         Err(VfsError::NoTTY)
     }
@@ -105,8 +201,24 @@ impl<R: VfsRawMutex + 'static> VfsFile for FatFsFileInode<R> {
         self.fsync()
     }
     fn fsync(&self) -> VfsResult<()> {
+        self.writeback(0, 0)?;
         self.file.lock().flush().map_err(|_| VfsError::IoError)
     }
+    fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        self.attr.get_lock(lock)
+    }
+    fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.attr.set_lock(lock)
+    }
+    fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.attr.flock(owner, ty)
+    }
+    fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.attr.release_posix(pid)
+    }
+    fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.attr.release_flock(ofd)
+    }
 }
 
 impl<R: VfsRawMutex + 'static> VfsInode for FatFsFileInode<R> {
@@ -119,7 +231,11 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsFileInode<R> {
         self.attr.inner.lock().perm
     }
 
-    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+    fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
+        if attr.mask.contains(InodeAttrMask::SIZE) {
+            self.truncate(attr.size)?;
+        }
+        self.attr.set_attr(&attr);
         Ok(())
     }
 
@@ -132,8 +248,8 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsFileInode<R> {
             st_ino: 1,
             st_mode: mode,
             st_nlink: 1,
-            st_uid: 0,
-            st_gid: 0,
+            st_uid: attr.uid,
+            st_gid: attr.gid,
             st_rdev: 0,
             __pad: 0,
             st_size: len,
@@ -146,6 +262,20 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsFileInode<R> {
             unused: 0,
         })
     }
+    fn get_statx(&self, _mask: VfsStatxMask) -> VfsResult<VfsStatx> {
+        let mut statx = VfsStatx::from(self.get_attr()?);
+        if let Some(btime) = self.btime {
+            statx.set_btime(btime);
+        }
+        // the read-only attribute of FAT means the file can't be modified
+        let attributes = if self.read_only {
+            VfsStatxAttr::IMMUTABLE
+        } else {
+            VfsStatxAttr::empty()
+        };
+        statx.set_attributes(attributes, VfsStatxAttr::IMMUTABLE);
+        Ok(statx)
+    }
     fn list_xattr(&self) -> VfsResult<Vec<String>> {
         Err(VfsError::NoSys)
     }
@@ -166,15 +296,18 @@ impl<R: VfsRawMutex + 'static> VfsInode for FatFsFileInode<R> {
             .map_err(|_| VfsError::IoError)?;
         file.truncate().map_err(|_| VfsError::IoError)?;
         *this_len = len;
+        self.cache.truncate(len);
+        self.attr.update_mtime();
         Ok(())
     }
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
-        let mut attr = self.attr.inner.lock();
-        match time {
-            VfsTime::AccessTime(t) => attr.atime = t,
-            VfsTime::ModifiedTime(t) => attr.mtime = t,
-        }
-        attr.ctime = now;
+    fn fallocate(&self, mode: VfsFallocFlags, offset: u64, len: u64) -> VfsResult<()> {
+        // FAT has no sparse files and can't allocate clusters after the end of file
+        let size = *self.size.lock();
+        mode.zero_fill(offset, len, size, |pos, buf| self.write_at(pos, buf))
+    }
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        let attr = &mut *self.attr.inner.lock();
+        times.apply(&mut attr.atime, &mut attr.mtime, &mut attr.ctime, now);
         Ok(())
     }
 }
@@ -5,23 +5,33 @@ use alloc::sync::Weak;
 
 pub use dir::*;
 pub use file::*;
-use vfscore::utils::VfsNodePerm;
+use vfscore::{
+    inode::InodeAttr,
+    lock::{VfsFileLock, VfsLockList, VfsLockOwner, VfsLockType},
+    utils::VfsNodePerm,
+    VfsResult,
+};
 
 use crate::{fs::FatFsSuperBlock, *};
 
 struct FatFsInodeSame<R: VfsRawMutex> {
     pub sb: Weak<FatFsSuperBlock<R>>,
     pub inner: Mutex<R, FatFsInodeAttr>,
+    /// Shared by the inodes of the same path, see [`FatFsSuperBlock::lock_list`]
+    pub locks: Arc<Mutex<R, VfsLockList>>,
 }
 struct FatFsInodeAttr {
     pub atime: VfsTimeSpec,
     pub mtime: VfsTimeSpec,
     pub ctime: VfsTimeSpec,
     pub perm: VfsNodePerm,
+    /// FAT has no owners, so they are only kept in memory
+    pub uid: u32,
+    pub gid: u32,
 }
 
-impl<R: VfsRawMutex> FatFsInodeSame<R> {
-    pub fn new(sb: &Arc<FatFsSuperBlock<R>>, perm: VfsNodePerm) -> Self {
+impl<R: VfsRawMutex + 'static> FatFsInodeSame<R> {
+    pub fn new(sb: &Arc<FatFsSuperBlock<R>>, perm: VfsNodePerm, path: &str) -> Self {
         Self {
             sb: Arc::downgrade(sb),
             inner: Mutex::new(FatFsInodeAttr {
@@ -29,7 +39,49 @@ impl<R: VfsRawMutex> FatFsInodeSame<R> {
                 mtime: VfsTimeSpec::new(0, 0),
                 ctime: VfsTimeSpec::new(0, 0),
                 perm,
+                uid: 0,
+                gid: 0,
             }),
+            locks: sb.lock_list(path),
         }
     }
+    pub fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        Ok(self.locks.lock().test(&lock))
+    }
+    pub fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.locks.lock().set(lock)
+    }
+    pub fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.locks.lock().set_flock(owner, ty)
+    }
+    pub fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.locks.lock().release_posix(pid);
+        Ok(())
+    }
+    pub fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.locks.lock().release_flock(ofd);
+        Ok(())
+    }
+    /// Update mtime and ctime after the data or the children of the inode are modified
+    pub fn update_mtime(&self) {
+        let Some(sb) = self.sb.upgrade() else {
+            return;
+        };
+        let now = sb.current_time();
+        let mut inner = self.inner.lock();
+        inner.mtime = now;
+        inner.ctime = now;
+    }
+    /// Set the mode, the owner and the times in `attr`, the size is left to the inode
+    pub fn set_attr(&self, attr: &InodeAttr) {
+        let inner = &mut *self.inner.lock();
+        attr.apply_to(
+            &mut inner.perm,
+            &mut inner.uid,
+            &mut inner.gid,
+            &mut inner.atime,
+            &mut inner.mtime,
+            &mut inner.ctime,
+        );
+    }
 }
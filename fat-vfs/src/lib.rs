@@ -13,7 +13,10 @@ use core::fmt::{Debug, Formatter};
 use fatfs::*;
 pub use fs::FatFs;
 use lock_api::Mutex;
-use vfscore::utils::VfsTimeSpec;
+use vfscore::{
+    page::{VfsFrameAllocator, VfsHeapFrameAllocator},
+    utils::VfsTimeSpec,
+};
 
 use crate::device::FatDevice;
 
@@ -21,6 +24,10 @@ pub trait VfsRawMutex = lock_api::RawMutex + Send + Sync;
 
 pub trait FatFsProvider: Send + Sync + Clone {
     fn current_time(&self) -> VfsTimeSpec;
+    /// The allocator of the page cache, which is used by mmap
+    fn frame_allocator(&self) -> Arc<dyn VfsFrameAllocator> {
+        Arc::new(VfsHeapFrameAllocator)
+    }
 }
 
 #[allow(unused)]
@@ -49,5 +56,24 @@ impl<T: FatFsProvider> TimeProvider for TimeProviderImpl<T> {
     }
 }
 
+/// Translate the local time of FAT to the time since the Unix epoch, the timezone is ignored
+fn date_time_to_timespec(date_time: DateTime) -> VfsTimeSpec {
+    let (year, month, day) = (
+        date_time.date.year as i64,
+        date_time.date.month as i64,
+        date_time.date.day as i64,
+    );
+    // days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+    let time = date_time.time;
+    let sec = days * 86400 + time.hour as i64 * 3600 + time.min as i64 * 60 + time.sec as i64;
+    VfsTimeSpec::new(sec.max(0) as u64, time.millis as u64 * 1_000_000)
+}
+
 type FatDir = Dir<FatDevice, DefaultTimeProvider, LossyOemCpConverter>;
 type FatFile = File<FatDevice, DefaultTimeProvider, LossyOemCpConverter>;
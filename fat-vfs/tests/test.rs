@@ -1,11 +1,294 @@
-#[test]
-fn test_link() {}
-
-#[test]
-fn test_symlink() {}
-
-#[test]
-fn test_unlink() {}
-
-#[test]
-fn test_rename() {}
+use std::{
+    io::{Cursor, Read as _, Seek as _, Write as _},
+    sync::Arc,
+};
+
+use fat_vfs::{FatFs, FatFsProvider};
+use fatfs::{format_volume, FormatVolumeOptions, IoBase, Read, Seek, SeekFrom, Write};
+use ramfs::{RamFs, RamFsProvider};
+use spin::Mutex;
+use vfscore::{
+    cred::VfsCred,
+    dentry::VfsDentry,
+    error::VfsError,
+    file::VfsFile,
+    fstype::VfsFsType,
+    inode::VfsInode,
+    lock::{VfsFileLock, VfsLockType},
+    path::{SysContext, VfsCopyError, VfsPath},
+    utils::{VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTimeSpec},
+    VfsResult,
+};
+
+fn make_fatfs() -> Arc<dyn VfsDentry> {
+    let disk = Disk(Arc::new(Mutex::new(Cursor::new(vec![0; 8 * 1024 * 1024]))));
+    format_volume(&mut disk.clone(), FormatVolumeOptions::new()).unwrap();
+    let fatfs = Arc::new(FatFs::<_, Mutex<()>>::new(ProviderImpl));
+    fatfs.mount(0, "/", Some(Arc::new(disk)), &[]).unwrap()
+}
+
+#[test]
+fn test_name_too_long() {
+    let root = make_fatfs();
+    let path = VfsPath::new(root.clone(), root);
+    let file = VfsInodeMode::from_bits_truncate(0o666) | VfsInodeMode::FILE;
+
+    // the long file name is limited to 255 characters, not bytes
+    assert!(path.join("a".repeat(255)).unwrap().open(Some(file)).is_ok());
+    assert!(path.join("é".repeat(255)).unwrap().open(Some(file)).is_ok());
+    let res = path.join("a".repeat(256)).unwrap().open(Some(file));
+    assert_eq!(res.err(), Some(VfsError::NameTooLong));
+    let res = path.join("é".repeat(256)).unwrap().open(Some(file));
+    assert_eq!(res.err(), Some(VfsError::NameTooLong));
+}
+
+#[test]
+fn test_copy_from_ramfs() {
+    let ramfs = Arc::new(RamFs::<_, Mutex<()>>::new(ProviderImpl));
+    let root = ramfs.mount(0, "/", None, &[]).unwrap();
+    let src = VfsPath::new(root.clone(), root).join("src").unwrap();
+    let perm = VfsNodePerm::from_bits_truncate(0o755);
+    src.join("d").unwrap().create_dir_all(perm).unwrap();
+    let file = VfsInodeMode::from_bits_truncate(0o644) | VfsInodeMode::FILE;
+    let f = src.join("d/f").unwrap().open(Some(file)).unwrap();
+    f.inode().unwrap().write_at(0, b"hello").unwrap();
+    src.join("l").unwrap().symlink("d/f").unwrap();
+
+    let root = make_fatfs();
+    let dst = VfsPath::new(root.clone(), root).join("dst").unwrap();
+    let errors = src.copy_tree(&dst).unwrap();
+    // fat has no symlinks, the rest is still copied
+    assert_eq!(
+        errors,
+        vec![VfsCopyError {
+            path: "/src/l".to_string(),
+            error: VfsError::NoSys,
+        }]
+    );
+    let copy = dst
+        .join("d/f")
+        .unwrap()
+        .open(None)
+        .unwrap()
+        .inode()
+        .unwrap();
+    let mut buf = [0; 5];
+    assert_eq!(copy.read_at(0, &mut buf).unwrap(), 5);
+    assert_eq!(&buf, b"hello");
+
+    dst.remove_dir_all().unwrap();
+    assert_eq!(dst.open(None).err(), Some(VfsError::NoEntry));
+}
+
+#[test]
+fn test_move_from_ramfs() {
+    let ramfs = Arc::new(RamFs::<_, Mutex<()>>::new(ProviderImpl));
+    let root = ramfs.mount(0, "/", None, &[]).unwrap();
+    let src = VfsPath::new(root.clone(), root.clone())
+        .join("src")
+        .unwrap();
+    let context = SysContext {
+        pid: 0,
+        cred: VfsCred::root(),
+        umask: VfsNodePerm::empty(),
+        cwd: root.clone(),
+        root,
+    };
+    let perm = VfsNodePerm::from_bits_truncate(0o755);
+    let file = VfsInodeMode::from_bits_truncate(0o644) | VfsInodeMode::FILE;
+    src.create_dir_all(perm).unwrap();
+    src.join("f").unwrap().open(Some(file)).unwrap();
+    src.join("l").unwrap().symlink("f").unwrap();
+
+    let root = make_fatfs();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let dst = path.join("dst").unwrap();
+    dst.join("old").unwrap().create_dir_all(perm).unwrap();
+    // fat has no symlinks, so nothing is moved
+    let res = src.move_path(context.clone(), &dst);
+    assert_eq!(res, Err(VfsError::NoSys));
+    assert!(src.join("l").unwrap().open(None).is_ok());
+    assert!(dst.join("old").unwrap().open(None).is_ok());
+    assert_eq!(
+        path.join(".dst.move").unwrap().open(None).err(),
+        Some(VfsError::NoEntry)
+    );
+
+    src.join("l").unwrap().unlink().unwrap();
+    src.move_path(context, &path.join("new").unwrap()).unwrap();
+    assert!(path.join("new/f").unwrap().open(None).is_ok());
+    assert_eq!(src.open(None).err(), Some(VfsError::NoEntry));
+}
+
+#[test]
+fn test_readdir_cookie() {
+    let root = make_fatfs().inode().unwrap();
+    let dir = root
+        .create("dir", VfsNodeType::Dir, "rwxrwxrwx".into(), None)
+        .unwrap();
+    for name in ["a", "b", "c", "d"] {
+        dir.create(name, VfsNodeType::File, "rw-rw-rw-".into(), None)
+            .unwrap();
+    }
+    // remove every entry right after it is read, like rm -rf
+    let mut cookie = 0;
+    let mut names = Vec::new();
+    while let Some(entry) = dir.readdir(cookie).unwrap() {
+        if entry.name != "." && entry.name != ".." {
+            dir.unlink(&entry.name).unwrap();
+        }
+        cookie = entry.off;
+        names.push(entry.name);
+    }
+    assert_eq!(names, [".", "..", "a", "b", "c", "d"]);
+    // the entries created while iterating are listed after the old ones
+    dir.create("e", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    let entry = dir.readdir(cookie).unwrap().unwrap();
+    assert_eq!(entry.name, "e");
+    assert!(dir.readdir(entry.off).unwrap().is_none());
+}
+
+#[test]
+fn test_link() {}
+
+#[test]
+fn test_lock() {
+    let root = make_fatfs();
+    let f1 = root
+        .inode()
+        .unwrap()
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    f1.set_lock(VfsFileLock::new(1, 1, VfsLockType::Write, 0, 10))
+        .unwrap();
+    let res = f1.set_lock(VfsFileLock::new(2, 2, VfsLockType::Read, 5, 0));
+    assert_eq!(res, Err(VfsError::EAGAIN));
+    f1.flock(1, VfsLockType::Write).unwrap();
+    assert_eq!(f1.flock(2, VfsLockType::Read), Err(VfsError::EAGAIN));
+    // the last close of the open file description drops its flock lock, but not the POSIX
+    // locks of the process with the same number
+    f1.release_flock(1).unwrap();
+    f1.flock(2, VfsLockType::Read).unwrap();
+    let res = f1.set_lock(VfsFileLock::new(2, 2, VfsLockType::Write, 0, 0));
+    assert_eq!(res, Err(VfsError::EAGAIN));
+    f1.release_posix(1).unwrap();
+    f1.set_lock(VfsFileLock::new(2, 2, VfsLockType::Write, 0, 0))
+        .unwrap();
+
+    // the locks are kept by path, and follow the file when it or its parent is renamed
+    let root = root.inode().unwrap();
+    let d = root
+        .create("d", VfsNodeType::Dir, "rwxrwxrwx".into(), None)
+        .unwrap();
+    root.rename_to("f1", d.clone(), "f2", VfsRenameFlag::empty())
+        .unwrap();
+    let f2 = d.lookup("f2").unwrap();
+    assert!(!Arc::ptr_eq(&f1, &f2));
+    let res = f2.set_lock(VfsFileLock::new(3, 3, VfsLockType::Read, 0, 0));
+    assert_eq!(res, Err(VfsError::EAGAIN));
+    root.rename_to("d", root.clone(), "e", VfsRenameFlag::empty())
+        .unwrap();
+    let f2 = root.lookup("e").unwrap().lookup("f2").unwrap();
+    let res = f2.set_lock(VfsFileLock::new(3, 3, VfsLockType::Read, 0, 0));
+    assert_eq!(res, Err(VfsError::EAGAIN));
+    // a new file at the same path has no locks
+    let e = root.lookup("e").unwrap();
+    e.unlink("f2").unwrap();
+    let f2 = e
+        .create("f2", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    f2.set_lock(VfsFileLock::new(3, 3, VfsLockType::Read, 0, 0))
+        .unwrap();
+}
+
+#[test]
+fn test_symlink() {}
+
+#[test]
+fn test_unlink() {}
+
+#[test]
+fn test_rename() {}
+
+#[derive(Clone)]
+struct ProviderImpl;
+impl FatFsProvider for ProviderImpl {
+    fn current_time(&self) -> VfsTimeSpec {
+        VfsTimeSpec::new(0, 0)
+    }
+}
+impl RamFsProvider for ProviderImpl {
+    fn current_time(&self) -> VfsTimeSpec {
+        VfsTimeSpec::new(0, 0)
+    }
+}
+
+#[derive(Clone)]
+struct Disk(Arc<Mutex<Cursor<Vec<u8>>>>);
+
+impl IoBase for Disk {
+    type Error = ();
+}
+
+impl Read for Disk {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.lock().read(buf).map_err(|_| ())
+    }
+}
+
+impl Write for Disk {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.0.lock().write(buf).map_err(|_| ())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl Seek for Disk {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => std::io::SeekFrom::Start(pos),
+            SeekFrom::End(pos) => std::io::SeekFrom::End(pos),
+            SeekFrom::Current(pos) => std::io::SeekFrom::Current(pos),
+        };
+        self.0.lock().seek(pos).map_err(|_| ())
+    }
+}
+
+impl VfsFile for Disk {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let mut disk = self.0.lock();
+        disk.set_position(offset);
+        disk.read(buf).map_err(|_| VfsError::IoError)
+    }
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let mut disk = self.0.lock();
+        disk.set_position(offset);
+        disk.write(buf).map_err(|_| VfsError::IoError)
+    }
+    fn flush(&self) -> VfsResult<()> {
+        Ok(())
+    }
+    fn fsync(&self) -> VfsResult<()> {
+        Ok(())
+    }
+}
+
+impl VfsInode for Disk {
+    fn node_perm(&self) -> VfsNodePerm {
+        VfsNodePerm::empty()
+    }
+    fn get_attr(&self) -> VfsResult<VfsFileStat> {
+        Ok(VfsFileStat {
+            st_size: self.0.lock().get_ref().len() as u64,
+            st_blksize: 512,
+            ..Default::default()
+        })
+    }
+    fn inode_type(&self) -> VfsNodeType {
+        VfsNodeType::BlockDevice
+    }
+}
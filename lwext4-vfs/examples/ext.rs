@@ -20,7 +20,7 @@ use vfscore::{
     fstype::VfsFsType,
     inode::VfsInode,
     path::{DirIter, VfsPath},
-    utils::{VfsFileStat, VfsNodePerm, VfsNodeType, VfsTime, VfsTimeSpec},
+    utils::{VfsFileStat, VfsNodePerm, VfsNodeType, VfsTimeSet, VfsTimeSpec, VfsTimes},
     VfsResult,
 };
 
@@ -76,8 +76,8 @@ fn main() -> Result<(), Box<dyn Error>> {
     let data = [0x55u8; 512];
     let w = f3.inode()?.write_at(0, &data)?;
     assert_eq!(w, 512);
-    f3.inode()?.update_time(
-        VfsTime::AccessTime(VfsTimeSpec::new(1, 1)),
+    f3.inode()?.set_times(
+        VfsTimes::new(VfsTimeSet::Set(VfsTimeSpec::new(1, 1)), VfsTimeSet::Omit),
         VfsTimeSpec::new(1, 1),
     )?;
     let attr = f3.inode()?.get_attr()?;
@@ -106,6 +106,9 @@ fn main() -> Result<(), Box<dyn Error>> {
 
 struct ProviderImpl;
 impl ExtDevProvider for ProviderImpl {
+    fn current_time(&self) -> VfsTimeSpec {
+        VfsTimeSpec::new(0, 0)
+    }
     fn rdev2device(&self, _rdev: u64) -> Option<Arc<dyn VfsInode>> {
         None
     }
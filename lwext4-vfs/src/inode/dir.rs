@@ -1,71 +1,192 @@
 use alloc::{
+    collections::BTreeSet,
     string::{String, ToString},
     sync::{Arc, Weak},
     vec::Vec,
 };
+use core::ops::{Deref, DerefMut};
 
 use lock_api::Mutex;
 use log::{debug, info, warn};
-use lwext4_rs::{FileTimes, MetaDataExt, Permissions, ReadDir, Time};
+use lwext4_rs::{MetaDataExt, Permissions, ReadDir};
+use unifs::inode::UniFsDirChildren;
 use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_dir_inode_default,
-    inode::{InodeAttr, VfsInode},
+    inode::{InodeAttr, InodeAttrMask, VfsInode},
+    lock::{VfsFileLock, VfsLockOwner, VfsLockType},
     superblock::VfsSuperBlock,
     utils::{
-        VfsDirEntry, VfsFileStat, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime, VfsTimeSpec,
+        VfsDirEntry, VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag,
+        VfsTimeSpec, VfsTimes,
     },
     VfsResult,
 };
 
 use crate::{
-    inode::{file::ExtFileInode, link::ExtLinkInode, special::ExtSpecialInode, ExtFsInodeAttr},
+    inode::{
+        file::ExtFileInode, link::ExtLinkInode, set_meta, special::ExtSpecialInode, ExtFsInodeState,
+    },
     types::{into_file_type, into_vfs, into_vfs_node_type, Parent, ToDir},
     ExtFsSuperBlock, VfsRawMutex,
 };
 
+/// The [`ReadDir`] of a directory and the cookies of its entries.
+///
+/// lwext4 doesn't expose the offsets of the entries, so every entry gets a cookie when the
+/// cursor reads it or when it is created, and the cookie doesn't change when other entries are
+/// added or removed. readdir continues from the cursor, and the directory is read only once.
+pub(crate) struct ExtReadDir {
+    dir: ReadDir,
+    /// The inode number and the type of the entries with cookies
+    children: UniFsDirChildren<(u64, VfsNodeType)>,
+    /// Whether the cursor reached the end of the directory
+    done: bool,
+    /// The entries created before the cursor reached the end, the cursor skips them
+    created: BTreeSet<String>,
+    /// The number of entries read from lwext4
+    read: u64,
+}
+
+impl ExtReadDir {
+    pub(crate) fn new(dir: ReadDir) -> Self {
+        Self {
+            dir,
+            children: UniFsDirChildren::new(),
+            done: false,
+            created: BTreeSet::new(),
+            read: 0,
+        }
+    }
+    /// The first entry whose cookie is not less than `cookie`, the cursor reads the directory
+    /// until it is found
+    fn next_from(&mut self, cookie: u64) -> VfsResult<Option<VfsDirEntry>> {
+        loop {
+            if let Some((cookie, name, (ino, ty))) = self.children.next_from(cookie) {
+                return Ok(Some(VfsDirEntry {
+                    ino,
+                    ty,
+                    name: name.to_string(),
+                    off: cookie + 1,
+                }));
+            }
+            if self.done {
+                return Ok(None);
+            }
+            let Some(entry) = self.dir.next() else {
+                self.done = true;
+                self.created.clear();
+                continue;
+            };
+            self.read += 1;
+            let name = entry.name().to_string();
+            if self.created.remove(&name) {
+                continue;
+            }
+            let ty = into_vfs_node_type(entry.file_type().map_err(into_vfs)?);
+            self.children.push(name, (entry.inode() as u64, ty));
+        }
+    }
+    fn added(&mut self, name: &str, ino: u64, ty: VfsNodeType) {
+        if !self.done {
+            self.created.insert(name.to_string());
+        }
+        self.children.push(name.to_string(), (ino, ty));
+    }
+    fn removed(&mut self, name: &str) {
+        self.created.remove(name);
+        self.children.remove(name);
+    }
+}
+
+impl Deref for ExtReadDir {
+    type Target = ReadDir;
+    fn deref(&self) -> &Self::Target {
+        &self.dir
+    }
+}
+
+impl DerefMut for ExtReadDir {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.dir
+    }
+}
+
 pub struct ExtDirInode<R: VfsRawMutex> {
-    dir: Arc<Mutex<R, ReadDir>>,
+    dir: Arc<Mutex<R, ExtReadDir>>,
     sb: Weak<ExtFsSuperBlock<R>>,
-    times: Mutex<R, ExtFsInodeAttr>,
+    state: Arc<ExtFsInodeState<R>>,
 }
 unsafe impl<R: VfsRawMutex> Send for ExtDirInode<R> {}
 unsafe impl<R: VfsRawMutex> Sync for ExtDirInode<R> {}
 impl<R: VfsRawMutex> ExtDirInode<R> {
-    pub(crate) fn new(dir: Arc<Mutex<R, ReadDir>>, sb: &Arc<ExtFsSuperBlock<R>>) -> Self {
-        Self {
+    pub(crate) fn new(
+        dir: Arc<Mutex<R, ExtReadDir>>,
+        sb: &Arc<ExtFsSuperBlock<R>>,
+    ) -> VfsResult<Self> {
+        let ino = dir.lock().as_file().metadata().map_err(into_vfs)?.ino();
+        Ok(Self {
             dir,
             sb: Arc::downgrade(sb),
-            times: Mutex::new(ExtFsInodeAttr::default()),
-        }
+            state: sb.inode_state(ino),
+        })
     }
     fn path(&self) -> String {
         self.dir.lock().path()
     }
+    /// The number of entries read from lwext4 by readdir, which grows by the entries read again
+    /// when the directory is rescanned
+    pub fn entries_read(&self) -> u64 {
+        self.dir.lock().read
+    }
+    /// Update mtime and ctime after the children are changed
+    fn modified(&self, sb: &ExtFsSuperBlock<R>) {
+        self.state
+            .times
+            .lock()
+            .update_mtime(sb.provider.current_time());
+    }
+    /// Give the new child `name` a cookie
+    fn child_added(&self, sb: &ExtFsSuperBlock<R>, name: &str) -> VfsResult<()> {
+        let meta = sb.fs.metadata(&(self.path() + name)).map_err(into_vfs)?;
+        let ty = VfsNodeType::from(VfsInodeMode::from_bits_truncate(meta.mode()));
+        self.dir.lock().added(name, meta.ino(), ty);
+        Ok(())
+    }
+    fn child_removed(&self, name: &str) {
+        self.dir.lock().removed(name);
+    }
+    /// The inode number of `name` if removing it removes the inode too
+    fn last_link(&self, sb: &ExtFsSuperBlock<R>, name: &str) -> Option<u64> {
+        let meta = sb.fs.metadata(&(self.path() + name)).ok()?;
+        let ty = VfsNodeType::from(VfsInodeMode::from_bits_truncate(meta.mode()));
+        (ty == VfsNodeType::Dir || meta.nlink() <= 1).then_some(meta.ino())
+    }
 }
 
 impl<R: VfsRawMutex + 'static> VfsFile for ExtDirInode<R> {
-    fn readdir(&self, start_index: usize) -> VfsResult<Option<VfsDirEntry>> {
-        let mut dir = self.dir.lock();
-        // todo!(This should be optimized)
-        dir.rewind();
-        dir.advance_by(start_index).map_err(|_| VfsError::NoEntry)?;
-        let entry = dir.next();
-        let entry = match entry {
-            None => return Ok(None),
-            Some(entry) => entry,
-        };
-        let ty = entry.file_type().map_err(into_vfs)?;
-        Ok(Some(VfsDirEntry {
-            ino: entry.inode() as u64,
-            ty: into_vfs_node_type(ty),
-            name: entry.name().to_string(),
-        }))
+    fn readdir(&self, cookie: u64) -> VfsResult<Option<VfsDirEntry>> {
+        self.dir.lock().next_from(cookie)
     }
     fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
         Err(VfsError::NoTTY)
     }
+    fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        self.state.get_lock(lock)
+    }
+    fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.state.set_lock(lock)
+    }
+    fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.state.flock(owner, ty)
+    }
+    fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.state.release_posix(pid)
+    }
+    fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.state.release_flock(ofd)
+    }
 }
 
 impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
@@ -93,7 +214,7 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
             .map_err(|_x| VfsError::Invalid)?;
         let path = self.path() + name;
         info!("[create] file path: {}, ty: {:?}", path, ty);
-        match ty {
+        let inode = match ty {
             VfsNodeType::File => {
                 let file = sb
                     .fs
@@ -104,16 +225,20 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
                     .create(true)
                     .open(path)
                     .map_err(into_vfs)?;
-                let file = ExtFileInode::new(file, &sb);
+                let file = ExtFileInode::new(file, &sb)?;
                 Ok(Arc::new(file) as Arc<dyn VfsInode>)
             }
             VfsNodeType::Dir => {
                 sb.fs.create_dir(&path).map_err(into_vfs)?;
                 let dir = sb.fs.readdir(path).map_err(into_vfs)?;
-                let dir = ExtDirInode::new(Arc::new(Mutex::new(dir)), &sb);
+                let dir = ExtDirInode::new(Arc::new(Mutex::new(ExtReadDir::new(dir))), &sb)?;
                 Ok(Arc::new(dir) as Arc<dyn VfsInode>)
             }
             VfsNodeType::SymLink => Err(VfsError::Invalid),
+            VfsNodeType::Fifo | VfsNodeType::Socket => {
+                warn!("[create] unsupported file type: {:?}", ty);
+                Err(VfsError::EOPNOTSUPP)
+            }
             VfsNodeType::BlockDevice | VfsNodeType::CharDevice => {
                 let rdev = rdev.ok_or(VfsError::Invalid)?;
                 sb.fs
@@ -122,14 +247,17 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
                 sb.fs
                     .set_permissions(&path, Permissions::from_mode(perm.bits() as u32))
                     .map_err(into_vfs)?;
-                let file = ExtSpecialInode::new(path, &sb, ty, rdev, sb.provider.clone());
+                let file = ExtSpecialInode::new(path, &sb, ty, rdev, sb.provider.clone())?;
                 Ok(Arc::new(file) as Arc<dyn VfsInode>)
             }
             ty => {
                 warn!("[create] unsupported file type: {:?}", ty);
                 Err(VfsError::NoSys)
             }
-        }
+        }?;
+        self.child_added(&sb, name)?;
+        self.modified(&sb);
+        Ok(inode)
     }
     fn link(&self, name: &str, src: Arc<dyn VfsInode>) -> VfsResult<Arc<dyn VfsInode>> {
         let original = match src.inode_type() {
@@ -171,6 +299,8 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
         let link = self.path() + name;
         info!("[link] name: {}, src: {:?}", link, original);
         sb.fs.hard_link(original, link).map_err(into_vfs)?;
+        self.child_added(&sb, name)?;
+        self.modified(&sb);
         self.lookup(name)
     }
     fn unlink(&self, name: &str) -> VfsResult<()> {
@@ -180,7 +310,14 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
             .map_err(|_x| VfsError::Invalid)?;
         let path = self.path() + name;
         info!("[unlink] path: {}", path);
-        sb.fs.remove_file(path).map_err(into_vfs)
+        let last = self.last_link(&sb, name);
+        sb.fs.remove_file(path).map_err(into_vfs)?;
+        if let Some(ino) = last {
+            sb.forget_inode(ino);
+        }
+        self.child_removed(name);
+        self.modified(&sb);
+        Ok(())
     }
     fn symlink(&self, name: &str, sy_name: &str) -> VfsResult<Arc<dyn VfsInode>> {
         let sb = self
@@ -190,20 +327,24 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
         let path = self.path() + name;
         sb.fs.soft_link(&sy_name, &path).map_err(into_vfs)?;
         info!("[symlink] path: {} -> {}", path, sy_name);
+        self.child_added(&sb, name)?;
+        self.modified(&sb);
         Ok(self.lookup(name)?)
     }
     fn lookup(&self, name: &str) -> VfsResult<Arc<dyn VfsInode>> {
         debug!("[extfs] lookup: {}", name);
-        let mut dir = self.dir.lock();
-        dir.rewind();
-        let entry = dir
-            .find(|entry| entry.name() == name)
-            .ok_or(VfsError::NoEntry)?;
-        debug!("entry: {:?}", entry);
         let sb = self
             .get_super_block()?
             .downcast_arc::<ExtFsSuperBlock<R>>()
             .map_err(|_x| VfsError::Invalid)?;
+        // lookup scans its own ReadDir, so the cursor of readdir is not moved
+        let entry = sb
+            .fs
+            .readdir(self.path())
+            .map_err(into_vfs)?
+            .find(|entry| entry.name() == name)
+            .ok_or(VfsError::NoEntry)?;
+        debug!("entry: {:?}", entry);
         let ty = into_vfs_node_type(entry.file_type().map_err(into_vfs)?);
         match ty {
             VfsNodeType::Unknown => {
@@ -211,33 +352,36 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
                 Err(VfsError::Invalid)
             }
             VfsNodeType::Fifo | VfsNodeType::Socket => {
-                unimplemented!()
+                warn!("[extfs] lookup: {:?} is not supported", ty);
+                Err(VfsError::EOPNOTSUPP)
             }
             VfsNodeType::CharDevice | VfsNodeType::BlockDevice => {
                 let path = entry.path();
                 let meta = sb.fs.metadata(&path).map_err(into_vfs)?;
                 let file =
-                    ExtSpecialInode::new(path, &sb, ty, meta.rdev() as _, sb.provider.clone());
+                    ExtSpecialInode::new(path, &sb, ty, meta.rdev() as _, sb.provider.clone())?;
                 Ok(Arc::new(file) as Arc<dyn VfsInode>)
             }
             VfsNodeType::Dir => {
                 let path = entry.path();
                 if name == "." {
-                    let dir = ExtDirInode::new(self.dir.clone(), &sb);
+                    let dir = ExtDirInode::new(self.dir.clone(), &sb)?;
                     return Ok(Arc::new(dir) as Arc<dyn VfsInode>);
                 } else if name == ".." {
                     let p_path = path.parent().unwrap();
                     return if p_path == "" {
-                        let dir = ExtDirInode::new(self.dir.clone(), &sb);
+                        let dir = ExtDirInode::new(self.dir.clone(), &sb)?;
                         Ok(Arc::new(dir) as Arc<dyn VfsInode>)
                     } else {
                         let dir = sb.fs.readdir(p_path).map_err(into_vfs)?;
-                        let dir = ExtDirInode::new(Arc::new(Mutex::new(dir)), &sb);
+                        let dir = Arc::new(Mutex::new(ExtReadDir::new(dir)));
+                        let dir = ExtDirInode::new(dir, &sb)?;
                         Ok(Arc::new(dir) as Arc<dyn VfsInode>)
                     };
                 } else {
                     let dir = sb.fs.readdir(path.to_dir()).map_err(into_vfs)?;
-                    let dir = ExtDirInode::new(Arc::new(Mutex::new(dir)), &sb);
+                    let dir = Arc::new(Mutex::new(ExtReadDir::new(dir)));
+                    let dir = ExtDirInode::new(dir, &sb)?;
                     Ok(Arc::new(dir) as Arc<dyn VfsInode>)
                 }
             }
@@ -251,12 +395,12 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
                     .write(true)
                     .open(path)
                     .map_err(into_vfs)?;
-                let file = ExtFileInode::new(file, &sb);
+                let file = ExtFileInode::new(file, &sb)?;
                 Ok(Arc::new(file) as Arc<dyn VfsInode>)
             }
             VfsNodeType::SymLink => {
                 let path = entry.path();
-                let file = ExtLinkInode::new(path, &sb);
+                let file = ExtLinkInode::new(path, &sb)?;
                 Ok(Arc::new(file) as Arc<dyn VfsInode>)
             }
         }
@@ -268,11 +412,25 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
             .map_err(|_x| VfsError::Invalid)?;
         let path = self.path() + name;
         info!("[rm dir] path: {}", path);
-        sb.fs.remove_dir(path).map_err(into_vfs)
-    }
-    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+        let last = self.last_link(&sb, name);
+        sb.fs.remove_dir(path).map_err(into_vfs)?;
+        if let Some(ino) = last {
+            sb.forget_inode(ino);
+        }
+        self.child_removed(name);
+        self.modified(&sb);
         Ok(())
     }
+    fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
+        if attr.mask.contains(InodeAttrMask::SIZE) {
+            return Err(VfsError::IsDir);
+        }
+        let sb = self.sb.upgrade().ok_or(VfsError::Invalid)?;
+        if set_meta(&sb, &self.path(), &attr)? {
+            self.state.times.lock().ctime = attr.ctime;
+        }
+        self.set_times(attr.times(), attr.ctime)
+    }
     fn get_attr(&self) -> VfsResult<VfsFileStat> {
         let dir = self.dir.lock();
         let file = dir.as_file();
@@ -283,7 +441,7 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
             .map_err(|_x| VfsError::Invalid)?;
         let fs_stat = sb.fs.mount_handle().stats().map_err(into_vfs)?;
         let st_blksize = fs_stat.block_size;
-        let times = self.times.lock();
+        let times = self.state.times.lock();
         Ok(VfsFileStat {
             st_dev: 0,
             st_ino: meta.ino(),
@@ -307,7 +465,7 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
         })
     }
     fn list_xattr(&self) -> VfsResult<Vec<String>> {
-        unimplemented!()
+        Err(VfsError::NoSys)
     }
     fn inode_type(&self) -> VfsNodeType {
         VfsNodeType::Dir
@@ -329,26 +487,32 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtDirInode<R> {
             .get_super_block()?
             .downcast_arc::<ExtFsSuperBlock<R>>()
             .map_err(|_x| VfsError::Invalid)?;
-        sb.fs.rename(old_path, new_path).map_err(into_vfs)
+        // the inode replaced by the rename is removed with its last link
+        let old_ino = sb.fs.metadata(&old_path).map_err(into_vfs)?.ino();
+        let replaced = new_parent
+            .last_link(&sb, new_name)
+            .filter(|ino| *ino != old_ino);
+        sb.fs.rename(old_path, new_path).map_err(into_vfs)?;
+        if let Some(ino) = replaced {
+            sb.forget_inode(ino);
+        }
+        self.child_removed(old_name);
+        // the entry replaced by the rename keeps no cookie
+        new_parent.child_removed(new_name);
+        new_parent.child_added(&sb, new_name)?;
+        self.modified(&sb);
+        if !Arc::ptr_eq(&self.dir, &new_parent.dir) {
+            new_parent.modified(&sb);
+        }
+        Ok(())
     }
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
         let dir = self.dir.lock();
         let mut file = dir.as_file();
-        let times = FileTimes::new();
-        let mut attr_times = self.times.lock();
-        match time {
-            VfsTime::AccessTime(t) => {
-                attr_times.atime = t;
-                times.set_accessed(Time::from_extra(t.sec as u32, Some(t.nsec as u32)));
-            }
-            VfsTime::ModifiedTime(t) => {
-                attr_times.mtime = t;
-                times.set_modified(Time::from_extra(t.sec as u32, Some(t.nsec as u32)));
-            }
-        }
-        // times.set_modified(Time::from_extra(now.sec as u32, Some(now.nsec as u32)));
-        info!("[update_time] path: {:?}, times: {:?}", file.path(), times);
-        attr_times.ctime = now;
+        let Some(times) = self.state.times.lock().set_times(times, now) else {
+            return Ok(());
+        };
+        info!("[set_times] path: {:?}, times: {:?}", file.path(), times);
         file.set_times(times).map_err(into_vfs)
     }
 }
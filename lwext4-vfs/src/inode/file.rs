@@ -8,51 +8,73 @@ use alloc::{
 use embedded_io::{Read, Seek, SeekFrom, Write};
 use lock_api::Mutex;
 use log::info;
-use lwext4_rs::{File, FileTimes, MetaDataExt, Time};
+use lwext4_rs::{File, MetaDataExt};
 use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_file_inode_default,
-    inode::{InodeAttr, VfsInode},
+    inode::{InodeAttr, InodeAttrMask, VfsInode},
+    lock::{VfsFileLock, VfsLockOwner, VfsLockType},
+    page::{VfsFrame, VfsPageCache},
     superblock::VfsSuperBlock,
-    utils::{VfsFileStat, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime, VfsTimeSpec},
+    utils::{
+        VfsFallocFlags, VfsFileStat, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTimeSpec, VfsTimes,
+    },
     VfsResult,
 };
 
-use crate::{inode::ExtFsInodeAttr, types::into_vfs, ExtFsSuperBlock, VfsRawMutex};
+use crate::{
+    inode::{set_meta, ExtFsInodeState},
+    types::into_vfs,
+    ExtFsSuperBlock, VfsRawMutex,
+};
 
 pub struct ExtFileInode<R: VfsRawMutex> {
     file: Mutex<R, File>,
     sb: Weak<ExtFsSuperBlock<R>>,
-    times: Mutex<R, ExtFsInodeAttr>,
+    state: Arc<ExtFsInodeState<R>>,
+    cache: VfsPageCache<R>,
 }
 
 unsafe impl<R: VfsRawMutex> Send for ExtFileInode<R> {}
 unsafe impl<R: VfsRawMutex> Sync for ExtFileInode<R> {}
 
 impl<R: VfsRawMutex> ExtFileInode<R> {
-    pub fn new(file: File, sb: &Arc<ExtFsSuperBlock<R>>) -> Self {
-        Self {
+    pub fn new(file: File, sb: &Arc<ExtFsSuperBlock<R>>) -> VfsResult<Self> {
+        let ino = file.metadata().map_err(into_vfs)?.ino();
+        Ok(Self {
             file: Mutex::new(file),
             sb: Arc::downgrade(sb),
-            times: Mutex::new(ExtFsInodeAttr::default()),
-        }
+            state: sb.inode_state(ino),
+            cache: VfsPageCache::new(sb.provider.frame_allocator()),
+        })
     }
     pub(super) fn path(&self) -> String {
         self.file.lock().path()
     }
-}
+    /// Update mtime and ctime after the data is changed
+    fn modified(&self) {
+        if let Some(sb) = self.sb.upgrade() {
+            self.state
+                .times
+                .lock()
+                .update_mtime(sb.provider.current_time());
+        }
+    }
 
-impl<R: VfsRawMutex + 'static> VfsFile for ExtFileInode<R> {
-    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
-        let mut file = self.file.lock();
+    /// Read the locked file without the page cache
+    fn read_raw(&self, file: &mut File, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
         if file.stream_position().map_err(into_vfs)? != offset {
             file.seek(SeekFrom::Start(offset)).map_err(into_vfs)?;
         }
         file.read(buf).map_err(into_vfs)
     }
-    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
-        let mut file = self.file.lock();
+
+    /// Write the locked file without the page cache
+    fn write_raw(&self, file: &mut File, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
         let file_size = file.metadata().map_err(into_vfs)?.size();
         if file_size < offset {
             let empty = vec![0; (offset - file_size) as usize];
@@ -64,6 +86,109 @@ impl<R: VfsRawMutex + 'static> VfsFile for ExtFileInode<R> {
         }
         file.write(buf).map_err(into_vfs)
     }
+}
+
+impl<R: VfsRawMutex + 'static> VfsFile for ExtFileInode<R> {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let count = self.read_raw(&mut self.file.lock(), offset, buf)?;
+        // the pages written through mmap may not be written back yet
+        self.cache.read_cached(offset, &mut buf[..count]);
+        Ok(count)
+    }
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let len = self.write_raw(&mut self.file.lock(), offset, buf)?;
+        self.cache.update(offset, &buf[..len]);
+        if len > 0 {
+            self.modified();
+        }
+        Ok(len)
+    }
+    fn read_vectored_at(&self, offset: u64, bufs: &mut [&mut [u8]]) -> VfsResult<usize> {
+        let mut file = self.file.lock();
+        let mut count = 0;
+        for buf in bufs.iter_mut() {
+            let pos = offset + count as u64;
+            let len = match self.read_raw(&mut file, pos, buf) {
+                Ok(len) => len,
+                Err(e) if count == 0 => return Err(e),
+                Err(_) => break,
+            };
+            self.cache.read_cached(pos, &mut buf[..len]);
+            count += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(count)
+    }
+    fn write_vectored_at(&self, offset: u64, bufs: &[&[u8]]) -> VfsResult<usize> {
+        let mut file = self.file.lock();
+        let mut count = 0;
+        for buf in bufs {
+            let pos = offset + count as u64;
+            let len = match self.write_raw(&mut file, pos, buf) {
+                Ok(len) => len,
+                Err(e) if count == 0 => return Err(e),
+                Err(_) => break,
+            };
+            self.cache.update(pos, &buf[..len]);
+            count += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        if count > 0 {
+            self.modified();
+        }
+        Ok(count)
+    }
+    fn get_page(&self, offset: u64) -> VfsResult<Arc<dyn VfsFrame>> {
+        let size = self.file.lock().metadata().map_err(into_vfs)?.size();
+        if offset >= size {
+            return Err(VfsError::Invalid);
+        }
+        self.cache.get_page(offset, |offset, buf| {
+            // lwext4 may return less than asked before the end of file
+            let mut count = 0;
+            while count < buf.len() {
+                let len = self.read_raw(
+                    &mut self.file.lock(),
+                    offset + count as u64,
+                    &mut buf[count..],
+                )?;
+                if len == 0 {
+                    break;
+                }
+                count += len;
+            }
+            Ok(())
+        })
+    }
+    fn set_page_dirty(&self, offset: u64) -> VfsResult<()> {
+        self.cache.set_page_dirty(offset)
+    }
+    fn writeback(&self, offset: u64, len: u64) -> VfsResult<()> {
+        self.cache.writeback(offset, len, |offset, data| {
+            // the part of the page after the end of file is not written
+            let size = self.file.lock().metadata().map_err(into_vfs)?.size();
+            if offset < size {
+                let len = ((size - offset) as usize).min(data.len());
+                let mut count = 0;
+                while count < len {
+                    let n = self.write_raw(
+                        &mut self.file.lock(),
+                        offset + count as u64,
+                        &data[count..len],
+                    )?;
+                    if n == 0 {
+                        return Err(VfsError::NoSpace);
+                    }
+                    count += n;
+                }
+            }
+            Ok(())
+        })
+    }
     fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
         Err(VfsError::NoTTY)
     }
@@ -71,8 +196,24 @@ impl<R: VfsRawMutex + 'static> VfsFile for ExtFileInode<R> {
         self.fsync()
     }
     fn fsync(&self) -> VfsResult<()> {
+        self.writeback(0, 0)?;
         self.file.lock().flush().map_err(into_vfs)
     }
+    fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        self.state.get_lock(lock)
+    }
+    fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.state.set_lock(lock)
+    }
+    fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.state.flock(owner, ty)
+    }
+    fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.state.release_posix(pid)
+    }
+    fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.state.release_flock(ofd)
+    }
 }
 
 impl<R: VfsRawMutex + 'static> VfsInode for ExtFileInode<R> {
@@ -87,8 +228,15 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtFileInode<R> {
         });
         perm
     }
-    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
-        Ok(())
+    fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
+        let sb = self.sb.upgrade().ok_or(VfsError::Invalid)?;
+        if attr.mask.contains(InodeAttrMask::SIZE) {
+            self.truncate(attr.size)?;
+        }
+        if set_meta(&sb, &self.path(), &attr)? {
+            self.state.times.lock().ctime = attr.ctime;
+        }
+        self.set_times(attr.times(), attr.ctime)
     }
     fn get_attr(&self) -> VfsResult<VfsFileStat> {
         let file = self.file.lock();
@@ -99,7 +247,7 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtFileInode<R> {
             .map_err(|_x| VfsError::Invalid)?;
         let fs_stat = sb.fs.mount_handle().stats().map_err(into_vfs)?;
         let st_blksize = fs_stat.block_size;
-        let times = self.times.lock();
+        let times = self.state.times.lock();
         Ok(VfsFileStat {
             st_dev: 0,
             st_ino: meta.ino(),
@@ -122,32 +270,31 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtFileInode<R> {
             unused: 0,
         })
     }
+    // lwext4 exposes neither the creation time nor the inode flags, so the default get_statx
+    // which only reports the basic stats is used
     fn list_xattr(&self) -> VfsResult<Vec<String>> {
-        unimplemented!()
+        Err(VfsError::NoSys)
     }
     fn inode_type(&self) -> VfsNodeType {
         VfsNodeType::File
     }
     fn truncate(&self, len: u64) -> VfsResult<()> {
-        self.file.lock().set_len(len).map_err(into_vfs)
+        self.file.lock().set_len(len).map_err(into_vfs)?;
+        self.cache.truncate(len);
+        self.modified();
+        Ok(())
+    }
+    fn fallocate(&self, mode: VfsFallocFlags, offset: u64, len: u64) -> VfsResult<()> {
+        // lwext4 can neither punch holes nor allocate blocks after the end of file
+        let size = self.file.lock().metadata().map_err(into_vfs)?.size();
+        mode.zero_fill(offset, len, size, |pos, buf| self.write_at(pos, buf))
     }
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
         let mut file = self.file.lock();
-        let times = FileTimes::new();
-        let mut attr_times = self.times.lock();
-        match time {
-            VfsTime::AccessTime(t) => {
-                attr_times.atime = t;
-                times.set_accessed(Time::from_extra(t.sec as u32, Some(t.nsec as u32)));
-            }
-            VfsTime::ModifiedTime(t) => {
-                attr_times.mtime = t;
-                times.set_modified(Time::from_extra(t.sec as u32, Some(t.nsec as u32)));
-            }
-        }
-        // times.set_modified(Time::from_extra(now.sec as u32, Some(now.nsec as u32)));
-        info!("[update_time] path: {:?}, times: {:?}", file.path(), times);
-        attr_times.ctime = now;
+        let Some(times) = self.state.times.lock().set_times(times, now) else {
+            return Ok(());
+        };
+        info!("[set_times] path: {:?}, times: {:?}", file.path(), times);
         file.set_times(times).map_err(into_vfs)
     }
 }
@@ -5,37 +5,41 @@ use alloc::{
 };
 use core::cmp::max;
 
-use lock_api::Mutex;
 use log::trace;
-use lwext4_rs::{FileTimes, MetaDataExt, Time};
+use lwext4_rs::MetaDataExt;
 use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_common_inode_default,
-    inode::{InodeAttr, VfsInode},
+    inode::{InodeAttr, InodeAttrMask, VfsInode},
     superblock::VfsSuperBlock,
-    utils::{VfsFileStat, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime, VfsTimeSpec},
+    utils::{VfsFileStat, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTimeSpec, VfsTimes},
     VfsResult,
 };
 
-use crate::{inode::ExtFsInodeAttr, types::into_vfs, ExtFsSuperBlock, VfsRawMutex};
+use crate::{
+    inode::{set_meta, ExtFsInodeState},
+    types::into_vfs,
+    ExtFsSuperBlock, VfsRawMutex,
+};
 
 pub struct ExtLinkInode<R: VfsRawMutex> {
     path: String,
     sb: Weak<ExtFsSuperBlock<R>>,
-    times: Mutex<R, ExtFsInodeAttr>,
+    state: Arc<ExtFsInodeState<R>>,
 }
 
 unsafe impl<R: VfsRawMutex> Send for ExtLinkInode<R> {}
 unsafe impl<R: VfsRawMutex> Sync for ExtLinkInode<R> {}
 
 impl<R: VfsRawMutex> ExtLinkInode<R> {
-    pub fn new(path: String, sb: &Arc<ExtFsSuperBlock<R>>) -> Self {
-        Self {
+    pub fn new(path: String, sb: &Arc<ExtFsSuperBlock<R>>) -> VfsResult<Self> {
+        let ino = sb.fs.metadata(&path).map_err(into_vfs)?.ino();
+        Ok(Self {
             path,
             sb: Arc::downgrade(sb),
-            times: Mutex::new(ExtFsInodeAttr::default()),
-        }
+            state: sb.inode_state(ino),
+        })
     }
     pub(super) fn path(&self) -> String {
         self.path.clone()
@@ -80,8 +84,15 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtLinkInode<R> {
         buf[..len].copy_from_slice(&link.as_bytes()[..len]);
         Ok(len)
     }
-    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
-        Ok(())
+    fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
+        if attr.mask.contains(InodeAttrMask::SIZE) {
+            return Err(VfsError::Invalid);
+        }
+        let sb = self.sb.upgrade().ok_or(VfsError::Invalid)?;
+        if set_meta(&sb, &self.path, &attr)? {
+            self.state.times.lock().ctime = attr.ctime;
+        }
+        self.set_times(attr.times(), attr.ctime)
     }
     fn get_attr(&self) -> VfsResult<VfsFileStat> {
         let sb = self
@@ -91,7 +102,7 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtLinkInode<R> {
         let meta = sb.fs.metadata(self.path.as_str()).map_err(into_vfs)?;
         let fs_stat = sb.fs.mount_handle().stats().map_err(into_vfs)?;
         let st_blksize = fs_stat.block_size;
-        let times = self.times.lock();
+        let times = self.state.times.lock();
         Ok(VfsFileStat {
             st_dev: 0,
             st_ino: meta.ino(),
@@ -115,31 +126,20 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtLinkInode<R> {
         })
     }
     fn list_xattr(&self) -> VfsResult<Vec<String>> {
-        unimplemented!()
+        Err(VfsError::NoSys)
     }
     fn inode_type(&self) -> VfsNodeType {
         VfsNodeType::SymLink
     }
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
         let sb = self
             .get_super_block()?
             .downcast_arc::<ExtFsSuperBlock<R>>()
             .map_err(|_x| VfsError::Invalid)?;
-        let times = FileTimes::new();
-        let mut attr_times = self.times.lock();
-        match time {
-            VfsTime::AccessTime(t) => {
-                attr_times.atime = t;
-                times.set_accessed(Time::from_extra(t.sec as u32, Some(t.nsec as u32)));
-            }
-            VfsTime::ModifiedTime(t) => {
-                attr_times.mtime = t;
-                times.set_modified(Time::from_extra(t.sec as u32, Some(t.nsec as u32)));
-            }
-        }
-        // times.set_modified(Time::from_extra(now.sec as u32, Some(now.nsec as u32)));
-        trace!("[update_time] path: {:?}, times: {:?}", self.path, times);
-        attr_times.ctime = now;
+        let Some(times) = self.state.times.lock().set_times(times, now) else {
+            return Ok(());
+        };
+        trace!("[set_times] path: {:?}, times: {:?}", self.path, times);
         sb.fs.set_times(&self.path, times).map_err(into_vfs)
     }
 }
@@ -1,4 +1,13 @@
-use vfscore::utils::VfsTimeSpec;
+use lock_api::Mutex;
+use lwext4_rs::{FileTimes, MetaDataExt, Permissions, Time};
+use vfscore::{
+    inode::{InodeAttr, InodeAttrMask},
+    lock::{VfsFileLock, VfsLockList, VfsLockOwner, VfsLockType},
+    utils::{VfsTimeSet, VfsTimeSpec, VfsTimes},
+    VfsResult,
+};
+
+use crate::{types::into_vfs, ExtFsSuperBlock, VfsRawMutex};
 
 pub mod dir;
 pub mod file;
@@ -11,3 +20,98 @@ struct ExtFsInodeAttr {
     pub mtime: VfsTimeSpec,
     pub ctime: VfsTimeSpec,
 }
+
+impl ExtFsInodeAttr {
+    /// Update mtime and ctime after the data or the children of the inode are modified
+    fn update_mtime(&mut self, now: VfsTimeSpec) {
+        self.mtime = now;
+        self.ctime = now;
+    }
+    /// Update the times, return the [`FileTimes`] to persist or none if nothing changes
+    fn set_times(&mut self, times: VfsTimes, now: VfsTimeSpec) -> Option<FileTimes> {
+        if times.is_omit() {
+            return None;
+        }
+        times.apply(&mut self.atime, &mut self.mtime, &mut self.ctime, now);
+        let ext_time = |t: VfsTimeSpec| Time::from_extra(t.sec as u32, Some(t.nsec as u32));
+        let mut file_times = FileTimes::new();
+        if times.atime != VfsTimeSet::Omit {
+            file_times = file_times.set_accessed(ext_time(self.atime));
+        }
+        if times.mtime != VfsTimeSet::Omit {
+            file_times = file_times.set_modified(ext_time(self.mtime));
+        }
+        Some(file_times)
+    }
+}
+
+/// The times and the advisory locks of an inode, lwext4 doesn't keep them so they are only kept
+/// in memory
+///
+/// [`ExtFsSuperBlock`] keeps one for each inode number, so the inodes created by different lookups
+/// of the same file share it.
+pub(crate) struct ExtFsInodeState<R: VfsRawMutex> {
+    times: Mutex<R, ExtFsInodeAttr>,
+    locks: Mutex<R, VfsLockList>,
+}
+
+impl<R: VfsRawMutex> ExtFsInodeState<R> {
+    pub(crate) fn new() -> Self {
+        Self {
+            times: Mutex::new(ExtFsInodeAttr::default()),
+            locks: Mutex::new(VfsLockList::new()),
+        }
+    }
+    fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        Ok(self.locks.lock().test(&lock))
+    }
+    fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.locks.lock().set(lock)
+    }
+    fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.locks.lock().set_flock(owner, ty)
+    }
+    fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.locks.lock().release_posix(pid);
+        Ok(())
+    }
+    fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.locks.lock().release_flock(ofd);
+        Ok(())
+    }
+}
+
+/// Persist the mode and the owner in `attr`, return whether any of them is set
+///
+/// The size and the times are left to the inode.
+fn set_meta<R: VfsRawMutex>(
+    sb: &ExtFsSuperBlock<R>,
+    path: &str,
+    attr: &InodeAttr,
+) -> VfsResult<bool> {
+    if attr.mask.contains(InodeAttrMask::MODE) {
+        sb.fs
+            .set_permissions(path, Permissions::from_mode(attr.perm().bits() as u32))
+            .map_err(into_vfs)?;
+    }
+    if attr
+        .mask
+        .intersects(InodeAttrMask::UID | InodeAttrMask::GID)
+    {
+        let meta = sb.fs.metadata(path).map_err(into_vfs)?;
+        let uid = if attr.mask.contains(InodeAttrMask::UID) {
+            attr.uid
+        } else {
+            meta.uid()
+        };
+        let gid = if attr.mask.contains(InodeAttrMask::GID) {
+            attr.gid
+        } else {
+            meta.gid()
+        };
+        sb.fs.set_owner(path, uid, gid).map_err(into_vfs)?;
+    }
+    Ok(attr
+        .mask
+        .intersects(InodeAttrMask::MODE | InodeAttrMask::UID | InodeAttrMask::GID))
+}
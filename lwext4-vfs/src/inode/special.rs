@@ -3,26 +3,37 @@ use alloc::{
     sync::{Arc, Weak},
     vec::Vec,
 };
+use core::task::Waker;
 
-use lock_api::Mutex;
 use log::info;
-use lwext4_rs::{FileTimes, MetaDataExt, Time};
+use lwext4_rs::MetaDataExt;
 use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_common_inode_default,
-    inode::{InodeAttr, VfsInode},
+    inode::{InodeAttr, InodeAttrMask, VfsInode},
+    lock::{VfsFileLock, VfsLockOwner, VfsLockType},
+    page::{VfsFrameAllocator, VfsHeapFrameAllocator},
     superblock::VfsSuperBlock,
     utils::{
-        VfsFileStat, VfsNodePerm, VfsNodeType, VfsPollEvents, VfsRenameFlag, VfsTime, VfsTimeSpec,
+        VfsFileStat, VfsNodePerm, VfsNodeType, VfsPollEvents, VfsRenameFlag, VfsTimeSpec, VfsTimes,
     },
     VfsResult,
 };
 
-use crate::{inode::ExtFsInodeAttr, types::into_vfs, ExtFsSuperBlock, VfsRawMutex};
+use crate::{
+    inode::{set_meta, ExtFsInodeState},
+    types::into_vfs,
+    ExtFsSuperBlock, VfsRawMutex,
+};
 
 pub trait ExtDevProvider: Send + Sync {
+    fn current_time(&self) -> VfsTimeSpec;
     fn rdev2device(&self, rdev: u64) -> Option<Arc<dyn VfsInode>>;
+    /// The allocator of the page cache, which is used by mmap
+    fn frame_allocator(&self) -> Arc<dyn VfsFrameAllocator> {
+        Arc::new(VfsHeapFrameAllocator)
+    }
 }
 
 pub struct ExtSpecialInode<R: VfsRawMutex> {
@@ -31,7 +42,7 @@ pub struct ExtSpecialInode<R: VfsRawMutex> {
     ty: VfsNodeType,
     rdev: u64,
     provider: Arc<dyn ExtDevProvider>,
-    times: Mutex<R, ExtFsInodeAttr>,
+    state: Arc<ExtFsInodeState<R>>,
 }
 
 unsafe impl<R: VfsRawMutex> Send for ExtSpecialInode<R> {}
@@ -44,15 +55,16 @@ impl<R: VfsRawMutex> ExtSpecialInode<R> {
         ty: VfsNodeType,
         rdev: u64,
         provider: Arc<dyn ExtDevProvider>,
-    ) -> Self {
-        Self {
+    ) -> VfsResult<Self> {
+        let ino = sb.fs.metadata(&path).map_err(into_vfs)?.ino();
+        Ok(Self {
             path,
             sb: Arc::downgrade(sb),
             ty,
             rdev,
             provider,
-            times: Mutex::new(ExtFsInodeAttr::default()),
-        }
+            state: sb.inode_state(ino),
+        })
     }
     pub(super) fn real_dev(&self) -> VfsResult<Arc<dyn VfsInode>> {
         let dev = self.provider.rdev2device(self.rdev);
@@ -77,6 +89,9 @@ impl<R: VfsRawMutex + 'static> VfsFile for ExtSpecialInode<R> {
     fn poll(&self, event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
         self.real_dev()?.poll(event)
     }
+    fn poll_register(&self, event: VfsPollEvents, waker: &Waker) -> VfsResult<VfsPollEvents> {
+        self.real_dev()?.poll_register(event, waker)
+    }
 
     fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
         self.real_dev()?.ioctl(_cmd, _arg)
@@ -88,6 +103,21 @@ impl<R: VfsRawMutex + 'static> VfsFile for ExtSpecialInode<R> {
     fn fsync(&self) -> VfsResult<()> {
         self.real_dev()?.fsync()
     }
+    fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        self.state.get_lock(lock)
+    }
+    fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.state.set_lock(lock)
+    }
+    fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.state.flock(owner, ty)
+    }
+    fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.state.release_posix(pid)
+    }
+    fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.state.release_flock(ofd)
+    }
 }
 
 impl<R: VfsRawMutex + 'static> VfsInode for ExtSpecialInode<R> {
@@ -112,8 +142,13 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtSpecialInode<R> {
         perm
     }
 
-    fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
-        Ok(())
+    fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
+        // the size of a device can't be changed, and truncating it is ignored like O_TRUNC
+        let sb = self.sb.upgrade().ok_or(VfsError::Invalid)?;
+        if set_meta(&sb, &self.path, &attr)? {
+            self.state.times.lock().ctime = attr.ctime;
+        }
+        self.set_times(attr.times(), attr.ctime)
     }
     fn get_attr(&self) -> VfsResult<VfsFileStat> {
         let sb = self
@@ -123,7 +158,7 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtSpecialInode<R> {
         let meta = sb.fs.metadata(self.path.as_str()).map_err(into_vfs)?;
         let fs_stat = sb.fs.mount_handle().stats().map_err(into_vfs)?;
         let st_blksize = fs_stat.block_size;
-        let times = self.times.lock();
+        let times = self.state.times.lock();
         Ok(VfsFileStat {
             st_dev: 0,
             st_ino: meta.ino(),
@@ -147,31 +182,20 @@ impl<R: VfsRawMutex + 'static> VfsInode for ExtSpecialInode<R> {
         })
     }
     fn list_xattr(&self) -> VfsResult<Vec<String>> {
-        unimplemented!()
+        Err(VfsError::NoSys)
     }
     fn inode_type(&self) -> VfsNodeType {
         self.ty
     }
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
         let sb = self
             .get_super_block()?
             .downcast_arc::<ExtFsSuperBlock<R>>()
             .map_err(|_x| VfsError::Invalid)?;
-        let times = FileTimes::new();
-        let mut attr_times = self.times.lock();
-        match time {
-            VfsTime::AccessTime(t) => {
-                attr_times.atime = t;
-                times.set_accessed(Time::from_extra(t.sec as u32, Some(t.nsec as u32)));
-            }
-            VfsTime::ModifiedTime(t) => {
-                attr_times.mtime = t;
-                times.set_modified(Time::from_extra(t.sec as u32, Some(t.nsec as u32)));
-            }
-        }
-        // times.set_modified(Time::from_extra(now.sec as u32, Some(now.nsec as u32)));
-        info!("[update_time] path: {:?}, times: {:?}", self.path, times);
-        attr_times.ctime = now;
+        let Some(times) = self.state.times.lock().set_times(times, now) else {
+            return Ok(());
+        };
+        info!("[set_times] path: {:?}, times: {:?}", self.path, times);
         sb.fs.set_times(&self.path, times).map_err(into_vfs)
     }
 }
@@ -14,7 +14,7 @@ use alloc::{
     sync::{Arc, Weak},
 };
 
-pub use inode::special::ExtDevProvider;
+pub use inode::{dir::ExtDirInode, special::ExtDevProvider};
 use lock_api::Mutex;
 use log::info;
 pub use lwext4_rs::FsType as ExtFsType;
@@ -32,7 +32,7 @@ use vfscore::{
 
 use crate::{
     blk::ExtDevice,
-    inode::dir::ExtDirInode,
+    inode::{dir::ExtReadDir, ExtFsInodeState},
     types::{into_vfs, ToDir},
 };
 pub trait VfsRawMutex = lock_api::RawMutex + Send + Sync;
@@ -125,6 +125,8 @@ struct ExtFsSuperBlock<R: VfsRawMutex> {
     fs: FileSystem,
     mnt_info: Mutex<R, BTreeMap<String, Arc<dyn VfsDentry>>>,
     provider: Arc<dyn ExtDevProvider>,
+    /// The times and the locks of the inodes, by inode number
+    inodes: Mutex<R, BTreeMap<u64, Arc<ExtFsInodeState<R>>>>,
 }
 
 unsafe impl<R: VfsRawMutex> Send for ExtFsSuperBlock<R> {}
@@ -154,10 +156,11 @@ impl<R: VfsRawMutex + 'static> ExtFsSuperBlock<R> {
             fs,
             mnt_info: Mutex::new(BTreeMap::new()),
             provider,
+            inodes: Mutex::new(BTreeMap::new()),
         });
 
-        let dir = Arc::new(Mutex::new(dir));
-        let dir = ExtDirInode::new(dir, &sb);
+        let dir = Arc::new(Mutex::new(ExtReadDir::new(dir)));
+        let dir = ExtDirInode::new(dir, &sb)?;
         let root_inode = Arc::new(dir);
 
         sb.root.lock().replace(root_inode.clone());
@@ -180,6 +183,22 @@ impl<R: VfsRawMutex + 'static> ExtFsSuperBlock<R> {
     }
 }
 
+impl<R: VfsRawMutex> ExtFsSuperBlock<R> {
+    /// The times and the locks of the inode `ino`, they are created the first time
+    fn inode_state(&self, ino: u64) -> Arc<ExtFsInodeState<R>> {
+        self.inodes
+            .lock()
+            .entry(ino)
+            .or_insert_with(|| Arc::new(ExtFsInodeState::new()))
+            .clone()
+    }
+    /// Drop the times and the locks of `ino` after its last link is removed, because the inode
+    /// number can be reused. The inodes which are still open keep them.
+    fn forget_inode(&self, ino: u64) {
+        self.inodes.lock().remove(&ino);
+    }
+}
+
 impl<R: VfsRawMutex + 'static> VfsSuperBlock for ExtFsSuperBlock<R> {
     fn sync_fs(&self, _wait: bool) -> VfsResult<()> {
         self.ext_dev.device_file.flush()?;
@@ -12,11 +12,13 @@ pub fn from_vfs(err: VfsError) -> Error {
         VfsError::NoEntry => Error::NoEntry,
         VfsError::EINTR => Error::InvalidError,
         VfsError::IoError => Error::Io,
+        VfsError::ENXIO => Error::InvalidError,
         VfsError::EAGAIN => Error::InvalidError,
         VfsError::NoMem => Error::OutOfMemory,
         VfsError::Access => Error::PermissionDenied,
         VfsError::EBUSY => Error::InvalidError,
         VfsError::EExist => Error::FileExists,
+        VfsError::EXDEV => Error::InvalidError,
         VfsError::NotDir => Error::NotDirectory,
         VfsError::Invalid => Error::InvalidArgument,
         VfsError::NoDev => Error::NoDevice,
@@ -25,9 +27,13 @@ pub fn from_vfs(err: VfsError) -> Error {
         VfsError::NoSpace => Error::NoSpace,
         VfsError::ESPIPE => Error::InvalidError,
         VfsError::EPIPE => Error::InvalidError,
+        VfsError::EDEADLK => Error::InvalidError,
         VfsError::NameTooLong => Error::TooBig,
         VfsError::NoSys => Error::NotSupported,
         VfsError::NotEmpty => Error::InvalidError,
+        VfsError::ELOOP => Error::InvalidError,
+        VfsError::ENODATA => Error::InvalidError,
+        VfsError::EOPNOTSUPP => Error::NotSupported,
     }
 }
 
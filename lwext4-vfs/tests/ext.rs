@@ -1 +1,189 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Seek,
+    sync::Arc,
+};
 
+use lwext4_rs::{
+    BlockDeviceConfig, DefaultInterface, FileSystem, FsBuilder, FsType, MountHandle, RegisterHandle,
+};
+use lwext4_vfs::{ExtDevProvider, ExtDirInode, ExtFs, ExtFsType};
+use spin::Mutex;
+use vfscore::{
+    error::VfsError,
+    file::VfsFile,
+    fstype::VfsFsType,
+    inode::VfsInode,
+    lock::{VfsFileLock, VfsLockType},
+    utils::{VfsFileStat, VfsNodePerm, VfsNodeType, VfsTimeSpec, VfsTimes},
+    VfsResult,
+};
+
+const IMAGE: &str = "./ext_readdir_image";
+const ENTRIES: usize = 10000;
+
+/// List a directory with many entries, which used to rewind the directory on every readdir
+#[test]
+fn test_readdir_large_dir() {
+    mkfs(IMAGE, ENTRIES);
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(IMAGE)
+        .unwrap();
+    let extfs = Arc::new(ExtFs::<ProviderImpl, Mutex<()>>::new(
+        ExtFsType::Ext4,
+        ProviderImpl,
+    ));
+    let root = extfs
+        .clone()
+        .mount(0, "/", Some(Arc::new(DeviceInode(Mutex::new(file)))), &[])
+        .unwrap();
+    let dir = root.inode().unwrap().lookup("big").unwrap();
+    let ext_dir = dir
+        .clone()
+        .downcast_arc::<ExtDirInode<Mutex<()>>>()
+        .ok()
+        .unwrap();
+
+    let mut cookie = 0;
+    let mut names = Vec::new();
+    while let Some(entry) = dir.readdir(cookie).unwrap() {
+        cookie = entry.off;
+        names.push(entry.name);
+    }
+    // the entries and "." and ".."
+    assert_eq!(names.len(), ENTRIES + 2);
+    // every entry is read once, the directory is not rescanned
+    assert_eq!(ext_dir.entries_read(), names.len() as u64);
+    // a lookup doesn't move the cursor of readdir
+    dir.lookup("f0").unwrap();
+    assert!(dir.readdir(cookie).unwrap().is_none());
+    assert_eq!(ext_dir.entries_read(), names.len() as u64);
+
+    // seeking backwards doesn't read the directory again
+    let entry = dir.readdir(10).unwrap().unwrap();
+    assert_eq!(entry.name, names[10]);
+    assert_eq!(entry.off, 11);
+    let entry = dir.readdir(0).unwrap().unwrap();
+    assert_eq!(entry.name, names[0]);
+    assert_eq!(ext_dir.entries_read(), names.len() as u64);
+
+    // removing the entries while iterating, like rm -rf, skips none of them
+    let mut cookie = 0;
+    let mut removed = 0;
+    while let Some(entry) = dir.readdir(cookie).unwrap() {
+        if entry.name.starts_with('f') {
+            dir.unlink(&entry.name).unwrap();
+            removed += 1;
+        }
+        cookie = entry.off;
+    }
+    assert_eq!(removed, ENTRIES);
+
+    // the inodes of different lookups of the same file share the locks and the times
+    let f1 = dir
+        .create("g", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    let f2 = dir.lookup("g").unwrap();
+    f1.set_lock(VfsFileLock::new(1, 1, VfsLockType::Write, 0, 0))
+        .unwrap();
+    let res = f2.set_lock(VfsFileLock::new(2, 2, VfsLockType::Read, 0, 0));
+    assert_eq!(res, Err(VfsError::EAGAIN));
+    let now = VfsTimeSpec::new(100, 0);
+    f2.set_times(VfsTimes::now(), now).unwrap();
+    assert_eq!(f1.get_attr().unwrap().st_mtime, now);
+
+    std::fs::remove_file(IMAGE).unwrap();
+}
+
+fn mkfs(path: &str, entries: usize) {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(path)
+        .unwrap();
+    file.set_len(64 * 1024 * 1024).unwrap();
+    let mut config = BlockDeviceConfig::default();
+    let bs: u64 = 512;
+    config.block_size = bs as u32;
+    config.part_size = file.metadata().unwrap().len();
+    config.part_offset = 0;
+    config.block_count = config.part_size / bs;
+
+    let blk = DefaultInterface::new_device(file, config);
+    let fs = FsBuilder::new()
+        .ty(FsType::Ext4)
+        .journal(true)
+        .block_size(1024)
+        .label("ext4fs")
+        .build(blk)
+        .unwrap();
+    let blk = fs.take_device();
+    let register_handler = RegisterHandle::register(blk, "ext4fs".to_string()).unwrap();
+    let mount_handler = MountHandle::mount(register_handler, "/".to_string(), true, false).unwrap();
+    let fs = FileSystem::new(mount_handler).unwrap();
+    fs.create_dir("/big").unwrap();
+    for i in 0..entries {
+        fs.file_builder()
+            .write(true)
+            .create(true)
+            .open(format!("/big/f{i}"))
+            .unwrap();
+    }
+}
+
+struct ProviderImpl;
+impl ExtDevProvider for ProviderImpl {
+    fn current_time(&self) -> VfsTimeSpec {
+        VfsTimeSpec::new(0, 0)
+    }
+    fn rdev2device(&self, _rdev: u64) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+}
+
+struct DeviceInode(Mutex<File>);
+
+impl VfsFile for DeviceInode {
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        use std::io::Read;
+        let mut file = self.0.lock();
+        file.seek(std::io::SeekFrom::Start(offset))
+            .map_err(|_| VfsError::IoError)?;
+        file.read(buf).map_err(|_| VfsError::IoError)
+    }
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        use std::io::Write;
+        let mut file = self.0.lock();
+        file.seek(std::io::SeekFrom::Start(offset))
+            .map_err(|_| VfsError::IoError)?;
+        file.write(buf).map_err(|_| VfsError::IoError)
+    }
+    fn flush(&self) -> VfsResult<()> {
+        self.fsync()
+    }
+    fn fsync(&self) -> VfsResult<()> {
+        use std::io::Write;
+        self.0.lock().flush().map_err(|_| VfsError::IoError)
+    }
+}
+
+impl VfsInode for DeviceInode {
+    fn node_perm(&self) -> VfsNodePerm {
+        VfsNodePerm::empty()
+    }
+    fn get_attr(&self) -> VfsResult<VfsFileStat> {
+        let len = self.0.lock().metadata().unwrap().len();
+        Ok(VfsFileStat {
+            st_size: len,
+            st_blksize: 512,
+            ..Default::default()
+        })
+    }
+    fn inode_type(&self) -> VfsNodeType {
+        VfsNodeType::BlockDevice
+    }
+}
@@ -3,15 +3,16 @@ use alloc::{
     vec::Vec,
 };
 
-use unifs::inode::{basic_file_stat, UniFsDirInode};
+use unifs::inode::{basic_file_stat, UniFsDirChildren, UniFsDirInode};
 use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_dir_inode_default,
     inode::{InodeAttr, VfsInode},
+    lock::{VfsFileLock, VfsLockOwner, VfsLockType},
     superblock::VfsSuperBlock,
     utils::{
-        VfsDirEntry, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime, VfsTimeSpec,
+        VfsDirEntry, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTimeSpec, VfsTimes,
     },
     VfsResult,
 };
@@ -20,7 +21,7 @@ use super::*;
 use crate::inode::{file::RamFsFileInode, symlink::RamFsSymLinkInode};
 pub struct RamFsDirInode<T: Send + Sync, R: VfsRawMutex> {
     inode: UniFsDirInode<T, R>,
-    ext_attr: lock_api::Mutex<R, BTreeMap<String, String>>,
+    ext_attr: lock_api::Mutex<R, BTreeMap<String, Vec<u8>>>,
 }
 
 impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> RamFsDirInode<T, R> {
@@ -33,7 +34,7 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> RamFsDirInode<T, R> {
         Self {
             inode: UniFsDirInode {
                 basic: UniFsInodeSame::new(sb, provider, inode_number, perm),
-                children: lock_api::Mutex::new(Vec::new()),
+                children: lock_api::Mutex::new(UniFsDirChildren::new()),
             },
             ext_attr: lock_api::Mutex::new(BTreeMap::new()),
         }
@@ -44,11 +45,34 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> RamFsDirInode<T, R> {
     {
         f(&self.inode.basic)
     }
+    /// Update mtime and ctime after the children are changed
+    fn modified(&self) {
+        let basic = &self.inode.basic;
+        basic.update_mtime(basic.provider.current_time());
+    }
 }
 
 impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsFile for RamFsDirInode<T, R> {
-    fn readdir(&self, start_index: usize) -> VfsResult<Option<VfsDirEntry>> {
-        self.inode.readdir(start_index)
+    fn readdir(&self, cookie: u64) -> VfsResult<Option<VfsDirEntry>> {
+        let res = self.inode.readdir(cookie)?;
+        let basic = &self.inode.basic;
+        basic.update_atime(true, basic.provider.current_time());
+        Ok(res)
+    }
+    fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        self.inode.basic.get_lock(lock)
+    }
+    fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.inode.basic.set_lock(lock)
+    }
+    fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.inode.basic.flock(owner, ty)
+    }
+    fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.inode.basic.release_posix(pid)
+    }
+    fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.inode.basic.release_flock(ofd)
     }
 }
 
@@ -99,7 +123,8 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsDir
         self.inode
             .children
             .lock()
-            .push((name.to_string(), inode_number));
+            .push(name.to_string(), inode_number);
+        self.modified();
         Ok(inode)
     }
     fn link(&self, name: &str, src: Arc<dyn VfsInode>) -> VfsResult<Arc<dyn VfsInode>> {
@@ -116,12 +141,14 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsDir
 
         let inode_number = inode.update_metadata(|meta| {
             meta.inner.lock().link_count += 1;
+            meta.update_ctime(meta.provider.current_time());
             meta.inode_number
         });
         self.inode
             .children
             .lock()
-            .push((name.to_string(), inode_number));
+            .push(name.to_string(), inode_number);
+        self.modified();
 
         Ok(inode)
     }
@@ -131,14 +158,12 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsDir
             .get_super_block()?
             .downcast_arc::<UniFsSuperBlock<R>>()
             .map_err(|_| VfsError::Invalid)?;
-        let index = self
+        let inode_number = self
             .inode
             .children
             .lock()
-            .iter()
-            .position(|(n, _)| n == name)
+            .find(name)
             .ok_or(VfsError::NoEntry)?;
-        let (_, inode_number) = self.inode.children.lock().get(index).unwrap().clone();
         let inode = sb.get_inode(inode_number).unwrap();
 
         macro_rules! gen {
@@ -148,6 +173,7 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsDir
                     .map_err(|_| VfsError::Invalid)?;
                 let res = inode.update_metadata(|meta| {
                     meta.inner.lock().link_count -= 1;
+                    meta.update_ctime(meta.provider.current_time());
                     meta.inner.lock().link_count
                 });
                 res
@@ -166,7 +192,8 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsDir
                 .fetch_sub(1, core::sync::atomic::Ordering::SeqCst);
             sb.remove_inode(inode_number);
         } // delete inode from sb
-        self.inode.children.lock().remove(index);
+        self.inode.children.lock().remove(name);
+        self.modified();
         Ok(())
     }
 
@@ -189,8 +216,9 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsDir
         self.inode
             .children
             .lock()
-            .push((name.to_string(), inode_number));
+            .push(name.to_string(), inode_number);
         sb.insert_inode(inode_number, inode.clone());
+        self.modified();
         Ok(inode)
     }
     fn lookup(&self, name: &str) -> VfsResult<Arc<dyn VfsInode>> {
@@ -198,12 +226,33 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsDir
     }
 
     fn rmdir(&self, name: &str) -> VfsResult<()> {
-        self.unlink(name)
+        let sb = self
+            .get_super_block()?
+            .downcast_arc::<UniFsSuperBlock<R>>()
+            .map_err(|_| VfsError::Invalid)?;
+        let inode_number = self
+            .inode
+            .children
+            .lock()
+            .find(name)
+            .ok_or(VfsError::NoEntry)?;
+        let inode = sb.get_inode(inode_number).unwrap();
+        let dir = inode
+            .downcast_arc::<RamFsDirInode<T, R>>()
+            .map_err(|_| VfsError::NotDir)?;
+        if !dir.inode.children.lock().is_empty() {
+            return Err(VfsError::NotEmpty);
+        }
+        sb.inode_count
+            .fetch_sub(1, core::sync::atomic::Ordering::SeqCst);
+        sb.remove_inode(inode_number);
+        self.inode.children.lock().remove(name);
+        self.modified();
+        Ok(())
     }
 
     fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
-        set_attr(&self.inode.basic, attr);
-        Ok(())
+        self.inode.set_attr(attr)
     }
     fn get_attr(&self) -> VfsResult<VfsFileStat> {
         let mut stat = basic_file_stat(&self.inode.basic);
@@ -219,6 +268,18 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsDir
         let res = self.ext_attr.lock().keys().cloned().collect();
         Ok(res)
     }
+    fn get_xattr(&self, name: &str) -> VfsResult<Vec<u8>> {
+        let res = self.ext_attr.lock().get(name).cloned();
+        res.ok_or(VfsError::ENODATA)
+    }
+    fn set_xattr(&self, name: &str, value: &[u8]) -> VfsResult<()> {
+        self.ext_attr
+            .lock()
+            .insert(name.to_string(), value.to_vec());
+        let basic = &self.inode.basic;
+        basic.update_ctime(basic.provider.current_time());
+        Ok(())
+    }
 
     fn inode_type(&self) -> VfsNodeType {
         VfsNodeType::Dir
@@ -233,13 +294,26 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsDir
         let new_parent = new_parent
             .downcast_arc::<RamFsDirInode<T, R>>()
             .map_err(|_| VfsError::Invalid)?;
+        let moved = self.inode.lookup(old_name)?;
         self.inode
-            .rename_to(old_name, &new_parent.inode, new_name, flag)
+            .rename_to(old_name, &new_parent.inode, new_name, flag)?;
+        self.modified();
+        new_parent.modified();
+        // the ctime of the moved inode is changed too, like most Linux filesystems
+        let now = self.inode.basic.provider.current_time();
+        if let Some(inode) = moved.downcast_ref::<RamFsFileInode<T, R>>() {
+            inode.update_metadata(|meta| meta.update_ctime(now));
+        } else if let Some(inode) = moved.downcast_ref::<RamFsDirInode<T, R>>() {
+            inode.update_metadata(|meta| meta.update_ctime(now));
+        } else if let Some(inode) = moved.downcast_ref::<RamFsSymLinkInode<T, R>>() {
+            inode.update_metadata(|meta| meta.update_ctime(now));
+        }
+        Ok(())
     }
 
     impl_dir_inode_default!();
 
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
-        self.inode.update_time(time, now)
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        self.inode.set_times(times, now)
     }
 }
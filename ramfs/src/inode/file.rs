@@ -1,15 +1,21 @@
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{
+    string::ToString,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
 
 use unifs::inode::basic_file_stat;
 use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_file_inode_default,
-    inode::{InodeAttr, VfsInode},
+    inode::{InodeAttr, InodeAttrMask, VfsInode},
+    lock::{VfsFileLock, VfsLockOwner, VfsLockType},
+    page::{page_chunks, VfsFrame, VfsFrameAllocator, VFS_PAGE_SIZE},
     superblock::VfsSuperBlock,
     utils::{
-        VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsPollEvents, VfsRenameFlag, VfsTime,
-        VfsTimeSpec,
+        VfsFallocFlags, VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag,
+        VfsSeekDataHole, VfsTimeSpec, VfsTimes,
     },
     VfsResult,
 };
@@ -19,10 +25,97 @@ use crate::RamFsProvider;
 pub struct RamFsFileInode<T: Send + Sync, R: VfsRawMutex> {
     basic: UniFsInodeSame<T, R>,
     inner: lock_api::Mutex<R, RamFsFileInodeInner>,
-    ext_attr: lock_api::Mutex<R, BTreeMap<String, String>>,
+    ext_attr: lock_api::Mutex<R, BTreeMap<String, Vec<u8>>>,
+    allocator: Arc<dyn VfsFrameAllocator>,
 }
 struct RamFsFileInodeInner {
-    data: Vec<u8>,
+    /// page offset -> page, the missing pages are holes
+    pages: BTreeMap<u64, Arc<dyn VfsFrame>>,
+    size: u64,
+}
+
+impl RamFsFileInodeInner {
+    /// Zero the part of the last page after the end of file, before the file grows.
+    ///
+    /// This part may have been written through a mapping.
+    fn zero_tail(&self) {
+        let in_page = (self.size % VFS_PAGE_SIZE as u64) as usize;
+        if in_page == 0 {
+            return;
+        }
+        if let Some(page) = self.pages.get(&(self.size - in_page as u64)) {
+            page.write_at(in_page, &[0; VFS_PAGE_SIZE][..VFS_PAGE_SIZE - in_page]);
+        }
+    }
+
+    fn page_or_alloc(
+        &mut self,
+        page_offset: u64,
+        allocator: &dyn VfsFrameAllocator,
+    ) -> VfsResult<Arc<dyn VfsFrame>> {
+        match self.pages.get(&page_offset) {
+            Some(page) => Ok(page.clone()),
+            None => {
+                let page = allocator.alloc_frame()?;
+                self.pages.insert(page_offset, page.clone());
+                Ok(page)
+            }
+        }
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> usize {
+        let offset = offset.min(self.size);
+        let len = (self.size - offset).min(buf.len() as u64) as usize;
+        for (page_offset, in_page, in_buf, n) in page_chunks(offset, len) {
+            let dst = &mut buf[in_buf..in_buf + n];
+            match self.pages.get(&page_offset) {
+                Some(page) => page.read_at(in_page, dst),
+                None => dst.fill(0),
+            }
+        }
+        len
+    }
+
+    fn write_at(
+        &mut self,
+        offset: u64,
+        buf: &[u8],
+        allocator: &dyn VfsFrameAllocator,
+    ) -> VfsResult<()> {
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let end = offset + buf.len() as u64;
+        if end > self.size {
+            self.zero_tail();
+        }
+        for (page_offset, in_page, in_buf, n) in page_chunks(offset, buf.len()) {
+            let page = self.page_or_alloc(page_offset, allocator)?;
+            page.write_at(in_page, &buf[in_buf..in_buf + n]);
+        }
+        self.size = self.size.max(end);
+        Ok(())
+    }
+
+    /// Deallocate the pages in `[offset, offset + len)`, the partial pages are zeroed.
+    fn punch_hole(&mut self, offset: u64, len: u64) {
+        let zeros = [0; VFS_PAGE_SIZE];
+        let end = (offset + len).min(self.size);
+        if offset >= end {
+            return;
+        }
+        for (page_offset, in_page, _, n) in page_chunks(offset, (end - offset) as usize) {
+            let Some(page) = self.pages.get(&page_offset) else {
+                continue;
+            };
+            // a page which is mapped by mmap is zeroed instead, the mapping still refers to it
+            if n == VFS_PAGE_SIZE && Arc::strong_count(page) == 1 {
+                self.pages.remove(&page_offset);
+            } else {
+                page.write_at(in_page, &zeros[..n]);
+            }
+        }
+    }
 }
 
 impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> RamFsFileInode<T, R> {
@@ -32,10 +125,15 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> RamFsFileInode<T, R>
         inode_number: u64,
         perm: VfsNodePerm,
     ) -> Self {
+        let allocator = provider.frame_allocator();
         Self {
             basic: UniFsInodeSame::new(sb, provider, inode_number, perm),
-            inner: lock_api::Mutex::new(RamFsFileInodeInner { data: Vec::new() }),
+            inner: lock_api::Mutex::new(RamFsFileInodeInner {
+                pages: BTreeMap::new(),
+                size: 0,
+            }),
             ext_attr: lock_api::Mutex::new(BTreeMap::new()),
+            allocator,
         }
     }
     pub fn update_metadata<F, Res>(&self, f: F) -> Res
@@ -44,41 +142,94 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> RamFsFileInode<T, R>
     {
         f(&self.basic)
     }
+    /// Update atime after the file is read
+    fn accessed(&self) {
+        self.basic
+            .update_atime(false, self.basic.provider.current_time());
+    }
+    /// Update mtime and ctime after the file is modified
+    fn modified(&self) {
+        self.basic.update_mtime(self.basic.provider.current_time());
+    }
 }
 
 impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsFile for RamFsFileInode<T, R> {
     fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let count = self.inner.lock().read_at(offset, buf);
+        self.accessed();
+        Ok(count)
+    }
+    fn read_vectored_at(&self, offset: u64, bufs: &mut [&mut [u8]]) -> VfsResult<usize> {
         let inner = self.inner.lock();
-        let size = inner.data.len() as u64;
-        let offset = offset.min(size);
-        let len = (size - offset).min(buf.len() as u64) as usize;
-        let data = inner.data.as_slice();
-        buf[..len].copy_from_slice(&data[offset as usize..offset as usize + len]);
-        Ok(len)
+        let mut count = 0;
+        for buf in bufs.iter_mut() {
+            let len = inner.read_at(offset + count as u64, buf);
+            count += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        drop(inner);
+        self.accessed();
+        Ok(count)
     }
     fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
         if buf.is_empty() {
             return Ok(0);
         }
-        let mut inner = self.inner.lock();
-        let buf_len = buf.len();
-        let offset = offset as usize;
-        let content = &mut inner.data;
-        if offset + buf_len > content.len() {
-            content.resize(offset + buf_len, 0);
-        }
-        let dst = &mut content[offset..offset + buf_len];
-        dst.copy_from_slice(&buf[..dst.len()]);
+        self.inner
+            .lock()
+            .write_at(offset, buf, self.allocator.as_ref())?;
+        self.modified();
         Ok(buf.len())
     }
-    fn poll(&self, _event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
-        todo!()
+    fn write_vectored_at(&self, offset: u64, bufs: &[&[u8]]) -> VfsResult<usize> {
+        let mut inner = self.inner.lock();
+        let mut count = 0;
+        for buf in bufs {
+            match inner.write_at(offset + count as u64, buf, self.allocator.as_ref()) {
+                Ok(()) => count += buf.len(),
+                Err(e) if count == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        drop(inner);
+        if count > 0 {
+            self.modified();
+        }
+        Ok(count)
+    }
+    fn get_page(&self, offset: u64) -> VfsResult<Arc<dyn VfsFrame>> {
+        if !offset.is_multiple_of(VFS_PAGE_SIZE as u64) {
+            return Err(VfsError::Invalid);
+        }
+        let mut inner = self.inner.lock();
+        // like Linux, there is no page after the end of file
+        if offset >= inner.size {
+            return Err(VfsError::Invalid);
+        }
+        inner.page_or_alloc(offset, self.allocator.as_ref())
     }
     fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
         // let cmd = pconst::io::TeletypeCommand::try_from(cmd).map_err(|_| VfsError::Invalid)?;
         // warn!("not support ioctl, cmd: {:?}, arg: {:x}", cmd, arg);
         Err(VfsError::NoTTY)
     }
+    fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        self.basic.get_lock(lock)
+    }
+    fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.basic.set_lock(lock)
+    }
+    fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.basic.flock(owner, ty)
+    }
+    fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.basic.release_posix(pid)
+    }
+    fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.basic.release_flock(ofd)
+    }
 }
 
 impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsFileInode<T, R> {
@@ -92,14 +243,17 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsFil
     }
 
     fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
-        set_attr(&self.basic, attr);
+        if attr.mask.contains(InodeAttrMask::SIZE) {
+            self.truncate(attr.size)?;
+        }
+        self.basic.set_attr(&attr);
         Ok(())
     }
 
     fn get_attr(&self) -> VfsResult<VfsFileStat> {
         let basic = &self.basic;
         let mut stat = basic_file_stat(basic);
-        stat.st_size = self.inner.lock().data.len() as u64;
+        stat.st_size = self.inner.lock().size;
         stat.st_mode = VfsInodeMode::from(
             VfsNodePerm::from_bits_truncate(stat.st_mode as u16),
             VfsNodeType::File,
@@ -112,26 +266,150 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsFil
         let res = self.ext_attr.lock().keys().cloned().collect();
         Ok(res)
     }
+    fn get_xattr(&self, name: &str) -> VfsResult<Vec<u8>> {
+        let res = self.ext_attr.lock().get(name).cloned();
+        res.ok_or(VfsError::ENODATA)
+    }
+    fn set_xattr(&self, name: &str, value: &[u8]) -> VfsResult<()> {
+        self.ext_attr
+            .lock()
+            .insert(name.to_string(), value.to_vec());
+        let basic = &self.basic;
+        basic.update_ctime(basic.provider.current_time());
+        Ok(())
+    }
     fn inode_type(&self) -> VfsNodeType {
         VfsNodeType::File
     }
 
     fn truncate(&self, len: u64) -> VfsResult<()> {
         let mut inner = self.inner.lock();
-        if len < inner.data.len() as u64 {
-            inner.data.truncate(len as _);
+        if len < inner.size {
+            let first_dropped = len.next_multiple_of(VFS_PAGE_SIZE as u64);
+            let _ = inner.pages.split_off(&first_dropped);
+            inner.size = len;
+            inner.zero_tail();
         } else {
-            inner.data.resize(len as _, 0);
+            inner.zero_tail();
+            inner.size = len;
         }
+        drop(inner);
+        self.modified();
         Ok(())
     }
-    impl_file_inode_default!();
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
-        match time {
-            VfsTime::ModifiedTime(t) => self.basic.inner.lock().mtime = t,
-            VfsTime::AccessTime(t) => self.basic.inner.lock().atime = t,
+    fn fallocate(&self, mode: VfsFallocFlags, offset: u64, len: u64) -> VfsResult<()> {
+        mode.check(offset, len)?;
+        let mut inner = self.inner.lock();
+        if mode.contains(VfsFallocFlags::PUNCH_HOLE) {
+            inner.punch_hole(offset, len);
+            drop(inner);
+            self.modified();
+            return Ok(());
         }
-        self.basic.inner.lock().ctime = now;
+        let end = offset + len;
+        if end > inner.size {
+            inner.zero_tail();
+        }
+        let zeros = [0; VFS_PAGE_SIZE];
+        let mut page_offset = offset - offset % VFS_PAGE_SIZE as u64;
+        while page_offset < end {
+            let page = inner.page_or_alloc(page_offset, self.allocator.as_ref())?;
+            if mode.contains(VfsFallocFlags::ZERO_RANGE) {
+                let start = offset.max(page_offset);
+                let stop = end.min(page_offset + VFS_PAGE_SIZE as u64);
+                page.write_at(
+                    (start - page_offset) as usize,
+                    &zeros[..(stop - start) as usize],
+                );
+            }
+            page_offset += VFS_PAGE_SIZE as u64;
+        }
+        if !mode.contains(VfsFallocFlags::KEEP_SIZE) {
+            inner.size = inner.size.max(end);
+        }
+        drop(inner);
+        self.modified();
+        Ok(())
+    }
+    fn copy_file_range_to(
+        &self,
+        src_offset: u64,
+        dst: &dyn VfsInode,
+        dst_offset: u64,
+        len: usize,
+    ) -> VfsResult<usize> {
+        let dst = dst
+            .downcast_ref::<RamFsFileInode<T, R>>()
+            .ok_or(VfsError::EOPNOTSUPP)?;
+        if core::ptr::eq(self, dst) || !Weak::ptr_eq(&self.basic.sb, &dst.basic.sb) {
+            return Err(VfsError::EOPNOTSUPP);
+        }
+        // lock the two files in the order of inode number
+        let (src_inner, mut dst_inner) = if self.basic.inode_number < dst.basic.inode_number {
+            let src_inner = self.inner.lock();
+            (src_inner, dst.inner.lock())
+        } else {
+            let dst_inner = dst.inner.lock();
+            (self.inner.lock(), dst_inner)
+        };
+        let len = src_inner.size.saturating_sub(src_offset).min(len as u64) as usize;
+        let mut buf = [0; VFS_PAGE_SIZE];
+        for (page_offset, in_page, in_buf, n) in page_chunks(src_offset, len) {
+            let pos = dst_offset + in_buf as u64;
+            match src_inner.pages.get(&page_offset) {
+                Some(page) => {
+                    page.read_at(in_page, &mut buf[..n]);
+                    dst_inner.write_at(pos, &buf[..n], dst.allocator.as_ref())?;
+                }
+                // keep the holes of the source
+                None => {
+                    dst_inner.punch_hole(pos, n as u64);
+                    if pos + n as u64 > dst_inner.size {
+                        dst_inner.zero_tail();
+                        dst_inner.size = pos + n as u64;
+                    }
+                }
+            }
+        }
+        drop((src_inner, dst_inner));
+        self.accessed();
+        if len > 0 {
+            dst.modified();
+        }
+        Ok(len)
+    }
+    fn seek_data_hole(&self, offset: u64, whence: VfsSeekDataHole) -> VfsResult<u64> {
+        let inner = self.inner.lock();
+        if offset >= inner.size {
+            return Err(VfsError::ENXIO);
+        }
+        let page_size = VFS_PAGE_SIZE as u64;
+        let first = offset - offset % page_size;
+        let res = match whence {
+            VfsSeekDataHole::Data => {
+                let page = inner.pages.range(first..).next().map(|(o, _)| *o);
+                match page {
+                    Some(page) if page < inner.size => page.max(offset),
+                    _ => return Err(VfsError::ENXIO),
+                }
+            }
+            VfsSeekDataHole::Hole => {
+                let mut next = first;
+                for page in inner.pages.range(first..).map(|(o, _)| *o) {
+                    if page != next {
+                        break;
+                    }
+                    next += page_size;
+                }
+                next.max(offset).min(inner.size)
+            }
+        };
+        Ok(res)
+    }
+    impl_file_inode_default!();
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        let inner = &mut *self.basic.inner.lock();
+        times.apply(&mut inner.atime, &mut inner.mtime, &mut inner.ctime, now);
         Ok(())
     }
 }
@@ -11,7 +11,7 @@ use unifs::{
     UniFsSuperBlock,
 };
 use vfscore::{
-    inode::InodeAttr,
+    lock::VfsLockList,
     utils::{VfsFileStat, VfsNodePerm},
 };
 
@@ -43,14 +43,10 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> RamFsInodeSameNew<T,
                 mtime: time,
                 ctime: time,
                 perm,
+                uid: 0,
+                gid: 0,
             }),
+            locks: lock_api::Mutex::new(VfsLockList::new()),
         }
     }
 }
-
-fn set_attr<T: Send + Sync, R: VfsRawMutex>(basic: &UniFsInodeSame<T, R>, attr: InodeAttr) {
-    let mut inner = basic.inner.lock();
-    inner.atime = attr.atime;
-    inner.mtime = attr.mtime;
-    inner.ctime = attr.ctime;
-}
@@ -1,14 +1,18 @@
-use alloc::{string::String, sync::Arc, vec::Vec};
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec::Vec,
+};
 
 use unifs::inode::basic_file_stat;
 use vfscore::{
     error::VfsError,
     file::VfsFile,
     impl_common_inode_default,
-    inode::{InodeAttr, VfsInode},
+    inode::{InodeAttr, InodeAttrMask, VfsInode},
     superblock::VfsSuperBlock,
     utils::{
-        VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime, VfsTimeSpec,
+        VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTimeSpec, VfsTimes,
     },
     VfsResult,
 };
@@ -18,7 +22,7 @@ use crate::RamFsProvider;
 pub struct RamFsSymLinkInode<T: Send + Sync, R: VfsRawMutex> {
     basic: UniFsInodeSame<T, R>,
     inner: lock_api::Mutex<R, String>,
-    ext_attr: lock_api::Mutex<R, BTreeMap<String, String>>,
+    ext_attr: lock_api::Mutex<R, BTreeMap<String, Vec<u8>>>,
 }
 
 impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> RamFsSymLinkInode<T, R> {
@@ -64,11 +68,16 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsSym
         let len = inner.as_bytes().len();
         let min_len = buf.len().min(len);
         buf[..min_len].copy_from_slice(&inner.as_bytes()[..min_len]);
+        self.basic
+            .update_atime(false, self.basic.provider.current_time());
         Ok(min_len)
     }
 
     fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
-        set_attr(&self.basic, attr);
+        if attr.mask.contains(InodeAttrMask::SIZE) {
+            return Err(VfsError::Invalid);
+        }
+        self.basic.set_attr(&attr);
         Ok(())
     }
 
@@ -86,18 +95,27 @@ impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsInode for RamFsSym
         let res = self.ext_attr.lock().keys().cloned().collect();
         Ok(res)
     }
+    fn get_xattr(&self, name: &str) -> VfsResult<Vec<u8>> {
+        let res = self.ext_attr.lock().get(name).cloned();
+        res.ok_or(VfsError::ENODATA)
+    }
+    fn set_xattr(&self, name: &str, value: &[u8]) -> VfsResult<()> {
+        self.ext_attr
+            .lock()
+            .insert(name.to_string(), value.to_vec());
+        let basic = &self.basic;
+        basic.update_ctime(basic.provider.current_time());
+        Ok(())
+    }
     fn inode_type(&self) -> VfsNodeType {
         VfsNodeType::SymLink
     }
 
     impl_common_inode_default!();
 
-    fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
-        match time {
-            VfsTime::ModifiedTime(t) => self.basic.inner.lock().mtime = t,
-            VfsTime::AccessTime(t) => self.basic.inner.lock().atime = t,
-        }
-        self.basic.inner.lock().ctime = now;
+    fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        let inner = &mut *self.basic.inner.lock();
+        times.apply(&mut inner.atime, &mut inner.mtime, &mut inner.ctime, now);
         Ok(())
     }
 }
@@ -18,13 +18,18 @@ use vfscore::{
     error::VfsError,
     fstype::{FileSystemFlags, VfsFsType},
     inode::VfsInode,
+    page::{VfsFrameAllocator, VfsHeapFrameAllocator},
     superblock::VfsSuperBlock,
-    utils::{VfsNodePerm, VfsTimeSpec},
+    utils::{VfsMountFlags, VfsNodePerm, VfsTimeSpec},
     VfsResult,
 };
 
 pub trait RamFsProvider: Send + Sync + Clone {
     fn current_time(&self) -> VfsTimeSpec;
+    /// The allocator of the pages which hold the file data, the pages are mapped by mmap directly
+    fn frame_allocator(&self) -> Arc<dyn VfsFrameAllocator> {
+        Arc::new(VfsHeapFrameAllocator)
+    }
 }
 
 pub struct RamFs<T: Send + Sync, R: VfsRawMutex> {
@@ -44,13 +49,15 @@ impl<T: RamFsProvider, R: VfsRawMutex + 'static> RamFs<T, R> {
 impl<T: RamFsProvider + 'static, R: VfsRawMutex + 'static> VfsFsType for RamFs<T, R> {
     fn mount(
         self: Arc<Self>,
-        _flags: u32,
+        flags: u32,
         _ab_mnt: &str,
         _dev: Option<Arc<dyn VfsInode>>,
         _data: &[u8],
     ) -> VfsResult<Arc<dyn VfsDentry>> {
         let unifs = Arc::new(UniFs::<T, R>::new("ramfs", self.provider.clone()));
         let sb = UniFsSuperBlock::new(&(self.clone() as Arc<dyn VfsFsType>));
+        // ramfs stores ACLs in its xattrs, so they are always supported
+        sb.set_mount_flags(VfsMountFlags::from_bits_truncate(flags) | VfsMountFlags::MS_POSIXACL);
         let root = Arc::new(RamFsDirInode::new(
             &sb,
             self.provider.clone(),
@@ -1,12 +1,29 @@
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::Waker,
+};
 
 use ramfs::{RamFs, RamFsProvider};
 use spin::{mutex::Mutex, Lazy};
 use vfscore::{
+    acl::{
+        VfsAcl, VfsAclEntry, VfsAclPerm, VfsAclTag, XATTR_POSIX_ACL_ACCESS, XATTR_POSIX_ACL_DEFAULT,
+    },
+    copy::{copy_file_range, VFS_COPY_CHUNK_SIZE},
+    cred::VfsCred,
     dentry::VfsDentry,
+    error::VfsError,
     fstype::VfsFsType,
-    path::DirIter,
-    utils::{VfsNodeType, VfsTimeSpec},
+    inode::InodeAttr,
+    lock::{VfsFileLock, VfsLockType},
+    path::{DirIter, SysContext, VfsCopyError, VfsPath},
+    utils::{
+        VfsFallocFlags, VfsInodeMode, VfsMountFlags, VfsNodePerm, VfsNodeType, VfsPollEvents,
+        VfsRenameFlag, VfsSeekDataHole, VfsTimeSet, VfsTimeSpec, VfsTimes,
+    },
     VfsResult,
 };
 
@@ -75,6 +92,793 @@ fn test_symlink() {
     assert_eq!(buf, b"f1");
 }
 
+#[test]
+fn test_lock() {
+    let root = make_ramfs().unwrap();
+    let f1 = root
+        .inode()
+        .unwrap()
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    f1.set_lock(VfsFileLock::new(1, 1, VfsLockType::Write, 0, 10))
+        .unwrap();
+    let res = f1.set_lock(VfsFileLock::new(2, 2, VfsLockType::Read, 5, 0));
+    assert_eq!(res, Err(VfsError::EAGAIN));
+    let blocker = f1
+        .get_lock(VfsFileLock::new(2, 2, VfsLockType::Read, 5, 0))
+        .unwrap();
+    assert_eq!(blocker.map(|l| l.pid), Some(1));
+    f1.set_lock(VfsFileLock::new(2, 2, VfsLockType::Read, 10, 0))
+        .unwrap();
+
+    f1.flock(1, VfsLockType::Write).unwrap();
+    assert_eq!(f1.flock(2, VfsLockType::Read), Err(VfsError::EAGAIN));
+    // the last close of the open file description drops its flock lock, but not the POSIX
+    // locks of the process with the same number
+    f1.release_flock(1).unwrap();
+    f1.flock(2, VfsLockType::Read).unwrap();
+    let res = f1.set_lock(VfsFileLock::new(2, 2, VfsLockType::Write, 0, 0));
+    assert_eq!(res, Err(VfsError::EAGAIN));
+    f1.release_posix(1).unwrap();
+    f1.set_lock(VfsFileLock::new(2, 2, VfsLockType::Write, 0, 0))
+        .unwrap();
+}
+
+#[test]
+fn test_poll() {
+    let root = make_ramfs().unwrap();
+    let f1 = root
+        .inode()
+        .unwrap()
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    // regular files are always ready, no waiter is kept
+    let res = f1
+        .poll_register(VfsPollEvents::IN | VfsPollEvents::OUT, Waker::noop())
+        .unwrap();
+    assert_eq!(res, VfsPollEvents::IN | VfsPollEvents::OUT);
+}
+
+#[test]
+fn test_mmap() {
+    let root = make_ramfs().unwrap();
+    let f1 = root
+        .inode()
+        .unwrap()
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    assert!(f1.get_page(0).is_err());
+    f1.write_at(4090, &[1; 10]).unwrap();
+    let page0 = f1.get_page(0).unwrap();
+    let page1 = f1.get_page(4096).unwrap();
+    assert!(Arc::ptr_eq(&page1, &f1.get_page(4096).unwrap()));
+    let mut buf = [0; 4];
+    page1.read_at(0, &mut buf);
+    assert_eq!(buf, [1; 4]);
+    // the page is shared with read_at/write_at
+    page0.write_at(0, &[2; 4]);
+    f1.set_page_dirty(0).unwrap();
+    f1.read_at(0, &mut buf).unwrap();
+    assert_eq!(buf, [2; 4]);
+    f1.write_at(4096, &[3; 2]).unwrap();
+    page1.read_at(0, &mut buf);
+    assert_eq!(buf, [3, 3, 1, 1]);
+    // the tail of the page is zeroed when the file grows again
+    f1.truncate(4097).unwrap();
+    f1.truncate(4100).unwrap();
+    f1.read_at(4096, &mut buf).unwrap();
+    assert_eq!(buf, [3, 0, 0, 0]);
+}
+
+#[test]
+fn test_fallocate() {
+    let root = make_ramfs().unwrap();
+    let f1 = root
+        .inode()
+        .unwrap()
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    f1.fallocate(VfsFallocFlags::empty(), 0, 8192).unwrap();
+    assert_eq!(f1.get_attr().unwrap().st_size, 8192);
+    f1.fallocate(VfsFallocFlags::KEEP_SIZE, 8192, 4096).unwrap();
+    assert_eq!(f1.get_attr().unwrap().st_size, 8192);
+    assert_eq!(
+        f1.fallocate(VfsFallocFlags::PUNCH_HOLE, 0, 4096),
+        Err(VfsError::EOPNOTSUPP)
+    );
+
+    f1.write_at(0, &[1; 8192]).unwrap();
+    f1.fallocate(
+        VfsFallocFlags::PUNCH_HOLE | VfsFallocFlags::KEEP_SIZE,
+        2,
+        4094,
+    )
+    .unwrap();
+    let mut buf = [0; 4];
+    f1.read_at(0, &mut buf).unwrap();
+    assert_eq!(buf, [1, 1, 0, 0]);
+    f1.fallocate(
+        VfsFallocFlags::PUNCH_HOLE | VfsFallocFlags::KEEP_SIZE,
+        4096,
+        4096,
+    )
+    .unwrap();
+    assert_eq!(f1.seek_data_hole(0, VfsSeekDataHole::Hole), Ok(4096));
+    assert_eq!(
+        f1.seek_data_hole(4096, VfsSeekDataHole::Data),
+        Err(VfsError::ENXIO)
+    );
+
+    f1.fallocate(VfsFallocFlags::ZERO_RANGE, 0, 12288).unwrap();
+    assert_eq!(f1.get_attr().unwrap().st_size, 12288);
+    f1.read_at(0, &mut buf).unwrap();
+    assert_eq!(buf, [0; 4]);
+    assert_eq!(f1.seek_data_hole(5000, VfsSeekDataHole::Data), Ok(5000));
+    assert_eq!(f1.seek_data_hole(0, VfsSeekDataHole::Hole), Ok(12288));
+}
+
+#[test]
+fn test_copy_file_range() {
+    let root = make_ramfs().unwrap();
+    let f1 = root
+        .inode()
+        .unwrap()
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    let f2 = root
+        .inode()
+        .unwrap()
+        .create("f2", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    f1.write_at(0, b"hello").unwrap();
+    f1.write_at(8192, b"world").unwrap();
+    let len = copy_file_range(
+        f1.as_ref(),
+        0,
+        f2.as_ref(),
+        4096,
+        100000,
+        VFS_COPY_CHUNK_SIZE,
+    )
+    .unwrap();
+    assert_eq!(len, 8197);
+    assert_eq!(f2.get_attr().unwrap().st_size, 12293);
+    let mut buf = [0; 5];
+    f2.read_at(4096, &mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+    f2.read_at(12288, &mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+    // the hole in the middle of f1 is kept
+    assert_eq!(f2.seek_data_hole(4096, VfsSeekDataHole::Hole), Ok(8192));
+
+    assert_eq!(
+        copy_file_range(f1.as_ref(), 0, f1.as_ref(), 4, 10, VFS_COPY_CHUNK_SIZE),
+        Err(VfsError::Invalid)
+    );
+    assert_eq!(
+        copy_file_range(f1.as_ref(), 0, f1.as_ref(), 4096, 10, VFS_COPY_CHUNK_SIZE),
+        Ok(10)
+    );
+    assert_eq!(
+        copy_file_range(
+            root.inode().unwrap().as_ref(),
+            0,
+            f1.as_ref(),
+            0,
+            10,
+            VFS_COPY_CHUNK_SIZE
+        ),
+        Err(VfsError::IsDir)
+    );
+}
+
+#[test]
+fn test_vectored_io() {
+    let root = make_ramfs().unwrap();
+    let f1 = root
+        .inode()
+        .unwrap()
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    let len = f1.write_vectored_at(4094, &[b"ab", b"", b"cdef"]).unwrap();
+    assert_eq!(len, 6);
+    let mut a = [0; 3];
+    let mut b = [0; 5];
+    let len = f1.read_vectored_at(4094, &mut [&mut a, &mut b]).unwrap();
+    assert_eq!(len, 6);
+    assert_eq!(&a, b"abc");
+    assert_eq!(&b[..3], b"def");
+}
+
+#[test]
+fn test_set_times() {
+    let root = make_ramfs().unwrap();
+    let f1 = root
+        .inode()
+        .unwrap()
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    let now = VfsTimeSpec::new(100, 0);
+    let times = VfsTimes::new(VfsTimeSet::Set(VfsTimeSpec::new(1, 2)), VfsTimeSet::Now);
+    f1.set_times(times, now).unwrap();
+    let stat = f1.get_attr().unwrap();
+    assert_eq!(stat.st_atime, VfsTimeSpec::new(1, 2));
+    assert_eq!(stat.st_mtime, now);
+    assert_eq!(stat.st_ctime, now);
+    // nothing changes, neither does ctime
+    f1.set_times(VfsTimes::default(), VfsTimeSpec::new(200, 0))
+        .unwrap();
+    assert_eq!(f1.get_attr().unwrap().st_ctime, now);
+}
+
+#[test]
+fn test_set_attr() {
+    let root = make_ramfs().unwrap();
+    let dir = root.inode().unwrap();
+    let f1 = dir
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    f1.write_at(0, &[1; 100]).unwrap();
+    let now = VfsTimeSpec::new(100, 0);
+
+    // only the fields in the mask are set
+    f1.set_attr(InodeAttr::new(now).with_mode(0o4750)).unwrap();
+    let stat = f1.get_attr().unwrap();
+    assert_eq!(stat.st_mode, 0o104750);
+    assert_eq!((stat.st_uid, stat.st_gid), (0, 0));
+    assert_eq!(stat.st_size, 100);
+    assert_eq!(stat.st_ctime, now);
+    f1.set_attr(InodeAttr::new(now).with_owner(1000, 100))
+        .unwrap();
+    let stat = f1.get_attr().unwrap();
+    assert_eq!((stat.st_uid, stat.st_gid), (1000, 100));
+    assert_eq!(stat.st_mode, 0o104750);
+
+    let times = VfsTimes::new(VfsTimeSet::Set(VfsTimeSpec::new(1, 2)), VfsTimeSet::Omit);
+    let attr = InodeAttr::new(VfsTimeSpec::new(200, 0))
+        .with_size(10)
+        .with_times(times);
+    f1.set_attr(attr).unwrap();
+    let stat = f1.get_attr().unwrap();
+    assert_eq!(stat.st_size, 10);
+    assert_eq!(stat.st_atime, VfsTimeSpec::new(1, 2));
+    assert_eq!(stat.st_ctime, VfsTimeSpec::new(200, 0));
+    // nothing changes, neither does ctime
+    f1.set_attr(InodeAttr::new(VfsTimeSpec::new(300, 0)))
+        .unwrap();
+    assert_eq!(f1.get_attr().unwrap().st_ctime, VfsTimeSpec::new(200, 0));
+
+    let res = dir.set_attr(InodeAttr::new(now).with_size(0));
+    assert_eq!(res, Err(VfsError::IsDir));
+    dir.set_attr(InodeAttr::new(now).with_mode(0o1777).with_owner(1, 2))
+        .unwrap();
+    let stat = dir.get_attr().unwrap();
+    assert_eq!(stat.st_mode, 0o41777);
+    assert_eq!((stat.st_uid, stat.st_gid), (1, 2));
+    let l = dir.symlink("l", "f1").unwrap();
+    let res = l.set_attr(InodeAttr::new(now).with_size(0));
+    assert_eq!(res, Err(VfsError::Invalid));
+}
+
+/// A provider whose clock is moved by the test
+#[derive(Clone)]
+struct ClockProvider(Arc<AtomicU64>);
+impl RamFsProvider for ClockProvider {
+    fn current_time(&self) -> VfsTimeSpec {
+        VfsTimeSpec::new(self.0.load(Ordering::SeqCst), 0)
+    }
+}
+
+#[test]
+fn test_auto_times() {
+    let clock = Arc::new(AtomicU64::new(100));
+    let fs = Arc::new(RamFs::<_, Mutex<()>>::new(ClockProvider(clock.clone())));
+    let root = fs
+        .clone()
+        .mount(VfsMountFlags::MS_RELATIME.bits(), "/", None, &[])
+        .unwrap()
+        .inode()
+        .unwrap();
+    clock.store(200, Ordering::SeqCst);
+    let f1 = root
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    assert_eq!(root.get_attr().unwrap().st_mtime, VfsTimeSpec::new(200, 0));
+
+    clock.store(300, Ordering::SeqCst);
+    f1.write_at(0, b"hello").unwrap();
+    let stat = f1.get_attr().unwrap();
+    assert_eq!(stat.st_mtime, VfsTimeSpec::new(300, 0));
+    assert_eq!(stat.st_ctime, VfsTimeSpec::new(300, 0));
+
+    // relatime: atime is updated once after the file is modified
+    clock.store(400, Ordering::SeqCst);
+    f1.read_at(0, &mut [0; 5]).unwrap();
+    assert_eq!(f1.get_attr().unwrap().st_atime, VfsTimeSpec::new(400, 0));
+    clock.store(500, Ordering::SeqCst);
+    f1.read_at(0, &mut [0; 5]).unwrap();
+    assert_eq!(f1.get_attr().unwrap().st_atime, VfsTimeSpec::new(400, 0));
+
+    clock.store(600, Ordering::SeqCst);
+    f1.truncate(0).unwrap();
+    assert_eq!(f1.get_attr().unwrap().st_mtime, VfsTimeSpec::new(600, 0));
+    root.unlink("f1").unwrap();
+    assert_eq!(root.get_attr().unwrap().st_ctime, VfsTimeSpec::new(600, 0));
+
+    let fs = Arc::new(RamFs::<_, Mutex<()>>::new(ClockProvider(clock.clone())));
+    let root = fs
+        .clone()
+        .mount(VfsMountFlags::MS_NOATIME.bits(), "/", None, &[])
+        .unwrap()
+        .inode()
+        .unwrap();
+    let f1 = root
+        .create("f1", VfsNodeType::File, "rw-rw-rw-".into(), None)
+        .unwrap();
+    clock.store(700, Ordering::SeqCst);
+    f1.read_at(0, &mut [0; 5]).unwrap();
+    assert_eq!(f1.get_attr().unwrap().st_atime, VfsTimeSpec::new(600, 0));
+}
+
+#[test]
+fn test_readdir_cookie() {
+    let root = make_ramfs().unwrap().inode().unwrap();
+    let dir = root
+        .create("dir", VfsNodeType::Dir, "rwxrwxrwx".into(), None)
+        .unwrap();
+    for name in ["a", "b", "c", "d"] {
+        dir.create(name, VfsNodeType::File, "rw-rw-rw-".into(), None)
+            .unwrap();
+    }
+    // remove every entry right after it is read, like rm -rf
+    let mut cookie = 0;
+    let mut names = Vec::new();
+    while let Some(entry) = dir.readdir(cookie).unwrap() {
+        dir.unlink(&entry.name).unwrap();
+        cookie = entry.off;
+        names.push(entry.name);
+    }
+    assert_eq!(names, ["a", "b", "c", "d"]);
+    assert!(dir.readdir(0).unwrap().is_none());
+}
+
+#[test]
+fn test_tree() {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let perm = VfsNodePerm::from_bits_truncate(0o750);
+    let file = VfsInodeMode::from_bits_truncate(0o640) | VfsInodeMode::FILE;
+    let a = path.join("a").unwrap();
+    a.join("b/c").unwrap().create_dir_all(perm).unwrap();
+    a.join("b/c").unwrap().create_dir_all(perm).unwrap();
+    assert_eq!(a.open(None).unwrap().inode().unwrap().node_perm(), perm);
+
+    let f = a
+        .join("b/f")
+        .unwrap()
+        .open(Some(file))
+        .unwrap()
+        .inode()
+        .unwrap();
+    f.write_at(0, b"hello").unwrap();
+    f.set_xattr("user.k", b"v").unwrap();
+    let times = VfsTimes::new(
+        VfsTimeSet::Set(VfsTimeSpec::new(1, 0)),
+        VfsTimeSet::Set(VfsTimeSpec::new(2, 0)),
+    );
+    f.set_times(times, Default::default()).unwrap();
+    a.join("l").unwrap().symlink("b").unwrap();
+    let res = a.join("b/f/x").unwrap().create_dir_all(perm);
+    assert_eq!(res.err(), Some(VfsError::NotDir));
+
+    // copy to another filesystem
+    let other = make_ramfs().unwrap();
+    let dst = VfsPath::new(other.clone(), other).join("copy").unwrap();
+    assert!(a.copy_tree(&dst).unwrap().is_empty());
+    assert!(dst.join("b/c").unwrap().open(None).is_ok());
+    let copy = dst
+        .join("b/f")
+        .unwrap()
+        .open(None)
+        .unwrap()
+        .inode()
+        .unwrap();
+    // check the times before reading updates the atime
+    let stat = copy.get_attr().unwrap();
+    assert_eq!(stat.st_atime, VfsTimeSpec::new(1, 0));
+    assert_eq!(stat.st_mtime, VfsTimeSpec::new(2, 0));
+    let mut buf = [0; 5];
+    assert_eq!(copy.read_at(0, &mut buf).unwrap(), 5);
+    assert_eq!(&buf, b"hello");
+    assert_eq!(copy.get_xattr("user.k").unwrap(), b"v");
+    assert_eq!(copy.node_perm(), "rw-r-----".into());
+    let link = dst
+        .open(None)
+        .unwrap()
+        .inode()
+        .unwrap()
+        .lookup("l")
+        .unwrap();
+    let len = link.readlink(&mut buf).unwrap();
+    assert_eq!(&buf[..len], b"b");
+
+    // an entry which fails doesn't stop the others
+    let dst = path.join("copy").unwrap();
+    dst.join("b").unwrap().create_dir_all(perm).unwrap();
+    dst.join("b/f").unwrap().open(Some(file)).unwrap();
+    let errors = a.copy_tree(&dst).unwrap();
+    assert_eq!(
+        errors,
+        vec![VfsCopyError {
+            path: "/a/b/f".to_string(),
+            error: VfsError::EExist
+        }]
+    );
+    assert!(dst.join("b/c").unwrap().open(None).is_ok());
+    // a directory can't be copied into itself
+    let res = a.copy_tree(&a.join("b/c/a").unwrap());
+    assert_eq!(res, Err(VfsError::Invalid));
+
+    // symlinks are removed, not followed
+    let keep = path.join("keep").unwrap();
+    keep.create_dir_all(perm).unwrap();
+    keep.join("f").unwrap().open(Some(file)).unwrap();
+    a.join("keep").unwrap().symlink("/keep").unwrap();
+    a.remove_dir_all().unwrap();
+    assert_eq!(a.open(None).err(), Some(VfsError::NoEntry));
+    assert!(keep.join("f").unwrap().open(None).is_ok());
+    let res = keep.join("f").unwrap().remove_dir_all();
+    assert_eq!(res, Err(VfsError::NotDir));
+}
+
+#[test]
+fn test_owner() {
+    let root = make_ramfs().unwrap();
+    let context = SysContext {
+        pid: 1,
+        cred: VfsCred::user(1000, 100),
+        umask: VfsNodePerm::from_bits_truncate(0o022),
+        cwd: root.clone(),
+        root: root.clone(),
+    };
+    let path = VfsPath::new(root.clone(), root.clone());
+    let user = path.clone().with_context(context);
+    // the owner of an entry of the root, symlinks are not followed
+    let owner = |name: &str| {
+        let stat = root
+            .inode()
+            .unwrap()
+            .lookup(name)
+            .unwrap()
+            .get_attr()
+            .unwrap();
+        (stat.st_uid, stat.st_gid, stat.st_mode)
+    };
+    let file = VfsInodeMode::from_bits_truncate(0o666) | VfsInodeMode::FILE;
+    let dir = VfsInodeMode::from_bits_truncate(0o777) | VfsInodeMode::DIR;
+    let root_inode = root.inode().unwrap();
+    let now = root_inode.get_attr().unwrap().st_ctime;
+    root_inode
+        .set_attr(InodeAttr::new(now).with_mode(0o777))
+        .unwrap();
+
+    user.join("f").unwrap().open(Some(file)).unwrap();
+    assert_eq!(owner("f"), (1000, 100, 0o100644));
+    user.join("d").unwrap().open(Some(dir)).unwrap();
+    assert_eq!(owner("d"), (1000, 100, 0o40755));
+    user.join("l").unwrap().symlink("f").unwrap();
+    assert_eq!(owner("l").0, 1000);
+    path.join("r").unwrap().open(Some(file)).unwrap();
+    assert_eq!(owner("r"), (0, 0, 0o100666));
+
+    // the entries of a setgid directory get its group
+    let sg = path.join("sg").unwrap();
+    sg.open(Some(dir)).unwrap();
+    sg.chown(Some(0), Some(50)).unwrap();
+    let sg_inode = sg.open(None).unwrap().inode().unwrap();
+    let now = sg_inode.get_attr().unwrap().st_ctime;
+    sg_inode
+        .set_attr(InodeAttr::new(now).with_mode(0o2777))
+        .unwrap();
+    let f = user.join("sg/f").unwrap().open(Some(file)).unwrap();
+    let stat = f.inode().unwrap().get_attr().unwrap();
+    assert_eq!((stat.st_uid, stat.st_gid), (1000, 50));
+    let d = user.join("sg/d").unwrap().open(Some(dir)).unwrap();
+    let stat = d.inode().unwrap().get_attr().unwrap();
+    assert_eq!(
+        (stat.st_uid, stat.st_gid, stat.st_mode),
+        (1000, 50, 0o42755)
+    );
+
+    // the owners can remove their entries of a sticky directory
+    let tmp = path.join("tmp").unwrap();
+    tmp.open(Some(
+        VfsInodeMode::from_bits_truncate(0o1777) | VfsInodeMode::DIR,
+    ))
+    .unwrap();
+    user.join("tmp/mine").unwrap().open(Some(file)).unwrap();
+    path.join("tmp/root").unwrap().open(Some(file)).unwrap();
+    user.join("tmp/mine").unwrap().unlink().unwrap();
+    let res = user.join("tmp/root").unwrap().unlink();
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+
+    // a copy by root keeps the owners, a copy by a user is owned by the user, and both keep the
+    // permissions whatever the umask is
+    let errors = path.join("d").unwrap().copy_tree(&path.join("d2").unwrap());
+    assert_eq!(errors, Ok(vec![]));
+    assert_eq!(owner("d2").0, 1000);
+    let errors = path.join("r").unwrap().copy_tree(&user.join("r2").unwrap());
+    assert_eq!(errors, Ok(vec![]));
+    assert_eq!(owner("r2"), (1000, 100, 0o100666));
+}
+
+#[test]
+fn test_acl() {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let user = |uid| {
+        path.clone().with_context(SysContext {
+            pid: 1,
+            cred: VfsCred::user(uid, 100),
+            umask: VfsNodePerm::from_bits_truncate(0o022),
+            cwd: root.clone(),
+            root: root.clone(),
+        })
+    };
+    let entry = |tag, perm| VfsAclEntry::new(tag, VfsAclPerm::from_bits_truncate(perm));
+    let mode = |path: &VfsPath| {
+        path.open(None)
+            .unwrap()
+            .inode()
+            .unwrap()
+            .get_attr()
+            .unwrap()
+            .st_mode
+    };
+    let file = VfsInodeMode::from_bits_truncate(0o666) | VfsInodeMode::FILE;
+    let dir = VfsInodeMode::from_bits_truncate(0o777) | VfsInodeMode::DIR;
+
+    // a named user can write a file which is only writable by its owner
+    let f = path.join("f").unwrap();
+    f.open(Some(
+        VfsInodeMode::from_bits_truncate(0o644) | VfsInodeMode::FILE,
+    ))
+    .unwrap();
+    assert_eq!(
+        user(1000).join("f").unwrap().truncate(0),
+        Err(VfsError::Access)
+    );
+    let acl = VfsAcl::new(vec![
+        entry(VfsAclTag::UserObj, 6),
+        entry(VfsAclTag::User(1000), 6),
+        entry(VfsAclTag::GroupObj, 4),
+        entry(VfsAclTag::Mask, 6),
+        entry(VfsAclTag::Other, 4),
+    ])
+    .unwrap();
+    f.set_xattr(XATTR_POSIX_ACL_ACCESS, &acl.to_xattr())
+        .unwrap();
+    assert_eq!(mode(&f), 0o100664);
+    user(1000).join("f").unwrap().truncate(0).unwrap();
+    assert_eq!(
+        user(1001).join("f").unwrap().truncate(0),
+        Err(VfsError::Access)
+    );
+    // the mask limits the named users
+    let f_inode = f.open(None).unwrap().inode().unwrap();
+    let now = f_inode.get_attr().unwrap().st_ctime;
+    f_inode
+        .set_attr(InodeAttr::new(now).with_mode(0o644))
+        .unwrap();
+    assert_eq!(
+        user(1000).join("f").unwrap().truncate(0),
+        Err(VfsError::Access)
+    );
+
+    // the entries of a directory with a default ACL inherit it instead of the umask
+    let d = path.join("d").unwrap();
+    d.open(Some(
+        VfsInodeMode::from_bits_truncate(0o755) | VfsInodeMode::DIR,
+    ))
+    .unwrap();
+    let default = VfsAcl::new(vec![
+        entry(VfsAclTag::UserObj, 7),
+        entry(VfsAclTag::User(1000), 7),
+        entry(VfsAclTag::GroupObj, 5),
+        entry(VfsAclTag::Mask, 7),
+        entry(VfsAclTag::Other, 0),
+    ])
+    .unwrap();
+    d.set_xattr(XATTR_POSIX_ACL_DEFAULT, &default.to_xattr())
+        .unwrap();
+    // the default ACL doesn't grant access to the directory itself
+    let res = user(1000).join("d/a").unwrap().open(Some(file));
+    assert_eq!(res.err(), Some(VfsError::Access));
+    let a = path.join("d/a").unwrap();
+    a.open(Some(file)).unwrap();
+    assert_eq!(mode(&a), 0o100660);
+    let access = VfsAcl::from_xattr(&a.get_xattr(XATTR_POSIX_ACL_ACCESS).unwrap()).unwrap();
+    assert_eq!(access.entries()[1], entry(VfsAclTag::User(1000), 7));
+    assert_eq!(access.entries()[3], entry(VfsAclTag::Mask, 6));
+    assert_eq!(a.get_xattr(XATTR_POSIX_ACL_DEFAULT), Err(VfsError::ENODATA));
+    user(1000).join("d/a").unwrap().truncate(0).unwrap();
+    assert_eq!(
+        user(1001).join("d/a").unwrap().truncate(0),
+        Err(VfsError::Access)
+    );
+    let sub = path.join("d/sub").unwrap();
+    sub.open(Some(dir)).unwrap();
+    assert_eq!(mode(&sub), 0o40770);
+    assert_eq!(
+        sub.get_xattr(XATTR_POSIX_ACL_DEFAULT),
+        Ok(default.to_xattr())
+    );
+
+    // only valid ACLs are set, by the owner
+    let res = f.set_xattr(XATTR_POSIX_ACL_ACCESS, &[2, 0, 0, 0]);
+    assert_eq!(res, Err(VfsError::Invalid));
+    let res = f.set_xattr(XATTR_POSIX_ACL_DEFAULT, &default.to_xattr());
+    assert_eq!(res, Err(VfsError::Access));
+    let res = user(1000)
+        .join("f")
+        .unwrap()
+        .set_xattr(XATTR_POSIX_ACL_ACCESS, &acl.to_xattr());
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+}
+
+#[test]
+fn test_xattr_permission() {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let user = path.clone().with_context(SysContext {
+        pid: 1,
+        cred: VfsCred::user(1000, 100),
+        umask: VfsNodePerm::empty(),
+        cwd: root.clone(),
+        root: root.clone(),
+    });
+    let file = |perm| VfsInodeMode::from_bits_truncate(perm) | VfsInodeMode::FILE;
+    path.join("ro").unwrap().open(Some(file(0o644))).unwrap();
+    path.join("rw").unwrap().open(Some(file(0o666))).unwrap();
+    path.join("l").unwrap().symlink("rw").unwrap();
+
+    // the user xattrs need the write permission
+    let res = user.join("ro").unwrap().set_xattr("user.k", b"v");
+    assert_eq!(res, Err(VfsError::Access));
+    user.join("rw").unwrap().set_xattr("user.k", b"v").unwrap();
+    assert_eq!(
+        path.join("l").unwrap().get_xattr("user.k"),
+        Ok(b"v".to_vec())
+    );
+
+    // the trusted and security xattrs need SYS_ADMIN
+    let res = user.join("rw").unwrap().set_xattr("trusted.k", b"v");
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    let res = user.join("rw").unwrap().set_xattr("security.k", b"v");
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    path.join("rw")
+        .unwrap()
+        .set_xattr("trusted.k", b"v")
+        .unwrap();
+}
+
+#[test]
+fn test_write_remove_privs() {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let user = path.clone().with_context(SysContext {
+        pid: 1,
+        cred: VfsCred::user(1000, 100),
+        umask: VfsNodePerm::empty(),
+        cwd: root.clone(),
+        root: root.clone(),
+    });
+    let f = path.join("f").unwrap();
+    let inode = f
+        .open(Some(
+            VfsInodeMode::from_bits_truncate(0o666) | VfsInodeMode::FILE,
+        ))
+        .unwrap()
+        .inode()
+        .unwrap();
+    f.chmod(VfsNodePerm::from_bits_truncate(0o6777)).unwrap();
+
+    // a path without a context keeps the bits
+    assert_eq!(f.write_at(0, b"root"), Ok(4));
+    assert_eq!(inode.get_attr().unwrap().st_mode, 0o106777);
+    // a write by another user clears them
+    assert_eq!(user.join("f").unwrap().write_at(4, b"user"), Ok(4));
+    assert_eq!(inode.get_attr().unwrap().st_mode, 0o100777);
+    let mut buf = [0; 8];
+    inode.read_at(0, &mut buf).unwrap();
+    assert_eq!(&buf, b"rootuser");
+
+    let res = user.join("f").unwrap().parent().write_at(0, b"x");
+    assert_eq!(res, Err(VfsError::IsDir));
+}
+
+#[test]
+fn test_move_path() {
+    let root = make_ramfs().unwrap();
+    let src = VfsPath::new(root.clone(), root.clone());
+    let other = make_ramfs().unwrap();
+    let dst = VfsPath::new(other.clone(), other.clone());
+    let context = SysContext {
+        pid: 0,
+        cred: VfsCred::root(),
+        umask: VfsNodePerm::empty(),
+        cwd: root.clone(),
+        root,
+    };
+    let perm = VfsNodePerm::from_bits_truncate(0o755);
+    let file = VfsInodeMode::from_bits_truncate(0o644) | VfsInodeMode::FILE;
+    src.join("m/d").unwrap().create_dir_all(perm).unwrap();
+    let f = src.join("m/d/f").unwrap().open(Some(file)).unwrap();
+    f.inode().unwrap().write_at(0, b"hello").unwrap();
+
+    let res = src.join("m").unwrap().rename_to(
+        context.clone(),
+        dst.join("m").unwrap(),
+        VfsRenameFlag::empty(),
+    );
+    assert_eq!(res, Err(VfsError::EXDEV));
+    let res = dst.join("l").unwrap().link(f);
+    assert_eq!(res, Err(VfsError::EXDEV));
+
+    src.join("m")
+        .unwrap()
+        .move_path(context.clone(), &dst.join("moved").unwrap())
+        .unwrap();
+    assert_eq!(
+        src.join("m").unwrap().open(None).err(),
+        Some(VfsError::NoEntry)
+    );
+    let f = dst.join("moved/d/f").unwrap().open(None).unwrap();
+    let mut buf = [0; 5];
+    f.inode().unwrap().read_at(0, &mut buf).unwrap();
+    assert_eq!(&buf, b"hello");
+    assert_eq!(other.inode().unwrap().children().count(), 1);
+
+    // an existing file is replaced
+    dst.join("old").unwrap().open(Some(file)).unwrap();
+    let g = src.join("g").unwrap().open(Some(file)).unwrap();
+    g.inode().unwrap().write_at(0, b"world").unwrap();
+    src.join("g")
+        .unwrap()
+        .move_path(context.clone(), &dst.join("old").unwrap())
+        .unwrap();
+    let old = dst.join("old").unwrap().open(None).unwrap();
+    old.inode().unwrap().read_at(0, &mut buf).unwrap();
+    assert_eq!(&buf, b"world");
+    assert_eq!(other.inode().unwrap().children().count(), 2);
+
+    // a symlink is moved, not its target
+    src.join("t").unwrap().open(Some(file)).unwrap();
+    src.join("l").unwrap().symlink("t").unwrap();
+    src.join("l")
+        .unwrap()
+        .move_path(context.clone(), &dst.join("l").unwrap())
+        .unwrap();
+    let l = dst.join("l").unwrap().exists().unwrap();
+    assert_eq!(l.inode().unwrap().inode_type(), VfsNodeType::SymLink);
+    let mut target = [0; 1];
+    l.inode().unwrap().readlink(&mut target).unwrap();
+    assert_eq!(&target, b"t");
+    assert!(src.join("t").unwrap().open(None).is_ok());
+    src.join("l2").unwrap().symlink("t").unwrap();
+    src.join("l2")
+        .unwrap()
+        .move_path(context, &src.join("l3").unwrap())
+        .unwrap();
+    let l3 = src.join("l3").unwrap().exists().unwrap();
+    assert_eq!(l3.inode().unwrap().inode_type(), VfsNodeType::SymLink);
+    assert_eq!(
+        src.join("l2").unwrap().exists().err(),
+        Some(VfsError::NoEntry)
+    );
+    assert!(src.join("t").unwrap().open(None).is_ok());
+}
+
 #[test]
 fn test_unlink() {}
 
@@ -1,12 +1,15 @@
 use alloc::{
+    collections::BTreeMap,
     string::{String, ToString},
     sync::Weak,
-    vec::Vec,
 };
 
 use vfscore::{
-    inode::InodeAttr,
-    utils::{VfsDirEntry, VfsFileStat, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime},
+    inode::{InodeAttr, InodeAttrMask},
+    lock::{VfsFileLock, VfsLockList, VfsLockOwner, VfsLockType},
+    utils::{
+        VfsDirEntry, VfsFileStat, VfsMountFlags, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTimes,
+    },
 };
 
 use crate::{UniFsSuperBlock, *};
@@ -16,6 +19,61 @@ pub struct UniFsInodeSame<T: Send + Sync, R: VfsRawMutex> {
     pub inode_number: u64,
     pub provider: T,
     pub inner: lock_api::Mutex<R, UniFsInodeAttr>,
+    pub locks: lock_api::Mutex<R, VfsLockList>,
+}
+
+impl<T: Send + Sync, R: VfsRawMutex> UniFsInodeSame<T, R> {
+    pub fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        Ok(self.locks.lock().test(&lock))
+    }
+    pub fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+        self.locks.lock().set(lock)
+    }
+    pub fn flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        self.locks.lock().set_flock(owner, ty)
+    }
+    pub fn release_posix(&self, pid: VfsLockOwner) -> VfsResult<()> {
+        self.locks.lock().release_posix(pid);
+        Ok(())
+    }
+    pub fn release_flock(&self, ofd: VfsLockOwner) -> VfsResult<()> {
+        self.locks.lock().release_flock(ofd);
+        Ok(())
+    }
+    /// Update atime after the inode is read, following the atime flags of the mount
+    pub fn update_atime(&self, is_dir: bool, now: VfsTimeSpec) {
+        let flags = self
+            .sb
+            .upgrade()
+            .map(|sb| sb.mount_flags())
+            .unwrap_or(VfsMountFlags::empty());
+        let mut inner = self.inner.lock();
+        if flags.atime_needs_update(is_dir, inner.atime, inner.mtime, inner.ctime, now) {
+            inner.atime = now;
+        }
+    }
+    /// Update mtime and ctime after the data or the children of the inode are modified
+    pub fn update_mtime(&self, now: VfsTimeSpec) {
+        let mut inner = self.inner.lock();
+        inner.mtime = now;
+        inner.ctime = now;
+    }
+    /// Set the mode, the owner and the times in `attr`, the size is left to the inode
+    pub fn set_attr(&self, attr: &InodeAttr) {
+        let inner = &mut *self.inner.lock();
+        attr.apply_to(
+            &mut inner.perm,
+            &mut inner.uid,
+            &mut inner.gid,
+            &mut inner.atime,
+            &mut inner.mtime,
+            &mut inner.ctime,
+        );
+    }
+    /// Update ctime after the metadata of the inode, like the link count, is changed
+    pub fn update_ctime(&self, now: VfsTimeSpec) {
+        self.inner.lock().ctime = now;
+    }
 }
 
 pub struct UniFsInodeAttr {
@@ -24,6 +82,8 @@ pub struct UniFsInodeAttr {
     pub mtime: VfsTimeSpec,
     pub ctime: VfsTimeSpec,
     pub perm: VfsNodePerm,
+    pub uid: u32,
+    pub gid: u32,
 }
 
 pub fn basic_file_stat<T: Send + Sync, R: VfsRawMutex>(
@@ -35,8 +95,8 @@ pub fn basic_file_stat<T: Send + Sync, R: VfsRawMutex>(
         st_ino: basic.inode_number,
         st_mode: inner.perm.bits() as u32,
         st_nlink: inner.link_count,
-        st_uid: 0,
-        st_gid: 0,
+        st_uid: inner.uid,
+        st_gid: inner.gid,
         st_rdev: 0,
         __pad: 0,
         st_size: 4096,
@@ -50,26 +110,83 @@ pub fn basic_file_stat<T: Send + Sync, R: VfsRawMutex>(
     }
 }
 
+/// The children of a directory, each with a value like its inode number.
+///
+/// Every child gets a cookie when it is added, which doesn't change when other children are
+/// added or removed, so readdir can resume from the cookie like telldir/seekdir.
+#[derive(Default)]
+pub struct UniFsDirChildren<T = u64> {
+    next_cookie: u64,
+    entries: BTreeMap<u64, (String, T)>,
+}
+
+impl<T: Copy> UniFsDirChildren<T> {
+    pub const fn new() -> Self {
+        Self {
+            next_cookie: 0,
+            entries: BTreeMap::new(),
+        }
+    }
+    pub fn push(&mut self, name: String, value: T) {
+        self.entries.insert(self.next_cookie, (name, value));
+        self.next_cookie += 1;
+    }
+    /// Find the value of the child `name`
+    pub fn find(&self, name: &str) -> Option<T> {
+        self.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value)
+    }
+    /// Remove the child `name`, return its value
+    pub fn remove(&mut self, name: &str) -> Option<T> {
+        let cookie = self
+            .entries
+            .iter()
+            .find(|(_, (n, _))| n == name)
+            .map(|(cookie, _)| *cookie)?;
+        self.entries.remove(&cookie).map(|(_, value)| value)
+    }
+    /// The first child whose cookie is not less than `cookie`, return its cookie, name and value
+    pub fn next_from(&self, cookie: u64) -> Option<(u64, &str, T)> {
+        self.entries
+            .range(cookie..)
+            .next()
+            .map(|(cookie, (name, value))| (*cookie, name.as_str(), *value))
+    }
+    pub fn iter(&self) -> impl Iterator<Item = (&str, T)> {
+        self.entries
+            .values()
+            .map(|(name, value)| (name.as_str(), *value))
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
 pub struct UniFsDirInode<T: Send + Sync, R: VfsRawMutex> {
     pub basic: UniFsInodeSame<T, R>,
-    pub children: lock_api::Mutex<R, Vec<(String, u64)>>,
+    pub children: lock_api::Mutex<R, UniFsDirChildren>,
 }
 
 impl<T: Send + Sync + 'static, R: VfsRawMutex + 'static> UniFsDirInode<T, R> {
-    pub fn readdir(&self, start_index: usize) -> VfsResult<Option<VfsDirEntry>> {
+    /// Return the first child from `cookie`, `off` of the entry is the cookie of the next one
+    pub fn readdir(&self, cookie: u64) -> VfsResult<Option<VfsDirEntry>> {
         let sb = self.basic.sb.upgrade().unwrap();
         let children = self.children.lock();
         let res = children
-            .iter()
-            .nth(start_index)
-            .map(|(name, inode_number)| {
+            .next_from(cookie)
+            .map(|(cookie, name, inode_number)| {
                 let inode = sb
-                    .get_inode(*inode_number)
+                    .get_inode(inode_number)
                     .unwrap_or_else(|| panic!("inode {} not found in superblock", inode_number,));
                 VfsDirEntry {
-                    ino: *inode_number,
+                    ino: inode_number,
                     ty: inode.inode_type(),
-                    name: name.clone(),
+                    name: name.to_string(),
+                    off: cookie + 1,
                 }
             });
         Ok(res)
@@ -85,9 +202,8 @@ impl<T: Send + Sync + 'static, R: VfsRawMutex + 'static> UniFsDirInode<T, R> {
         let res = self
             .children
             .lock()
-            .iter()
-            .find(|(item_name, _item)| item_name.as_str() == name)
-            .map(|(_, inode_number)| sb.get_inode(*inode_number).unwrap());
+            .find(name)
+            .map(|inode_number| sb.get_inode(inode_number).unwrap());
         if let Some(res) = res {
             Ok(res)
         } else {
@@ -96,7 +212,11 @@ impl<T: Send + Sync + 'static, R: VfsRawMutex + 'static> UniFsDirInode<T, R> {
     }
 
     #[inline]
-    pub fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
+    pub fn set_attr(&self, attr: InodeAttr) -> VfsResult<()> {
+        if attr.mask.contains(InodeAttrMask::SIZE) {
+            return Err(VfsError::IsDir);
+        }
+        self.basic.set_attr(&attr);
         Ok(())
     }
     #[inline]
@@ -112,13 +232,9 @@ impl<T: Send + Sync + 'static, R: VfsRawMutex + 'static> UniFsDirInode<T, R> {
         self.basic.inner.lock().perm
     }
 
-    pub fn update_time(&self, time: VfsTime, now: VfsTimeSpec) -> VfsResult<()> {
-        let mut inner = self.basic.inner.lock();
-        match time {
-            VfsTime::AccessTime(t) => inner.atime = t,
-            VfsTime::ModifiedTime(t) => inner.mtime = t,
-        }
-        inner.ctime = now;
+    pub fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        let inner = &mut *self.basic.inner.lock();
+        times.apply(&mut inner.atime, &mut inner.mtime, &mut inner.ctime, now);
         Ok(())
     }
     pub fn rename_to(
@@ -128,11 +244,9 @@ impl<T: Send + Sync + 'static, R: VfsRawMutex + 'static> UniFsDirInode<T, R> {
         new_name: &str,
         flag: VfsRenameFlag,
     ) -> VfsResult<()> {
-        let old_inode_index = self
-            .children
+        self.children
             .lock()
-            .iter()
-            .position(|(n, _)| n == old_name)
+            .find(old_name)
             .ok_or(VfsError::NoEntry)?;
         let new_inode = new_parent;
         let sb = self
@@ -141,39 +255,32 @@ impl<T: Send + Sync + 'static, R: VfsRawMutex + 'static> UniFsDirInode<T, R> {
             .map_err(|_| VfsError::Invalid)?;
         if flag.contains(VfsRenameFlag::RENAME_EXCHANGE) {
             // the old_name and new_name must exist
-            let new_inode_index = new_inode
+            new_inode
                 .children
                 .lock()
-                .iter()
-                .position(|(n, _)| n == new_name)
+                .find(new_name)
                 .ok_or(VfsError::NoEntry)?;
 
-            let (_, old_inode_number) = self.children.lock().remove(old_inode_index);
-            let (_, new_inode_number) = new_inode.children.lock().remove(new_inode_index);
+            let old_inode_number = self.children.lock().remove(old_name).unwrap();
+            let new_inode_number = new_inode.children.lock().remove(new_name).unwrap();
 
             self.children
                 .lock()
-                .push((new_name.to_string(), new_inode_number));
+                .push(new_name.to_string(), new_inode_number);
             new_inode
                 .children
                 .lock()
-                .push((old_name.to_string(), old_inode_number));
+                .push(old_name.to_string(), old_inode_number);
         } else {
-            let (_, old_inode_number) = self.children.lock().remove(old_inode_index);
+            let old_inode_number = self.children.lock().remove(old_name).unwrap();
             // the new_name may exist or not
             // we only need to delete it if it exists
-            let new_inode_index = new_inode
-                .children
-                .lock()
-                .iter()
-                .position(|(n, _)| n == new_name);
-            if let Some(new_inode_index) = new_inode_index {
-                let (_, new_inode_number) = new_inode.children.lock().remove(new_inode_index);
+            if let Some(new_inode_number) = new_inode.children.lock().remove(new_name) {
                 sb.remove_inode(new_inode_number);
             }
             self.children
                 .lock()
-                .push((new_name.to_string(), old_inode_number));
+                .push(new_name.to_string(), old_inode_number);
         }
         Ok(())
     }
@@ -11,7 +11,7 @@ use alloc::{
     string::{String, ToString},
     sync::{Arc, Weak},
 };
-use core::sync::atomic::{AtomicU64, AtomicUsize};
+use core::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
 
 use log::info;
 use vfscore::{
@@ -20,7 +20,7 @@ use vfscore::{
     fstype::{FileSystemFlags, VfsFsType},
     inode::VfsInode,
     superblock::{SuperType, VfsSuperBlock},
-    utils::{VfsFsStat, VfsTimeSpec},
+    utils::{VfsFsStat, VfsMountFlags, VfsTimeSpec},
     VfsResult,
 };
 
@@ -75,6 +75,7 @@ pub struct UniFsSuperBlock<R: VfsRawMutex> {
     pub inode_count: AtomicUsize,
     inode_cache: lock_api::Mutex<R, BTreeMap<u64, Arc<dyn VfsInode>>>,
     pub mnt_info: lock_api::Mutex<R, BTreeMap<String, Arc<dyn VfsDentry>>>,
+    mount_flags: AtomicU32,
 }
 
 impl<R: VfsRawMutex + 'static> UniFsSuperBlock<R> {
@@ -87,6 +88,7 @@ impl<R: VfsRawMutex + 'static> UniFsSuperBlock<R> {
             inode_count: AtomicUsize::new(0),
             inode_cache: lock_api::Mutex::new(BTreeMap::new()),
             mnt_info: lock_api::Mutex::new(BTreeMap::new()),
+            mount_flags: AtomicU32::new(0),
         })
     }
     pub fn insert_inode(&self, inode_number: u64, inode: Arc<dyn VfsInode>) {
@@ -117,6 +119,16 @@ impl<R: VfsRawMutex + 'static> UniFsSuperBlock<R> {
     }
 }
 
+impl<R: VfsRawMutex> UniFsSuperBlock<R> {
+    /// The flags passed to mount, which decide how atime is updated
+    pub fn mount_flags(&self) -> VfsMountFlags {
+        VfsMountFlags::from_bits_truncate(self.mount_flags.load(Ordering::Relaxed))
+    }
+    pub fn set_mount_flags(&self, flags: VfsMountFlags) {
+        self.mount_flags.store(flags.bits(), Ordering::Relaxed);
+    }
+}
+
 impl<R: VfsRawMutex + 'static> VfsSuperBlock for UniFsSuperBlock<R> {
     fn sync_fs(&self, _wait: bool) -> VfsResult<()> {
         Ok(())
@@ -131,7 +143,7 @@ impl<R: VfsRawMutex + 'static> VfsSuperBlock for UniFsSuperBlock<R> {
             f_bavail: (usize::MAX / 4096) as u64,
             f_files: (usize::MAX / 4096 / 4096) as u64,
             f_ffree: (usize::MAX / 4096 / 4096) as u64
-                - self.inode_count.load(core::sync::atomic::Ordering::SeqCst) as u64,
+                - self.inode_count.load(Ordering::SeqCst) as u64,
             f_fsid: [0, 0],
             f_namelen: 255,
             f_frsize: 0,
@@ -156,4 +168,8 @@ impl<R: VfsRawMutex + 'static> VfsSuperBlock for UniFsSuperBlock<R> {
             Err(VfsError::Invalid)
         }
     }
+
+    fn mount_flags(&self) -> VfsMountFlags {
+        UniFsSuperBlock::mount_flags(self)
+    }
 }
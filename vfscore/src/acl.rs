@@ -0,0 +1,397 @@
+//! POSIX ACLs, like `acl(5)` on Linux.
+//!
+//! An ACL is stored in the [`XATTR_POSIX_ACL_ACCESS`] xattr of an inode, in the binary format of
+//! Linux. A directory can have a default ACL in [`XATTR_POSIX_ACL_DEFAULT`], which is inherited by
+//! the new inodes in it. The ACLs are only used if the filesystem is mounted with
+//! [`VfsMountFlags::MS_POSIXACL`].
+use alloc::{sync::Arc, vec::Vec};
+
+use bitflags::bitflags;
+
+use crate::{error::VfsError, inode::VfsInode, utils::VfsMountFlags, VfsResult};
+
+/// The name of the xattr of the ACL which is checked on access
+pub const XATTR_POSIX_ACL_ACCESS: &str = "system.posix_acl_access";
+/// The name of the xattr of the default ACL of a directory
+pub const XATTR_POSIX_ACL_DEFAULT: &str = "system.posix_acl_default";
+
+const POSIX_ACL_XATTR_VERSION: u32 = 2;
+const ACL_UNDEFINED_ID: u32 = u32::MAX;
+
+const ACL_USER_OBJ: u16 = 0x01;
+const ACL_USER: u16 = 0x02;
+const ACL_GROUP_OBJ: u16 = 0x04;
+const ACL_GROUP: u16 = 0x08;
+const ACL_MASK: u16 = 0x10;
+const ACL_OTHER: u16 = 0x20;
+
+bitflags! {
+    /// The permissions of an ACL entry, which are also the access to check
+    pub struct VfsAclPerm: u16 {
+        const READ = 4;
+        const WRITE = 2;
+        const EXECUTE = 1;
+    }
+}
+
+/// Whom an ACL entry applies to, they are sorted like the entries of an ACL
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum VfsAclTag {
+    /// The owner of the inode
+    UserObj,
+    User(u32),
+    /// The group of the inode
+    GroupObj,
+    Group(u32),
+    /// The most permissions which are granted by the `User`, `GroupObj` and `Group` entries
+    Mask,
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VfsAclEntry {
+    pub tag: VfsAclTag,
+    pub perm: VfsAclPerm,
+}
+
+impl VfsAclEntry {
+    pub fn new(tag: VfsAclTag, perm: VfsAclPerm) -> Self {
+        Self { tag, perm }
+    }
+}
+
+/// A valid ACL, its entries are sorted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsAcl {
+    entries: Vec<VfsAclEntry>,
+}
+
+impl VfsAcl {
+    /// Sort and check the entries
+    ///
+    /// There must be one `UserObj`, `GroupObj` and `Other` entry, a `Mask` entry if there are
+    /// `User` or `Group` entries, and no duplicated entries.
+    pub fn new(mut entries: Vec<VfsAclEntry>) -> VfsResult<Self> {
+        entries.sort_by_key(|entry| entry.tag);
+        let count = |tag| entries.iter().filter(|entry| entry.tag == tag).count();
+        let named = entries
+            .iter()
+            .any(|entry| matches!(entry.tag, VfsAclTag::User(_) | VfsAclTag::Group(_)));
+        if count(VfsAclTag::UserObj) != 1
+            || count(VfsAclTag::GroupObj) != 1
+            || count(VfsAclTag::Other) != 1
+            || count(VfsAclTag::Mask) != named as usize
+            || entries.windows(2).any(|pair| pair[0].tag == pair[1].tag)
+        {
+            return Err(VfsError::Invalid);
+        }
+        Ok(Self { entries })
+    }
+
+    /// The ACL which is equivalent to the permission bits of `mode`
+    pub fn from_mode(mode: u32) -> Self {
+        let perm = |shift: u32| VfsAclPerm::from_bits_truncate((mode >> shift) as u16 & 0o7);
+        Self {
+            entries: alloc::vec![
+                VfsAclEntry::new(VfsAclTag::UserObj, perm(6)),
+                VfsAclEntry::new(VfsAclTag::GroupObj, perm(3)),
+                VfsAclEntry::new(VfsAclTag::Other, perm(0)),
+            ],
+        }
+    }
+
+    /// Parse the value of an ACL xattr
+    pub fn from_xattr(value: &[u8]) -> VfsResult<Self> {
+        if value.len() < 4 || !(value.len() - 4).is_multiple_of(8) {
+            return Err(VfsError::Invalid);
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([value[i], value[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes(value[i..i + 4].try_into().unwrap());
+        if u32_at(0) != POSIX_ACL_XATTR_VERSION {
+            return Err(VfsError::Invalid);
+        }
+        let mut entries = Vec::new();
+        for i in (4..value.len()).step_by(8) {
+            let (tag, perm, id) = (u16_at(i), u16_at(i + 2), u32_at(i + 4));
+            let tag = match tag {
+                ACL_USER_OBJ => VfsAclTag::UserObj,
+                ACL_USER => VfsAclTag::User(id),
+                ACL_GROUP_OBJ => VfsAclTag::GroupObj,
+                ACL_GROUP => VfsAclTag::Group(id),
+                ACL_MASK => VfsAclTag::Mask,
+                ACL_OTHER => VfsAclTag::Other,
+                _ => return Err(VfsError::Invalid),
+            };
+            let perm = VfsAclPerm::from_bits(perm).ok_or(VfsError::Invalid)?;
+            entries.push(VfsAclEntry::new(tag, perm));
+        }
+        Self::new(entries)
+    }
+
+    /// Serialize the ACL to the value of an xattr
+    pub fn to_xattr(&self) -> Vec<u8> {
+        let mut value = Vec::with_capacity(4 + self.entries.len() * 8);
+        value.extend_from_slice(&POSIX_ACL_XATTR_VERSION.to_le_bytes());
+        for entry in &self.entries {
+            let (tag, id) = match entry.tag {
+                VfsAclTag::UserObj => (ACL_USER_OBJ, ACL_UNDEFINED_ID),
+                VfsAclTag::User(id) => (ACL_USER, id),
+                VfsAclTag::GroupObj => (ACL_GROUP_OBJ, ACL_UNDEFINED_ID),
+                VfsAclTag::Group(id) => (ACL_GROUP, id),
+                VfsAclTag::Mask => (ACL_MASK, ACL_UNDEFINED_ID),
+                VfsAclTag::Other => (ACL_OTHER, ACL_UNDEFINED_ID),
+            };
+            value.extend_from_slice(&tag.to_le_bytes());
+            value.extend_from_slice(&entry.perm.bits().to_le_bytes());
+            value.extend_from_slice(&id.to_le_bytes());
+        }
+        value
+    }
+
+    pub fn entries(&self) -> &[VfsAclEntry] {
+        &self.entries
+    }
+
+    /// Whether the ACL has only the entries of the permission bits
+    pub fn is_minimal(&self) -> bool {
+        self.entries.len() == 3
+    }
+
+    fn perm_of(&self, tag: VfsAclTag) -> Option<VfsAclPerm> {
+        self.entries
+            .iter()
+            .find(|entry| entry.tag == tag)
+            .map(|entry| entry.perm)
+    }
+
+    /// The permission bits which the ACL shows in the mode, the group bits are the mask if
+    /// there is one
+    pub fn mode(&self) -> u32 {
+        let bits = |tag| self.perm_of(tag).map_or(0, |perm| perm.bits() as u32);
+        let group = match self.perm_of(VfsAclTag::Mask) {
+            Some(mask) => mask.bits() as u32,
+            None => bits(VfsAclTag::GroupObj),
+        };
+        bits(VfsAclTag::UserObj) << 6 | group << 3 | bits(VfsAclTag::Other)
+    }
+
+    /// Whether the ACL grants `want` to a user, like `posix_acl_permission` on Linux
+    ///
+    /// `owner` and `group` are the owner and the group of the inode, and `groups` are the groups
+    /// of the user.
+    pub fn permission(
+        &self,
+        owner: u32,
+        group: u32,
+        uid: u32,
+        groups: &[u32],
+        want: VfsAclPerm,
+    ) -> bool {
+        let masked = |perm: VfsAclPerm| {
+            let mask = self.perm_of(VfsAclTag::Mask).unwrap_or(VfsAclPerm::all());
+            (perm & mask).contains(want)
+        };
+        let member = |tag| match tag {
+            VfsAclTag::GroupObj => groups.contains(&group),
+            VfsAclTag::Group(id) => groups.contains(&id),
+            _ => false,
+        };
+        let mut in_group = false;
+        for entry in &self.entries {
+            match entry.tag {
+                VfsAclTag::UserObj if uid == owner => return entry.perm.contains(want),
+                VfsAclTag::User(id) if uid == id => return masked(entry.perm),
+                tag if member(tag) => {
+                    in_group = true;
+                    if masked(entry.perm) {
+                        return true;
+                    }
+                }
+                // a user in a matching group doesn't fall back to the other entry
+                VfsAclTag::Other => return !in_group && entry.perm.contains(want),
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Apply the mode of a new inode to the inherited ACL, return the mode of the inode
+    ///
+    /// Like `posix_acl_create_masq` on Linux, the permissions of the ACL are limited by `mode` and
+    /// the permission bits of the mode are limited by the ACL. The other bits of `mode` are kept.
+    pub fn create_masq(&mut self, mode: u32) -> u32 {
+        let mut mode = mode;
+        let has_mask = self.perm_of(VfsAclTag::Mask).is_some();
+        for entry in self.entries.iter_mut() {
+            let shift = match entry.tag {
+                VfsAclTag::UserObj => 6,
+                VfsAclTag::GroupObj if !has_mask => 3,
+                VfsAclTag::Mask => 3,
+                VfsAclTag::Other => 0,
+                _ => continue,
+            };
+            entry.perm &= VfsAclPerm::from_bits_truncate((mode >> shift) as u16 & 0o7);
+            mode &= (entry.perm.bits() as u32) << shift | !(0o7 << shift);
+        }
+        mode
+    }
+
+    /// Update the entries which are shown in the mode after the mode is changed, like
+    /// `posix_acl_chmod` on Linux
+    pub fn chmod(&mut self, mode: u32) {
+        let has_mask = self.perm_of(VfsAclTag::Mask).is_some();
+        for entry in self.entries.iter_mut() {
+            let shift = match entry.tag {
+                VfsAclTag::UserObj => 6,
+                VfsAclTag::GroupObj if !has_mask => 3,
+                VfsAclTag::Mask => 3,
+                VfsAclTag::Other => 0,
+                _ => continue,
+            };
+            entry.perm = VfsAclPerm::from_bits_truncate((mode >> shift) as u16 & 0o7);
+        }
+    }
+}
+
+/// Whether the filesystem of the inode is mounted with [`VfsMountFlags::MS_POSIXACL`]
+pub fn acl_enabled(inode: &Arc<dyn VfsInode>) -> bool {
+    inode
+        .get_super_block()
+        .map(|sb| sb.mount_flags().contains(VfsMountFlags::MS_POSIXACL))
+        .unwrap_or(false)
+}
+
+/// Read the ACL in the xattr `name` of the inode, none if it has no such ACL or the ACLs are not
+/// enabled
+pub fn get_acl(inode: &Arc<dyn VfsInode>, name: &str) -> VfsResult<Option<VfsAcl>> {
+    if !acl_enabled(inode) {
+        return Ok(None);
+    }
+    match inode.get_xattr(name) {
+        Ok(value) => VfsAcl::from_xattr(&value).map(Some),
+        Err(VfsError::ENODATA) | Err(VfsError::NoSys) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+
+    fn acl(entries: &[(VfsAclTag, u16)]) -> VfsResult<VfsAcl> {
+        let entries = entries
+            .iter()
+            .map(|(tag, perm)| VfsAclEntry::new(*tag, VfsAclPerm::from_bits_truncate(*perm)))
+            .collect();
+        VfsAcl::new(entries)
+    }
+
+    #[test]
+    fn test_xattr() {
+        let acl = acl(&[
+            (VfsAclTag::Other, 0),
+            (VfsAclTag::User(1000), 6),
+            (VfsAclTag::UserObj, 7),
+            (VfsAclTag::GroupObj, 5),
+            (VfsAclTag::Mask, 6),
+        ])
+        .unwrap();
+        let value = acl.to_xattr();
+        #[rustfmt::skip]
+        assert_eq!(value, vec![
+            2, 0, 0, 0,
+            1, 0, 7, 0, 0xff, 0xff, 0xff, 0xff,
+            2, 0, 6, 0, 0xe8, 3, 0, 0,
+            4, 0, 5, 0, 0xff, 0xff, 0xff, 0xff,
+            0x10, 0, 6, 0, 0xff, 0xff, 0xff, 0xff,
+            0x20, 0, 0, 0, 0xff, 0xff, 0xff, 0xff,
+        ]);
+        assert_eq!(VfsAcl::from_xattr(&value), Ok(acl.clone()));
+        assert_eq!(acl.mode(), 0o760);
+        assert!(!acl.is_minimal());
+
+        assert_eq!(VfsAcl::from_xattr(&value[..10]), Err(VfsError::Invalid));
+        let mut bad = value.clone();
+        bad[0] = 1;
+        assert_eq!(VfsAcl::from_xattr(&bad), Err(VfsError::Invalid));
+        let mut bad = value;
+        bad[6] = 8;
+        assert_eq!(VfsAcl::from_xattr(&bad), Err(VfsError::Invalid));
+    }
+
+    #[test]
+    fn test_valid() {
+        let minimal = [
+            (VfsAclTag::UserObj, 6),
+            (VfsAclTag::GroupObj, 4),
+            (VfsAclTag::Other, 4),
+        ];
+        assert_eq!(acl(&minimal), Ok(VfsAcl::from_mode(0o644)));
+        assert!(acl(&minimal[..2]).is_err());
+        let no_mask = [minimal[0], minimal[1], minimal[2], (VfsAclTag::Group(5), 7)];
+        assert_eq!(acl(&no_mask), Err(VfsError::Invalid));
+        let mut dup = no_mask.to_vec();
+        dup.push((VfsAclTag::Mask, 7));
+        assert!(acl(&dup).is_ok());
+        dup.push((VfsAclTag::Group(5), 4));
+        assert_eq!(acl(&dup), Err(VfsError::Invalid));
+    }
+
+    #[test]
+    fn test_permission() {
+        let acl = acl(&[
+            (VfsAclTag::UserObj, 6),
+            (VfsAclTag::User(1000), 7),
+            (VfsAclTag::GroupObj, 4),
+            (VfsAclTag::Group(50), 2),
+            (VfsAclTag::Mask, 6),
+            (VfsAclTag::Other, 4),
+        ])
+        .unwrap();
+        let rw = VfsAclPerm::READ | VfsAclPerm::WRITE;
+        let check = |uid, groups: &[u32], want| acl.permission(1, 10, uid, groups, want);
+        assert!(check(1, &[], rw));
+        assert!(!check(1, &[], VfsAclPerm::EXECUTE));
+        // the named user is limited by the mask
+        assert!(check(1000, &[], rw));
+        assert!(!check(1000, &[], VfsAclPerm::EXECUTE));
+        assert!(check(2, &[10], VfsAclPerm::READ));
+        assert!(check(2, &[50], VfsAclPerm::WRITE));
+        // the access must be granted by one of the groups
+        assert!(check(2, &[10, 50], VfsAclPerm::WRITE));
+        assert!(!check(2, &[10, 50], rw));
+        // a matching group which doesn't grant the access denies it
+        assert!(!check(2, &[50], VfsAclPerm::READ));
+        assert!(check(2, &[], VfsAclPerm::READ));
+        assert!(!check(2, &[], VfsAclPerm::WRITE));
+    }
+
+    #[test]
+    fn test_create_masq() {
+        let mut default = acl(&[
+            (VfsAclTag::UserObj, 7),
+            (VfsAclTag::User(1000), 7),
+            (VfsAclTag::GroupObj, 5),
+            (VfsAclTag::Mask, 7),
+            (VfsAclTag::Other, 5),
+        ])
+        .unwrap();
+        assert_eq!(default.create_masq(0o100666), 0o100664);
+        assert_eq!(default.mode(), 0o664);
+        assert_eq!(
+            default.entries()[1],
+            VfsAclEntry::new(VfsAclTag::User(1000), VfsAclPerm::all())
+        );
+        default.chmod(0o100640);
+        assert_eq!(default.mode(), 0o640);
+        assert_eq!(
+            default.entries()[2].perm,
+            VfsAclPerm::READ | VfsAclPerm::EXECUTE
+        );
+        let mut minimal = VfsAcl::from_mode(0o750);
+        assert_eq!(minimal.create_masq(0o2777), 0o2750);
+        assert_eq!(minimal, VfsAcl::from_mode(0o750));
+    }
+}
@@ -0,0 +1,136 @@
+//! In-kernel copy between files: copy_file_range(2), sendfile(2) and splice(2).
+//!
+//! [`copy_file_range`] first asks the source inode for a fast path with
+//! [`VfsInode::copy_file_range_to`], and falls back to [`copy_range`], which moves the data
+//! through a kernel buffer with `read_at`/`write_at`.
+use alloc::vec;
+
+use crate::{error::VfsError, file::VfsFile, inode::VfsInode, utils::VfsNodeType, VfsResult};
+
+/// The default chunk size of [`copy_range`]
+pub const VFS_COPY_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Copy up to `len` bytes from `src` at `src_offset` to `dst` at `dst_offset`, `chunk_size` bytes
+/// at a time.
+///
+/// This works for any files, so it is used by sendfile(2) and splice(2). The copy stops at the end
+/// of `src`, or when `dst` accepts less than a chunk, like a full pipe. Return the number of bytes
+/// copied. If an error occurs after some bytes have been copied, the progress is returned and the
+/// error is left for the next call.
+pub fn copy_range(
+    src: &dyn VfsFile,
+    src_offset: u64,
+    dst: &dyn VfsFile,
+    dst_offset: u64,
+    len: usize,
+    chunk_size: usize,
+) -> VfsResult<usize> {
+    if chunk_size == 0 {
+        return Err(VfsError::Invalid);
+    }
+    let mut buf = vec![0; chunk_size.min(len)];
+    let mut copied = 0;
+    while copied < len {
+        let want = (len - copied).min(buf.len());
+        let read = match src.read_at(src_offset + copied as u64, &mut buf[..want]) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if copied == 0 => return Err(e),
+            Err(_) => break,
+        };
+        let mut written = 0;
+        while written < read {
+            match dst.write_at(dst_offset + (copied + written) as u64, &buf[written..read]) {
+                Ok(0) => break,
+                Ok(n) => written += n,
+                Err(e) if copied + written == 0 => return Err(e),
+                Err(_) => break,
+            }
+        }
+        copied += written;
+        if written < read {
+            break;
+        }
+    }
+    Ok(copied)
+}
+
+/// Copy up to `len` bytes between two regular files, this is the copy_file_range(2) system call.
+///
+/// The fast path of the filesystem is used if it has one, otherwise the data is copied with
+/// [`copy_range`]. Copying between overlapping ranges of the same file is invalid.
+pub fn copy_file_range(
+    src: &dyn VfsInode,
+    src_offset: u64,
+    dst: &dyn VfsInode,
+    dst_offset: u64,
+    len: usize,
+    chunk_size: usize,
+) -> VfsResult<usize> {
+    for ty in [src.inode_type(), dst.inode_type()] {
+        match ty {
+            VfsNodeType::File => {}
+            VfsNodeType::Dir => return Err(VfsError::IsDir),
+            _ => return Err(VfsError::Invalid),
+        }
+    }
+    let same = core::ptr::addr_eq(src as *const dyn VfsInode, dst as *const dyn VfsInode);
+    if same
+        && src_offset < dst_offset.saturating_add(len as u64)
+        && dst_offset < src_offset.saturating_add(len as u64)
+    {
+        return Err(VfsError::Invalid);
+    }
+    if len == 0 {
+        return Ok(0);
+    }
+    match src.copy_file_range_to(src_offset, dst, dst_offset, len) {
+        Err(VfsError::EOPNOTSUPP) | Err(VfsError::NoSys) => {}
+        res => return res,
+    }
+    copy_range(src, src_offset, dst, dst_offset, len, chunk_size)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use spin::Mutex;
+
+    use super::*;
+
+    struct MemFile(Mutex<Vec<u8>>, usize);
+    impl VfsFile for MemFile {
+        fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+            let data = self.0.lock();
+            let offset = (offset as usize).min(data.len());
+            let len = (data.len() - offset).min(buf.len());
+            buf[..len].copy_from_slice(&data[offset..offset + len]);
+            Ok(len)
+        }
+        /// Accept at most `self.1` bytes in total, like a pipe
+        fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+            let mut data = self.0.lock();
+            let offset = offset as usize;
+            let len = buf.len().min(self.1.saturating_sub(offset));
+            if data.len() < offset + len {
+                data.resize(offset + len, 0);
+            }
+            data[offset..offset + len].copy_from_slice(&buf[..len]);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn test_copy_range() {
+        let src = MemFile(Mutex::new((0..100).collect()), usize::MAX);
+        let dst = MemFile(Mutex::new(Vec::new()), usize::MAX);
+        assert_eq!(copy_range(&src, 10, &dst, 0, 1000, 7), Ok(90));
+        assert_eq!(dst.0.lock()[..3], [10, 11, 12]);
+
+        // the destination is full after 50 bytes
+        let dst = MemFile(Mutex::new(Vec::new()), 50);
+        assert_eq!(copy_range(&src, 0, &dst, 0, 100, 16), Ok(50));
+        assert_eq!(copy_range(&src, 0, &dst, 0, 100, 0), Err(VfsError::Invalid));
+    }
+}
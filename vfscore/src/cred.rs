@@ -0,0 +1,84 @@
+//! The credentials of the caller, like `struct cred` on Linux.
+//!
+//! The owner of the new inodes and the access to the existing ones are decided by the fsuid, the
+//! fsgid and the supplementary groups. The checks which root bypasses on Linux are bypassed by
+//! the [`VfsCapabilities`] instead of the uid.
+use alloc::vec::Vec;
+
+use bitflags::bitflags;
+
+bitflags! {
+    /// The capabilities which are checked by the VFS, with the bits of Linux
+    pub struct VfsCapabilities: u64 {
+        /// Change the owner and the group of any file
+        const CHOWN = 1 << 0;
+        /// Bypass the permission checks of files and directories
+        const DAC_OVERRIDE = 1 << 1;
+        /// Bypass the checks which require the caller to own the file
+        const FOWNER = 1 << 3;
+        /// Keep the setuid and setgid bits when a file is changed
+        const FSETID = 1 << 4;
+        /// Mount and unmount filesystems
+        const SYS_ADMIN = 1 << 21;
+        /// Create device files
+        const MKNOD = 1 << 27;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsCred {
+    pub uid: u32,
+    pub gid: u32,
+    /// The uid which owns the new inodes and is checked on access
+    pub fsuid: u32,
+    /// The gid which owns the new inodes and is checked on access
+    pub fsgid: u32,
+    /// The supplementary groups
+    pub groups: Vec<u32>,
+    /// The effective capabilities
+    pub caps: VfsCapabilities,
+}
+
+impl VfsCred {
+    /// Root with all capabilities
+    pub fn root() -> Self {
+        Self::user(0, 0).with_caps(VfsCapabilities::all())
+    }
+    /// A user without supplementary groups and capabilities
+    pub fn user(uid: u32, gid: u32) -> Self {
+        Self {
+            uid,
+            gid,
+            fsuid: uid,
+            fsgid: gid,
+            groups: Vec::new(),
+            caps: VfsCapabilities::empty(),
+        }
+    }
+    pub fn with_fs_ids(mut self, fsuid: u32, fsgid: u32) -> Self {
+        self.fsuid = fsuid;
+        self.fsgid = fsgid;
+        self
+    }
+    pub fn with_groups(mut self, groups: Vec<u32>) -> Self {
+        self.groups = groups;
+        self
+    }
+    pub fn with_caps(mut self, caps: VfsCapabilities) -> Self {
+        self.caps = caps;
+        self
+    }
+    pub fn capable(&self, cap: VfsCapabilities) -> bool {
+        self.caps.contains(cap)
+    }
+    /// Whether `gid` is the fsgid or a supplementary group, like `in_group_p` on Linux
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.fsgid == gid || self.groups.contains(&gid)
+    }
+    /// The fsgid and the supplementary groups
+    pub fn all_groups(&self) -> Vec<u32> {
+        let mut groups = self.groups.clone();
+        groups.push(self.fsgid);
+        groups
+    }
+}
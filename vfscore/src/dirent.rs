@@ -0,0 +1,102 @@
+//! Serialization of directory entries into `struct linux_dirent64` for getdents64(2).
+//!
+//! ```c
+//! struct linux_dirent64 {
+//!     u64 d_ino;
+//!     s64 d_off;
+//!     unsigned short d_reclen;
+//!     unsigned char d_type;
+//!     char d_name[];
+//! };
+//! ```
+use crate::{error::VfsError, file::VfsFile, utils::VfsDirEntry, VfsResult};
+
+/// The size of the fields before `d_name`
+const DIRENT64_HEADER_SIZE: usize = 19;
+
+/// The length of the record of `name`, with the terminating null byte and padded to 8 bytes
+pub fn dirent64_reclen(name: &str) -> usize {
+    (DIRENT64_HEADER_SIZE + name.len() + 1).next_multiple_of(8)
+}
+
+/// Write one record at the start of `buf`.
+///
+/// Return the length of the record, or none if `buf` is too small.
+pub fn write_dirent64(buf: &mut [u8], entry: &VfsDirEntry) -> Option<usize> {
+    let reclen = dirent64_reclen(&entry.name);
+    let record = buf.get_mut(..reclen)?;
+    record[0..8].copy_from_slice(&entry.ino.to_ne_bytes());
+    record[8..16].copy_from_slice(&entry.off.to_ne_bytes());
+    record[16..18].copy_from_slice(&(reclen as u16).to_ne_bytes());
+    // VfsNodeType uses the values of DT_*
+    record[18] = entry.ty as u8;
+    let name = &mut record[DIRENT64_HEADER_SIZE..];
+    name[..entry.name.len()].copy_from_slice(entry.name.as_bytes());
+    name[entry.name.len()..].fill(0);
+    Some(reclen)
+}
+
+/// Fill `buf` with the entries of the directory `dir`, starting from the cookie `start`.
+///
+/// Return the number of bytes written and the cookie to resume from, so getdents64(2) is
+/// `getdents64(dir, pos, buf)` followed by saving the new position. It stops before the first
+/// entry which doesn't fit. If not even one entry fits, [`VfsError::Invalid`] is returned like
+/// Linux. An error after some entries are written is left for the next call.
+pub fn getdents64(dir: &dyn VfsFile, start: u64, buf: &mut [u8]) -> VfsResult<(usize, u64)> {
+    let mut cookie = start;
+    let mut written = 0;
+    loop {
+        let entry = match dir.readdir(cookie) {
+            Ok(Some(entry)) => entry,
+            Ok(None) => break,
+            Err(e) if written == 0 => return Err(e),
+            Err(_) => break,
+        };
+        match write_dirent64(&mut buf[written..], &entry) {
+            Some(reclen) => written += reclen,
+            None if written == 0 => return Err(VfsError::Invalid),
+            None => break,
+        }
+        cookie = entry.off;
+    }
+    Ok((written, cookie))
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{string::ToString, vec::Vec};
+
+    use super::*;
+    use crate::utils::VfsNodeType;
+
+    struct FakeDir(Vec<&'static str>);
+    impl VfsFile for FakeDir {
+        fn readdir(&self, cookie: u64) -> VfsResult<Option<VfsDirEntry>> {
+            Ok(self.0.get(cookie as usize).map(|name| VfsDirEntry {
+                ino: cookie + 1,
+                ty: VfsNodeType::File,
+                name: name.to_string(),
+                off: cookie + 1,
+            }))
+        }
+    }
+
+    #[test]
+    fn test_getdents64() {
+        let dir = FakeDir(Vec::from([".", "..", "a_long_file_name"]));
+        assert_eq!(dirent64_reclen("."), 24);
+        assert_eq!(dirent64_reclen("a_long_file_name"), 40);
+
+        let mut buf = [0xff; 60];
+        assert_eq!(getdents64(&dir, 0, &mut buf), Ok((48, 2)));
+        assert_eq!(u64::from_ne_bytes(buf[8..16].try_into().unwrap()), 1);
+        assert_eq!(u16::from_ne_bytes(buf[16..18].try_into().unwrap()), 24);
+        assert_eq!(buf[18], VfsNodeType::File as u8);
+        assert_eq!(&buf[19..21], b".\0");
+
+        assert_eq!(getdents64(&dir, 2, &mut buf), Ok((40, 3)));
+        assert_eq!(&buf[19..36], b"a_long_file_name\0");
+        assert_eq!(getdents64(&dir, 3, &mut buf), Ok((0, 3)));
+        assert_eq!(getdents64(&dir, 2, &mut buf[..30]), Err(VfsError::Invalid));
+    }
+}
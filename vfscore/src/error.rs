@@ -13,6 +13,8 @@ pub enum VfsError {
     EINTR = 4,
     /// EIO 输入输出错误
     IoError = 5,
+    /// ENXIO 无此设备或地址
+    ENXIO = 6,
     /// try again
     EAGAIN = 11,
     /// ENOMEM 内存不足
@@ -23,6 +25,8 @@ pub enum VfsError {
     EBUSY = 16,
     /// EEXIST 文件已存在
     EExist = 17,
+    /// EXDEV 跨设备链接
+    EXDEV = 18,
     /// ENOTDIR 不是目录
     NotDir = 20,
     /// EINVAL 无效参数
@@ -39,12 +43,20 @@ pub enum VfsError {
     ESPIPE = 29,
     /// Broken pipe
     EPIPE = 32,
+    /// EDEADLK 资源死锁
+    EDEADLK = 35,
     /// ENAMETOOLONG 名称太长
     NameTooLong = 36,
     /// ENOSYS 不支持的系统调用
     NoSys = 38,
     /// ENOTEMPTY  目录非空
     NotEmpty = 39,
+    /// ELOOP 符号链接层数过多
+    ELOOP = 40,
+    /// ENODATA 没有可用的数据
+    ENODATA = 61,
+    /// EOPNOTSUPP 不支持的操作
+    EOPNOTSUPP = 95,
 }
 
 impl Display for VfsError {
@@ -74,6 +86,9 @@ impl Display for VfsError {
             VfsError::EExist => {
                 write!(f, "File exists")
             }
+            VfsError::EXDEV => {
+                write!(f, "Invalid cross-device link")
+            }
             VfsError::NotDir => {
                 write!(f, "Not a directory")
             }
@@ -110,6 +125,21 @@ impl Display for VfsError {
             VfsError::EBUSY => {
                 write!(f, "Device or resource busy")
             }
+            VfsError::EDEADLK => {
+                write!(f, "Resource deadlock avoided")
+            }
+            VfsError::ENXIO => {
+                write!(f, "No such device or address")
+            }
+            VfsError::ELOOP => {
+                write!(f, "Too many levels of symbolic links")
+            }
+            VfsError::ENODATA => {
+                write!(f, "No data available")
+            }
+            VfsError::EOPNOTSUPP => {
+                write!(f, "Operation not supported")
+            }
         }
     }
 }
@@ -129,11 +159,13 @@ impl From<i32> for VfsError {
             2 => VfsError::NoEntry,
             4 => VfsError::EINTR,
             5 => VfsError::IoError,
+            6 => VfsError::ENXIO,
             11 => VfsError::EAGAIN,
             12 => VfsError::NoMem,
             13 => VfsError::Access,
             16 => VfsError::EBUSY,
             17 => VfsError::EExist,
+            18 => VfsError::EXDEV,
             20 => VfsError::NotDir,
             22 => VfsError::Invalid,
             19 => VfsError::NoDev,
@@ -142,9 +174,13 @@ impl From<i32> for VfsError {
             28 => VfsError::NoSpace,
             29 => VfsError::ESPIPE,
             32 => VfsError::EPIPE,
+            35 => VfsError::EDEADLK,
             36 => VfsError::NameTooLong,
             38 => VfsError::NoSys,
             39 => VfsError::NotEmpty,
+            40 => VfsError::ELOOP,
+            61 => VfsError::ENODATA,
+            95 => VfsError::EOPNOTSUPP,
             _ => VfsError::Invalid,
         }
     }
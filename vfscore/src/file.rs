@@ -1,7 +1,12 @@
+use alloc::sync::Arc;
+use core::task::Waker;
+
 use downcast_rs::{impl_downcast, DowncastSync};
 
 use crate::{
     error::VfsError,
+    lock::{VfsFileLock, VfsLockOwner, VfsLockType},
+    page::VfsFrame,
     utils::{VfsDirEntry, VfsPollEvents},
     VfsResult,
 };
@@ -13,11 +18,53 @@ pub trait VfsFile: Send + Sync + DowncastSync {
     fn write_at(&self, _offset: u64, _buf: &[u8]) -> VfsResult<usize> {
         Err(VfsError::NoSys)
     }
+    /// Read into several buffers from `offset`, this is called by the preadv(2) system call.
+    ///
+    /// The default reads each buffer with `read_at`, a filesystem should override it if it can
+    /// read all buffers atomically.
+    fn read_vectored_at(&self, offset: u64, bufs: &mut [&mut [u8]]) -> VfsResult<usize> {
+        let mut count = 0;
+        for buf in bufs.iter_mut() {
+            let len = match self.read_at(offset + count as u64, buf) {
+                Ok(len) => len,
+                Err(e) if count == 0 => return Err(e),
+                Err(_) => break,
+            };
+            count += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(count)
+    }
+    /// Write several buffers from `offset`, this is called by the pwritev(2) system call.
+    ///
+    /// The default writes each buffer with `write_at`, a filesystem should override it if it can
+    /// write all buffers atomically.
+    fn write_vectored_at(&self, offset: u64, bufs: &[&[u8]]) -> VfsResult<usize> {
+        let mut count = 0;
+        for buf in bufs {
+            let len = match self.write_at(offset + count as u64, buf) {
+                Ok(len) => len,
+                Err(e) if count == 0 => return Err(e),
+                Err(_) => break,
+            };
+            count += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(count)
+    }
     /// Read directory entries. This is called by the getdents(2) system call.
     ///
-    /// For every call, this function will return an valid entry, or an error. If
-    /// it read to the end of directory, it will return an empty entry.
-    fn readdir(&self, _start_index: usize) -> VfsResult<Option<VfsDirEntry>> {
+    /// For every call, this function will return the first entry at or after `cookie`, or an
+    /// error. If it read to the end of directory, it will return an empty entry. The reading
+    /// starts from cookie 0, and [`VfsDirEntry::off`] is the cookie to read the next entry.
+    ///
+    /// A cookie must stay valid when the directory is modified, like telldir(3) and seekdir(3),
+    /// so that no entry is skipped or returned twice because others are removed.
+    fn readdir(&self, _cookie: u64) -> VfsResult<Option<VfsDirEntry>> {
         Err(VfsError::NoSys)
     }
     fn poll(&self, event: VfsPollEvents) -> VfsResult<VfsPollEvents> {
@@ -30,6 +77,14 @@ pub trait VfsFile: Send + Sync + DowncastSync {
         }
         Ok(res)
     }
+    /// Poll the file and register a waiter if it is not ready.
+    ///
+    /// If none of `event` is ready, the file should keep `waker` (see [`crate::wait::VfsWaitQueue`])
+    /// and wake it when the readiness changes. A file which is always ready, like the default,
+    /// doesn't need to keep it.
+    fn poll_register(&self, event: VfsPollEvents, _waker: &Waker) -> VfsResult<VfsPollEvents> {
+        self.poll(event)
+    }
     fn ioctl(&self, _cmd: u32, _arg: usize) -> VfsResult<usize> {
         Err(VfsError::NoSys)
     }
@@ -41,6 +96,50 @@ pub trait VfsFile: Send + Sync + DowncastSync {
     fn fsync(&self) -> VfsResult<()> {
         Ok(())
     }
+    /// Get the page which holds the data at `offset`, used by mmap.
+    ///
+    /// `offset` must be aligned to [`crate::page::VFS_PAGE_SIZE`]. The page is created if it is
+    /// not present, the same page is returned until it is dropped from the file, so all
+    /// MAP_SHARED mappings and `read_at`/`write_at` see the same data.
+    fn get_page(&self, _offset: u64) -> VfsResult<Arc<dyn VfsFrame>> {
+        Err(VfsError::NoSys)
+    }
+    /// Mark the page at `offset` dirty, after it has been written through a MAP_SHARED mapping.
+    fn set_page_dirty(&self, _offset: u64) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Write the dirty pages in `[offset, offset + len)` back to the storage, `len` 0 means to the
+    /// end of file. This is called by msync(2) and munmap(2).
+    fn writeback(&self, _offset: u64, _len: u64) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Find a POSIX lock which conflicts with `lock`. This is called by fcntl(F_GETLK).
+    fn get_lock(&self, _lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+        Err(VfsError::NoSys)
+    }
+    /// Acquire or release a POSIX lock without waiting. This is called by fcntl(F_SETLK).
+    ///
+    /// If the lock is held by another owner, it should return [`VfsError::EAGAIN`].
+    /// fcntl(F_SETLKW) is implemented by [`crate::lock::VfsLockManager`].
+    fn set_lock(&self, _lock: VfsFileLock) -> VfsResult<()> {
+        Err(VfsError::NoSys)
+    }
+    /// Acquire or release a flock lock without waiting. This is called by the flock(2) system call.
+    ///
+    /// If the lock is held by another owner, it should return [`VfsError::EAGAIN`].
+    fn flock(&self, _owner: VfsLockOwner, _ty: VfsLockType) -> VfsResult<()> {
+        Err(VfsError::NoSys)
+    }
+    /// Release the POSIX locks held by the process `pid`. This should be called every time the
+    /// process closes a file descriptor of the file.
+    fn release_posix(&self, _pid: VfsLockOwner) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Release the flock lock held by the open file description `ofd`. This should be called when
+    /// the last file descriptor of the description is closed.
+    fn release_flock(&self, _ofd: VfsLockOwner) -> VfsResult<()> {
+        Ok(())
+    }
 }
 
 impl_downcast!(sync VfsFile);
@@ -0,0 +1,330 @@
+//! Expand shell glob patterns, see [`crate::path::VfsPath::glob`].
+//!
+//! A pattern is split into components by `/`. A component can have `*`, `?`, `[...]` and `\`
+//! escapes, and a component which is exactly `**` matches zero or more directories. The
+//! wildcards don't match a leading `.` unless the component starts with a literal `.`, like
+//! bash without `dotglob`.
+use alloc::{string::String, vec, vec::Vec};
+use core::{cmp::Ordering, mem};
+
+use crate::{error::VfsError, path::VfsPath, utils::VfsNodeType, VfsResult};
+
+/// A component of the pattern
+enum GlobPart {
+    /// A name without wildcards, which is looked up instead of listing the directory
+    Literal(String),
+    /// A name with wildcards
+    Pattern(Vec<char>),
+    /// `**`
+    AnyDirs,
+}
+
+/// The iterator of [`VfsPath::glob`]
+///
+/// The directories are listed lazily when the iterator gets to them, and the paths are yielded
+/// in sorted order, compared component by component. The matches below a `**` are collected
+/// before the first of them is yielded, because `**` matches at every depth.
+pub struct VfsGlob {
+    parts: Vec<GlobPart>,
+    /// Only match directories, the pattern ends with `/`
+    dir_only: bool,
+    /// The paths which match the first `n` parts
+    stack: Vec<(VfsPath, usize)>,
+    /// The collected matches of a `**`, in reverse order
+    ready: Vec<VfsResult<VfsPath>>,
+    /// A `**` is being collected, the nested ones are expanded in place
+    collecting: bool,
+}
+
+impl VfsGlob {
+    pub(crate) fn new(start: VfsPath, pattern: &str) -> VfsResult<Self> {
+        let mut parts = Vec::new();
+        for part in pattern.split('/').filter(|part| !part.is_empty()) {
+            let part = if part == "**" {
+                GlobPart::AnyDirs
+            } else if is_literal(part) {
+                GlobPart::Literal(unescape(part))
+            } else {
+                let part = part.chars().collect::<Vec<_>>();
+                check_pattern(&part)?;
+                GlobPart::Pattern(part)
+            };
+            parts.push(part);
+        }
+        Ok(Self {
+            parts,
+            dir_only: pattern.ends_with('/'),
+            stack: vec![(start, 0)],
+            ready: Vec::new(),
+            collecting: false,
+        })
+    }
+
+    /// Match everything below the `**` at `path`, and sort the matches
+    fn collect_any_dirs(&mut self, path: VfsPath, index: usize) {
+        let stack = mem::replace(&mut self.stack, vec![(path, index)]);
+        self.collecting = true;
+        let mut matches = self.by_ref().collect::<Vec<_>>();
+        self.collecting = false;
+        self.stack = stack;
+        // the errors are yielded first
+        matches.sort_by(|a, b| match (a, b) {
+            (Ok(a), Ok(b)) => cmp_paths(a, b),
+            (Ok(_), Err(_)) => Ordering::Greater,
+            (Err(_), Ok(_)) => Ordering::Less,
+            (Err(_), Err(_)) => Ordering::Equal,
+        });
+        matches.reverse();
+        self.ready = matches;
+    }
+
+    /// Push the children of `path` which match `pattern`, in sorted order
+    fn expand(&mut self, path: &VfsPath, index: usize, dirs: bool) -> VfsResult<()> {
+        let names = match list_dir(path, dirs) {
+            Ok(names) => names,
+            // there is nothing to match below a file
+            Err(VfsError::NotDir) | Err(VfsError::NoEntry) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let mut children = Vec::new();
+        for name in names {
+            let matched = match &self.parts[index] {
+                GlobPart::Pattern(pattern) => {
+                    (!name.starts_with('.') || pattern[0] == '.') && match_name(pattern, &name)
+                }
+                // `**` doesn't descend into hidden directories
+                _ => !name.starts_with('.'),
+            };
+            if matched {
+                children.push(name);
+            }
+        }
+        children.sort();
+        let next = match self.parts[index] {
+            GlobPart::AnyDirs => index,
+            _ => index + 1,
+        };
+        for name in children.iter().rev() {
+            self.stack.push((path.join(name)?, next));
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for VfsGlob {
+    type Item = VfsResult<VfsPath>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(res) = self.ready.pop() {
+                return Some(res);
+            }
+            let (path, index) = self.stack.pop()?;
+            if index == self.parts.len() {
+                match path.resolve(true) {
+                    Ok(dentry) => {
+                        let is_dir = dentry.inode().map(|inode| inode.inode_type().is_dir());
+                        match is_dir {
+                            Ok(is_dir) if is_dir || !self.dir_only => return Some(Ok(path)),
+                            Ok(_) => {}
+                            Err(e) => return Some(Err(e)),
+                        }
+                    }
+                    Err(VfsError::NoEntry) | Err(VfsError::NotDir) => {}
+                    Err(e) => return Some(Err(e)),
+                }
+                continue;
+            }
+            let res = match &self.parts[index] {
+                GlobPart::Literal(name) => path.join(name).map(|child| {
+                    self.stack.push((child, index + 1));
+                }),
+                GlobPart::Pattern(_) => self.expand(&path, index, false),
+                GlobPart::AnyDirs if !self.collecting => {
+                    self.collect_any_dirs(path, index);
+                    Ok(())
+                }
+                GlobPart::AnyDirs => {
+                    // the subdirectories are matched after `path` itself
+                    let res = self.expand(&path, index, true);
+                    self.stack.push((path, index + 1));
+                    res
+                }
+            };
+            if let Err(e) = res {
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// Compare two paths component by component, so `a/b/x` comes before `a/x`
+fn cmp_paths(a: &VfsPath, b: &VfsPath) -> Ordering {
+    let a = a.as_str().split('/').filter(|name| !name.is_empty());
+    let b = b.as_str().split('/').filter(|name| !name.is_empty());
+    a.cmp(b)
+}
+
+/// List the names in the directory, or only the subdirectories if `dirs` is true
+fn list_dir(path: &VfsPath, dirs: bool) -> VfsResult<Vec<String>> {
+    let inode = path.resolve(true)?.inode()?;
+    if !inode.inode_type().is_dir() {
+        return Err(VfsError::NotDir);
+    }
+    let mut names = Vec::new();
+    let mut cookie = 0;
+    while let Some(entry) = inode.readdir(cookie)? {
+        cookie = entry.off;
+        if entry.name == "." || entry.name == ".." {
+            continue;
+        }
+        if !dirs || entry.ty == VfsNodeType::Dir {
+            names.push(entry.name);
+        }
+    }
+    Ok(names)
+}
+
+fn is_literal(part: &str) -> bool {
+    let mut escaped = false;
+    for c in part.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '*' | '?' | '[' => return false,
+            _ => {}
+        }
+    }
+    true
+}
+
+fn unescape(part: &str) -> String {
+    let mut res = String::new();
+    let mut chars = part.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => res.extend(chars.next()),
+            _ => res.push(c),
+        }
+    }
+    res
+}
+
+/// Check that every `[` is closed
+fn check_pattern(pattern: &[char]) -> VfsResult<()> {
+    let mut i = 0;
+    while i < pattern.len() {
+        match pattern[i] {
+            '\\' => i += 2,
+            '[' => i = match_class(pattern, i, ' ').ok_or(VfsError::Invalid)?.1,
+            _ => i += 1,
+        }
+    }
+    Ok(())
+}
+
+/// Match the class which starts at `pattern[start]`, return whether `c` is in it and the index
+/// after the class, or None if the class is not closed
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut low = *pattern.get(i)?;
+        if low == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        first = false;
+        if low == '\\' {
+            i += 1;
+            low = *pattern.get(i)?;
+        }
+        i += 1;
+        let mut high = low;
+        if pattern.get(i) == Some(&'-') && pattern.get(i + 1).is_some_and(|c| *c != ']') {
+            high = pattern[i + 1];
+            if high == '\\' {
+                high = *pattern.get(i + 2)?;
+                i += 1;
+            }
+            i += 2;
+        }
+        if low <= c && c <= high {
+            matched = true;
+        }
+    }
+}
+
+/// Whether the name matches the pattern of a component
+fn match_name(pattern: &[char], name: &str) -> bool {
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    // where to retry if the part after the last `*` doesn't match
+    let mut retry = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                retry = Some((p, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match match_class(pattern, p, name[n]) {
+                Some((true, next)) => Some(next),
+                _ => None,
+            },
+            Some('\\') if pattern.get(p + 1) == Some(&name[n]) => Some(p + 2),
+            Some(c) if *c != '\\' && *c == name[n] => Some(p + 1),
+            _ => None,
+        };
+        match (step, retry) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            (None, Some((star, start))) => {
+                // let the `*` eat one more char
+                p = star + 1;
+                n = start + 1;
+                retry = Some((star, start + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::match_name;
+
+    fn matches(pattern: &str, name: &str) -> bool {
+        match_name(&pattern.chars().collect::<Vec<_>>(), name)
+    }
+
+    #[test]
+    fn test_match_name() {
+        assert!(matches("*", "abc"));
+        assert!(matches("*.rs", "lib.rs"));
+        assert!(!matches("*.rs", "lib.rc"));
+        assert!(matches("a*b*c", "aXXbYYc"));
+        assert!(!matches("a*b*c", "aXXbYY"));
+        assert!(matches("?b?", "abc"));
+        assert!(!matches("?b?", "abcd"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(!matches("[!a-c]x", "bx"));
+        assert!(matches("[^a-c]x", "dx"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("**", "abc"));
+        assert!(matches("", ""));
+        assert!(!matches("", "a"));
+    }
+}
@@ -1,17 +1,35 @@
 use alloc::{string::String, sync::Arc, vec::Vec};
 
+use bitflags::bitflags;
 use downcast_rs::{impl_downcast, DowncastSync};
 
 use crate::{
     error::VfsError,
     file::VfsFile,
     superblock::VfsSuperBlock,
-    utils::{VfsFileStat, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTime, VfsTimeSpec},
+    utils::{
+        VfsFallocFlags, VfsFileStat, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsSeekDataHole,
+        VfsStatx, VfsStatxMask, VfsTimeSet, VfsTimeSpec, VfsTimes,
+    },
     VfsResult,
 };
 
+bitflags! {
+    /// The fields of [`InodeAttr`] to set, like ATTR_* on Linux
+    pub struct InodeAttrMask: u32 {
+        const MODE = 1;
+        const UID = 2;
+        const GID = 4;
+        const SIZE = 8;
+        const ATIME = 16;
+        const MTIME = 32;
+    }
+}
+
 pub struct InodeAttr {
-    /// File mode.
+    /// The fields to set, the others are ignored
+    pub mask: InodeAttrMask,
+    /// File mode, only the permission bits are set.
     pub mode: u32,
     pub uid: u32,
     pub gid: u32,
@@ -19,11 +37,97 @@ pub struct InodeAttr {
     ///
     /// For truncate
     pub size: u64,
-    pub atime: VfsTimeSpec,
-    pub mtime: VfsTimeSpec,
+    pub atime: VfsTimeSet,
+    pub mtime: VfsTimeSet,
+    /// The current time, which is the new ctime
     pub ctime: VfsTimeSpec,
 }
 
+impl InodeAttr {
+    /// An attribute which sets nothing
+    pub fn new(ctime: VfsTimeSpec) -> Self {
+        Self {
+            mask: InodeAttrMask::empty(),
+            mode: 0,
+            uid: 0,
+            gid: 0,
+            size: 0,
+            atime: VfsTimeSet::Omit,
+            mtime: VfsTimeSet::Omit,
+            ctime,
+        }
+    }
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.mask |= InodeAttrMask::MODE;
+        self.mode = mode;
+        self
+    }
+    pub fn with_owner(mut self, uid: u32, gid: u32) -> Self {
+        self.mask |= InodeAttrMask::UID | InodeAttrMask::GID;
+        self.uid = uid;
+        self.gid = gid;
+        self
+    }
+    pub fn with_size(mut self, size: u64) -> Self {
+        self.mask |= InodeAttrMask::SIZE;
+        self.size = size;
+        self
+    }
+    pub fn with_times(mut self, times: VfsTimes) -> Self {
+        self.mask |= InodeAttrMask::ATIME | InodeAttrMask::MTIME;
+        self.atime = times.atime;
+        self.mtime = times.mtime;
+        self
+    }
+    /// The permissions in the mode
+    pub fn perm(&self) -> VfsNodePerm {
+        VfsNodePerm::from_bits_truncate(self.mode as u16)
+    }
+    /// The times to set, the ones which are not in the mask are omitted
+    pub fn times(&self) -> VfsTimes {
+        let time = |flag, time| {
+            if self.mask.contains(flag) {
+                time
+            } else {
+                VfsTimeSet::Omit
+            }
+        };
+        VfsTimes::new(
+            time(InodeAttrMask::ATIME, self.atime),
+            time(InodeAttrMask::MTIME, self.mtime),
+        )
+    }
+    /// Set the permissions, the owner and the times in the mask, the size is left to the caller
+    ///
+    /// ctime is set to the ctime of the attribute if anything else is set.
+    pub fn apply_to(
+        &self,
+        perm: &mut VfsNodePerm,
+        uid: &mut u32,
+        gid: &mut u32,
+        atime: &mut VfsTimeSpec,
+        mtime: &mut VfsTimeSpec,
+        ctime: &mut VfsTimeSpec,
+    ) {
+        let changes = self.mask - InodeAttrMask::ATIME - InodeAttrMask::MTIME - InodeAttrMask::SIZE;
+        let times = self.times();
+        if changes.is_empty() && times.is_omit() {
+            return;
+        }
+        if self.mask.contains(InodeAttrMask::MODE) {
+            *perm = self.perm();
+        }
+        if self.mask.contains(InodeAttrMask::UID) {
+            *uid = self.uid;
+        }
+        if self.mask.contains(InodeAttrMask::GID) {
+            *gid = self.gid;
+        }
+        times.apply(atime, mtime, ctime, self.ctime);
+        *ctime = self.ctime;
+    }
+}
+
 pub trait VfsInode: DowncastSync + VfsFile {
     /// Get the super block of this dentry
     fn get_super_block(&self) -> VfsResult<Arc<dyn VfsSuperBlock>> {
@@ -69,7 +173,9 @@ pub trait VfsInode: DowncastSync + VfsFile {
     }
     /// Set the attributes of the node.
     ///
-    ///  This method is called by chmod(2) and related system calls.
+    /// This method is called by chmod(2), chown(2), truncate(2) and related system calls. Only
+    /// the fields in `attr.mask` are set, and the ctime is set to `attr.ctime` unless nothing
+    /// changes.
     fn set_attr(&self, _attr: InodeAttr) -> VfsResult<()> {
         Err(VfsError::NoSys)
     }
@@ -79,16 +185,73 @@ pub trait VfsInode: DowncastSync + VfsFile {
     fn get_attr(&self) -> VfsResult<VfsFileStat> {
         Err(VfsError::NoSys)
     }
+    /// Get the extended attributes of the node, like the birth time and the file attributes.
+    ///
+    /// This method is called by statx(2). `mask` is the fields the caller wants, the filesystem may
+    /// return more or less, and `stx_mask` tells which are valid. The default one only returns
+    /// the fields of [`VfsInode::get_attr`].
+    fn get_statx(&self, _mask: VfsStatxMask) -> VfsResult<VfsStatx> {
+        self.get_attr().map(VfsStatx::from)
+    }
     /// Called by the VFS to list all extended attributes for a given file.
     ///
     /// This method is called by the listxattr(2) system call.
     fn list_xattr(&self) -> VfsResult<Vec<String>> {
         Err(VfsError::NoSys)
     }
+    /// Get the value of the extended attribute `name`.
+    ///
+    /// This method is called by the getxattr(2) system call. Return [`VfsError::ENODATA`] if the
+    /// attribute doesn't exist.
+    fn get_xattr(&self, _name: &str) -> VfsResult<Vec<u8>> {
+        Err(VfsError::NoSys)
+    }
+    /// Set the value of the extended attribute `name`, it is created if it doesn't exist.
+    ///
+    /// This method is called by the setxattr(2) system call.
+    fn set_xattr(&self, _name: &str, _value: &[u8]) -> VfsResult<()> {
+        Err(VfsError::NoSys)
+    }
     fn inode_type(&self) -> VfsNodeType;
     fn truncate(&self, _len: u64) -> VfsResult<()> {
         Err(VfsError::NoSys)
     }
+    /// Manipulate the allocated space of the file in `[offset, offset + len)`.
+    ///
+    /// This method is called by the fallocate(2) system call. The mode can be checked with
+    /// [`VfsFallocFlags::check`]. A filesystem which can't support the mode should return
+    /// [`VfsError::EOPNOTSUPP`].
+    fn fallocate(&self, _mode: VfsFallocFlags, _offset: u64, _len: u64) -> VfsResult<()> {
+        Err(VfsError::EOPNOTSUPP)
+    }
+    /// The fast path of copy_file_range(2), copy up to `len` bytes from this file to `dst`.
+    ///
+    /// This method is called by [`crate::copy::copy_file_range`], the ranges have been checked.
+    /// Return [`VfsError::EOPNOTSUPP`] if there is no fast path between the two files, for example
+    /// they are on different filesystems, then the data is copied with `read_at`/`write_at`.
+    fn copy_file_range_to(
+        &self,
+        _src_offset: u64,
+        _dst: &dyn VfsInode,
+        _dst_offset: u64,
+        _len: usize,
+    ) -> VfsResult<usize> {
+        Err(VfsError::EOPNOTSUPP)
+    }
+    /// Find the next data region or hole at or after `offset`.
+    ///
+    /// This method is called by lseek(2) with SEEK_DATA or SEEK_HOLE. Return [`VfsError::ENXIO`]
+    /// if `offset` is at or after the end of file. The default treats the whole file as data.
+    fn seek_data_hole(&self, offset: u64, whence: VfsSeekDataHole) -> VfsResult<u64> {
+        let size = self.get_attr()?.st_size;
+        if offset >= size {
+            return Err(VfsError::ENXIO);
+        }
+        match whence {
+            VfsSeekDataHole::Data => Ok(offset),
+            VfsSeekDataHole::Hole => Ok(size),
+        }
+    }
 
     /// Rename the file `old_name` to `new_name` in the directory `new_parent`.
     fn rename_to(
@@ -100,12 +263,12 @@ pub trait VfsInode: DowncastSync + VfsFile {
     ) -> VfsResult<()> {
         Err(VfsError::NoSys)
     }
-    /// Update the access and modification times of the inode.
+    /// Set the access and modification times of the inode.
     ///
-    /// This method is called by the utimensat(2) system call. The ctime will be updated automatically.
-    ///
-    /// The parameter `now` is used to update ctime.
-    fn update_time(&self, _time: VfsTime, _now: VfsTimeSpec) -> VfsResult<()> {
+    /// This method is called by the utimensat(2) system call. Both times are set at once, and
+    /// [`VfsTimeSet::Now`] is resolved to `now`. The ctime is set to `now` unless both times are
+    /// [`VfsTimeSet::Omit`].
+    fn set_times(&self, _times: VfsTimes, _now: VfsTimeSpec) -> VfsResult<()> {
         Err(VfsError::NoSys)
     }
 }
@@ -122,6 +285,14 @@ macro_rules! impl_dir_inode_default {
         fn truncate(&self, _len: u64) -> VfsResult<()> {
             Err(VfsError::IsDir)
         }
+        fn fallocate(
+            &self,
+            _mode: $crate::utils::VfsFallocFlags,
+            _offset: u64,
+            _len: u64,
+        ) -> VfsResult<()> {
+            Err(VfsError::IsDir)
+        }
     };
 }
 
@@ -2,13 +2,24 @@
 #![feature(error_in_core)]
 #![feature(exclusive_range_pattern)]
 extern crate alloc;
+pub mod acl;
+pub mod copy;
+pub mod cred;
 pub mod dentry;
+#[cfg(feature = "linux_abi")]
+pub mod dirent;
 pub mod error;
 pub mod file;
 pub mod fstype;
+pub mod glob;
 pub mod inode;
+pub mod lock;
+pub mod page;
 pub mod path;
+pub mod security;
 pub mod superblock;
 pub mod utils;
+pub mod wait;
+pub mod walk;
 
 pub type VfsResult<T> = Result<T, error::VfsError>;
@@ -0,0 +1,419 @@
+//! Advisory file locking.
+//!
+//! Two kinds of advisory locks are supported:
+//! - POSIX byte-range locks, used by fcntl(F_GETLK/F_SETLK/F_SETLKW). They are owned by a process,
+//!   can cover any part of a file, and are split or merged when the owner changes them.
+//! - whole-file locks, used by flock(2). They are owned by an open file description.
+//!
+//! [`VfsLockList`] holds the locks of one inode, filesystems keep one per inode and expose it
+//! through the lock methods of [`VfsFile`]. [`VfsLockManager`] is shared by the whole kernel, it
+//! implements blocking waits and deadlock detection on top of them.
+use alloc::{collections::BTreeMap, vec::Vec};
+
+use lock_api::{Mutex, RawMutex};
+
+use crate::{error::VfsError, file::VfsFile, VfsResult};
+
+/// The owner of a lock.
+///
+/// For POSIX locks this is usually the pid of the process, for flock locks it is an id of the
+/// open file description.
+pub type VfsLockOwner = u64;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VfsLockType {
+    /// F_RDLCK / LOCK_SH
+    Read,
+    /// F_WRLCK / LOCK_EX
+    Write,
+    /// F_UNLCK / LOCK_UN
+    Unlock,
+}
+
+/// A POSIX byte-range lock
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct VfsFileLock {
+    pub owner: VfsLockOwner,
+    /// The pid reported by F_GETLK
+    pub pid: u64,
+    pub ty: VfsLockType,
+    /// The first byte of the range
+    pub start: u64,
+    /// The last byte of the range (inclusive), `u64::MAX` means the lock extends to the end of file
+    pub end: u64,
+}
+
+impl VfsFileLock {
+    /// Create a lock which covers `len` bytes from `start`.
+    ///
+    /// A `len` of 0 means the lock extends to the end of file, like `l_len` of `struct flock`.
+    pub fn new(owner: VfsLockOwner, pid: u64, ty: VfsLockType, start: u64, len: u64) -> Self {
+        let end = if len == 0 {
+            u64::MAX
+        } else {
+            start.saturating_add(len - 1)
+        };
+        Self {
+            owner,
+            pid,
+            ty,
+            start,
+            end,
+        }
+    }
+    /// The length of the range, 0 means to the end of file
+    pub fn l_len(&self) -> u64 {
+        if self.end == u64::MAX {
+            0
+        } else {
+            self.end - self.start + 1
+        }
+    }
+    fn overlaps(&self, other: &VfsFileLock) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+    fn adjoins(&self, other: &VfsFileLock) -> bool {
+        self.overlaps(other)
+            || (self.end != u64::MAX && self.end + 1 == other.start)
+            || (other.end != u64::MAX && other.end + 1 == self.start)
+    }
+    fn conflicts(&self, other: &VfsFileLock) -> bool {
+        self.owner != other.owner
+            && self.overlaps(other)
+            && (self.ty == VfsLockType::Write || other.ty == VfsLockType::Write)
+    }
+}
+
+/// The advisory locks held on one inode
+#[derive(Debug, Default)]
+pub struct VfsLockList {
+    /// POSIX locks, at most one entry covers a byte for each owner
+    posix: Vec<VfsFileLock>,
+    /// flock locks, one entry for each owner
+    flock: Vec<(VfsLockOwner, VfsLockType)>,
+}
+
+impl VfsLockList {
+    pub const fn new() -> Self {
+        Self {
+            posix: Vec::new(),
+            flock: Vec::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.posix.is_empty() && self.flock.is_empty()
+    }
+
+    /// Return the POSIX locks held on this inode, ordered by owner and start
+    pub fn posix_locks(&self) -> &[VfsFileLock] {
+        &self.posix
+    }
+
+    /// Find a lock which conflicts with `lock`.
+    ///
+    /// This is the F_GETLK operation.
+    pub fn test(&self, lock: &VfsFileLock) -> Option<VfsFileLock> {
+        if lock.ty == VfsLockType::Unlock {
+            return None;
+        }
+        self.posix.iter().find(|l| l.conflicts(lock)).cloned()
+    }
+
+    /// Acquire, change or release a POSIX lock without waiting.
+    ///
+    /// The locks of the same owner are split, merged or converted as needed. If another owner holds
+    /// a conflicting lock, [`VfsError::EAGAIN`] is returned and nothing changes.
+    pub fn set(&mut self, lock: VfsFileLock) -> VfsResult<()> {
+        if lock.start > lock.end {
+            return Err(VfsError::Invalid);
+        }
+        if self.test(&lock).is_some() {
+            return Err(VfsError::EAGAIN);
+        }
+        let mut new = lock;
+        let mut res = Vec::with_capacity(self.posix.len() + 2);
+        for old in self.posix.drain(..) {
+            if old.owner != lock.owner {
+                res.push(old);
+                continue;
+            }
+            if old.ty == lock.ty && old.adjoins(&new) {
+                // merge the old lock into the new one
+                new.start = new.start.min(old.start);
+                new.end = new.end.max(old.end);
+                continue;
+            }
+            if !old.overlaps(&lock) {
+                res.push(old);
+                continue;
+            }
+            // the new lock replaces the overlapped part, keep the rest of the old lock
+            if old.start < lock.start {
+                res.push(VfsFileLock {
+                    end: lock.start - 1,
+                    ..old
+                });
+            }
+            if old.end > lock.end {
+                res.push(VfsFileLock {
+                    start: lock.end + 1,
+                    ..old
+                });
+            }
+        }
+        if new.ty != VfsLockType::Unlock {
+            res.push(new);
+        }
+        res.sort_by_key(|l| (l.owner, l.start));
+        self.posix = res;
+        Ok(())
+    }
+
+    /// Find an owner whose flock lock conflicts with a `ty` lock of `owner`
+    pub fn test_flock(&self, owner: VfsLockOwner, ty: VfsLockType) -> Option<VfsLockOwner> {
+        if ty == VfsLockType::Unlock {
+            return None;
+        }
+        self.flock
+            .iter()
+            .find(|(o, t)| *o != owner && (ty == VfsLockType::Write || *t == VfsLockType::Write))
+            .map(|(o, _)| *o)
+    }
+
+    /// Acquire, convert or release a flock lock without waiting.
+    ///
+    /// If another owner holds a conflicting lock, [`VfsError::EAGAIN`] is returned.
+    pub fn set_flock(&mut self, owner: VfsLockOwner, ty: VfsLockType) -> VfsResult<()> {
+        if self.test_flock(owner, ty).is_some() {
+            return Err(VfsError::EAGAIN);
+        }
+        self.flock.retain(|(o, _)| *o != owner);
+        if ty != VfsLockType::Unlock {
+            self.flock.push((owner, ty));
+        }
+        Ok(())
+    }
+
+    /// Release the POSIX locks held by the process `pid`.
+    ///
+    /// Like POSIX, this should be called every time the process closes a file descriptor of the
+    /// inode, even if it has other descriptors open.
+    pub fn release_posix(&mut self, pid: VfsLockOwner) {
+        self.posix.retain(|l| l.owner != pid);
+    }
+
+    /// Release the flock lock held by the open file description `ofd`.
+    ///
+    /// This should be called when the last file descriptor of the description is closed.
+    pub fn release_flock(&mut self, ofd: VfsLockOwner) {
+        self.flock.retain(|(o, _)| *o != ofd);
+    }
+}
+
+/// The kernel wide part of the lock manager.
+///
+/// Each inode only knows its own locks, so waiting for a lock and detecting deadlocks between
+/// owners blocked on different files is done here. The caller provides a `wait` function which is
+/// called every time the lock is busy, it usually puts the current task to sleep or yields, and
+/// returns [`VfsError::EINTR`] if the wait is interrupted by a signal.
+pub struct VfsLockManager<R: RawMutex> {
+    /// owner -> the owner of the lock it is waiting for
+    blocked: Mutex<R, BTreeMap<VfsLockOwner, VfsLockOwner>>,
+}
+
+impl<R: RawMutex> Default for VfsLockManager<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: RawMutex> VfsLockManager<R> {
+    pub const fn new() -> Self {
+        Self {
+            blocked: Mutex::const_new(R::INIT, BTreeMap::new()),
+        }
+    }
+
+    /// Acquire or release a POSIX lock, waiting until the conflicting locks are released.
+    ///
+    /// This is the F_SETLKW operation. [`VfsError::EDEADLK`] is returned if waiting would
+    /// deadlock.
+    pub fn set_lock_wait<F>(
+        &self,
+        file: &dyn VfsFile,
+        lock: VfsFileLock,
+        mut wait: F,
+    ) -> VfsResult<()>
+    where
+        F: FnMut() -> VfsResult<()>,
+    {
+        let res = loop {
+            match file.set_lock(lock) {
+                Err(VfsError::EAGAIN) => {}
+                res => break res,
+            }
+            let blocker = match file.get_lock(lock) {
+                Ok(Some(blocker)) => blocker,
+                // the conflicting lock has gone, try again
+                Ok(None) => continue,
+                Err(e) => break Err(e),
+            };
+            {
+                let mut blocked = self.blocked.lock();
+                if Self::would_deadlock(&blocked, lock.owner, blocker.owner) {
+                    break Err(VfsError::EDEADLK);
+                }
+                blocked.insert(lock.owner, blocker.owner);
+            }
+            if let Err(e) = wait() {
+                break Err(e);
+            }
+        };
+        self.blocked.lock().remove(&lock.owner);
+        res
+    }
+
+    /// Acquire, convert or release a flock lock, waiting until the conflicting locks are released.
+    ///
+    /// Like Linux, there is no deadlock detection for flock locks.
+    pub fn flock_wait<F>(
+        &self,
+        file: &dyn VfsFile,
+        owner: VfsLockOwner,
+        ty: VfsLockType,
+        mut wait: F,
+    ) -> VfsResult<()>
+    where
+        F: FnMut() -> VfsResult<()>,
+    {
+        loop {
+            match file.flock(owner, ty) {
+                Err(VfsError::EAGAIN) => wait()?,
+                res => return res,
+            }
+        }
+    }
+
+    /// Whether `owner` waiting for `blocker` closes a cycle in the wait-for graph
+    fn would_deadlock(
+        blocked: &BTreeMap<VfsLockOwner, VfsLockOwner>,
+        owner: VfsLockOwner,
+        blocker: VfsLockOwner,
+    ) -> bool {
+        let mut next = blocker;
+        // every owner waits for at most one other owner, so the walk ends after len steps
+        for _ in 0..=blocked.len() {
+            if next == owner {
+                return true;
+            }
+            match blocked.get(&next) {
+                Some(n) => next = *n,
+                None => return false,
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+
+    use spin::Mutex;
+
+    use super::*;
+
+    fn lock(owner: VfsLockOwner, ty: VfsLockType, start: u64, len: u64) -> VfsFileLock {
+        VfsFileLock::new(owner, owner, ty, start, len)
+    }
+
+    #[test]
+    fn test_split_and_merge() {
+        let mut list = VfsLockList::new();
+        list.set(lock(1, VfsLockType::Write, 0, 100)).unwrap();
+        // unlock the middle of the range
+        list.set(lock(1, VfsLockType::Unlock, 40, 20)).unwrap();
+        assert_eq!(list.posix_locks().len(), 2);
+        assert_eq!(list.posix_locks()[0].end, 39);
+        assert_eq!(list.posix_locks()[1].start, 60);
+        // downgrade a part of the range
+        list.set(lock(1, VfsLockType::Read, 80, 0)).unwrap();
+        assert_eq!(list.posix_locks().len(), 3);
+        assert_eq!(list.posix_locks()[2].ty, VfsLockType::Read);
+        assert_eq!(list.posix_locks()[2].l_len(), 0);
+        // fill the hole, the adjacent write locks are merged
+        list.set(lock(1, VfsLockType::Write, 40, 20)).unwrap();
+        assert_eq!(list.posix_locks().len(), 2);
+        assert_eq!(list.posix_locks()[0], lock(1, VfsLockType::Write, 0, 80));
+    }
+
+    #[test]
+    fn test_conflict() {
+        let mut list = VfsLockList::new();
+        list.set(lock(1, VfsLockType::Read, 0, 10)).unwrap();
+        list.set(lock(2, VfsLockType::Read, 5, 10)).unwrap();
+        assert_eq!(
+            list.set(lock(2, VfsLockType::Write, 0, 10)),
+            Err(VfsError::EAGAIN)
+        );
+        let blocker = list.test(&lock(3, VfsLockType::Write, 0, 1)).unwrap();
+        assert_eq!(blocker.owner, 1);
+        assert!(list.test(&lock(3, VfsLockType::Write, 20, 0)).is_none());
+        list.release_posix(1);
+        list.set(lock(2, VfsLockType::Write, 0, 10)).unwrap();
+
+        list.set_flock(1, VfsLockType::Read).unwrap();
+        list.set_flock(2, VfsLockType::Read).unwrap();
+        assert_eq!(list.set_flock(2, VfsLockType::Write), Err(VfsError::EAGAIN));
+        list.set_flock(1, VfsLockType::Unlock).unwrap();
+        list.set_flock(2, VfsLockType::Write).unwrap();
+        // a pid and an open file description with the same number don't share their locks
+        list.release_flock(2);
+        assert_eq!(list.posix_locks().len(), 2);
+        list.set_flock(3, VfsLockType::Write).unwrap();
+        list.release_posix(3);
+        assert_eq!(list.set_flock(4, VfsLockType::Read), Err(VfsError::EAGAIN));
+        list.release_flock(3);
+        list.release_posix(2);
+        assert!(list.is_empty());
+    }
+
+    struct LockFile(Mutex<VfsLockList>);
+    impl VfsFile for LockFile {
+        fn get_lock(&self, lock: VfsFileLock) -> VfsResult<Option<VfsFileLock>> {
+            Ok(self.0.lock().test(&lock))
+        }
+        fn set_lock(&self, lock: VfsFileLock) -> VfsResult<()> {
+            self.0.lock().set(lock)
+        }
+    }
+
+    #[test]
+    fn test_deadlock() {
+        let manager = VfsLockManager::<Mutex<()>>::new();
+        let f1 = Arc::new(LockFile(Mutex::new(VfsLockList::new())));
+        let f2 = Arc::new(LockFile(Mutex::new(VfsLockList::new())));
+        f1.set_lock(lock(1, VfsLockType::Write, 0, 0)).unwrap();
+        f2.set_lock(lock(2, VfsLockType::Write, 0, 0)).unwrap();
+        // owner 1 waits for owner 2, and owner 2 then asks for the lock of owner 1
+        let res = manager.set_lock_wait(f2.as_ref(), lock(1, VfsLockType::Write, 0, 0), || {
+            let res = manager.set_lock_wait(
+                f1.as_ref(),
+                lock(2, VfsLockType::Read, 0, 0),
+                || unreachable!(),
+            );
+            assert_eq!(res, Err(VfsError::EDEADLK));
+            // owner 2 gives up and releases its lock
+            f2.set_lock(lock(2, VfsLockType::Unlock, 0, 0))
+        });
+        assert_eq!(res, Ok(()));
+        assert_eq!(
+            f2.get_lock(lock(3, VfsLockType::Read, 0, 0))
+                .unwrap()
+                .unwrap()
+                .owner,
+            1
+        );
+    }
+}
@@ -0,0 +1,432 @@
+//! Pages of a file, used by mmap.
+//!
+//! The kernel maps file pages into user space with [`VfsFile::get_page`](crate::file::VfsFile::get_page).
+//! A filesystem which keeps its data in memory (ramfs) hands out its own frames. A block
+//! filesystem keeps a [`VfsPageCache`] for each file, `read_at`/`write_at` go through the cache so
+//! that MAP_SHARED mappings and the normal file interface see the same data.
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, vec, vec::Vec};
+use core::cell::UnsafeCell;
+
+use downcast_rs::{impl_downcast, DowncastSync};
+use lock_api::{Mutex, RawMutex};
+
+use crate::{error::VfsError, VfsResult};
+
+pub const VFS_PAGE_SIZE: usize = 4096;
+
+/// A physical frame which holds one page of a file
+pub trait VfsFrame: Send + Sync + DowncastSync {
+    /// The physical address of the frame, the kernel maps it to user space
+    fn phys_addr(&self) -> usize;
+    /// Read from the frame, `offset + buf.len()` must not exceed [`VFS_PAGE_SIZE`]
+    fn read_at(&self, offset: usize, buf: &mut [u8]);
+    /// Write to the frame, `offset + buf.len()` must not exceed [`VFS_PAGE_SIZE`]
+    fn write_at(&self, offset: usize, buf: &[u8]);
+}
+
+impl_downcast!(sync VfsFrame);
+
+/// The kernel provides frames through this trait
+pub trait VfsFrameAllocator: Send + Sync {
+    /// Allocate a zeroed frame
+    fn alloc_frame(&self) -> VfsResult<Arc<dyn VfsFrame>>;
+}
+
+#[repr(C, align(4096))]
+struct PageBuf([u8; VFS_PAGE_SIZE]);
+
+/// A frame allocated from the kernel heap.
+///
+/// [`VfsFrame::phys_addr`] returns the virtual address of the buffer, which is only useful for
+/// kernels whose heap is mapped linearly.
+pub struct VfsHeapFrame {
+    buf: Box<UnsafeCell<PageBuf>>,
+}
+
+// The frame is shared memory like a physical frame, the file which owns it serializes the
+// accesses through the vfs, the accesses from user space are racy by nature.
+unsafe impl Send for VfsHeapFrame {}
+unsafe impl Sync for VfsHeapFrame {}
+
+impl Default for VfsHeapFrame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VfsHeapFrame {
+    pub fn new() -> Self {
+        Self {
+            buf: Box::new(UnsafeCell::new(PageBuf([0; VFS_PAGE_SIZE]))),
+        }
+    }
+}
+
+impl VfsFrame for VfsHeapFrame {
+    fn phys_addr(&self) -> usize {
+        self.buf.get() as usize
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) {
+        assert!(offset + buf.len() <= VFS_PAGE_SIZE);
+        unsafe {
+            let src = (self.buf.get() as *const u8).add(offset);
+            core::ptr::copy_nonoverlapping(src, buf.as_mut_ptr(), buf.len());
+        }
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) {
+        assert!(offset + buf.len() <= VFS_PAGE_SIZE);
+        unsafe {
+            let dst = (self.buf.get() as *mut u8).add(offset);
+            core::ptr::copy_nonoverlapping(buf.as_ptr(), dst, buf.len());
+        }
+    }
+}
+
+/// Allocate [`VfsHeapFrame`]s
+pub struct VfsHeapFrameAllocator;
+
+impl VfsFrameAllocator for VfsHeapFrameAllocator {
+    fn alloc_frame(&self) -> VfsResult<Arc<dyn VfsFrame>> {
+        Ok(Arc::new(VfsHeapFrame::new()))
+    }
+}
+
+/// Split `[offset, offset + len)` into the parts covered by each page.
+///
+/// Return (page offset, offset in page, offset in buffer, length) for each part.
+pub fn page_chunks(offset: u64, len: usize) -> impl Iterator<Item = (u64, usize, usize, usize)> {
+    let end = offset + len as u64;
+    let mut pos = offset;
+    core::iter::from_fn(move || {
+        if pos >= end {
+            return None;
+        }
+        let page = pos & !(VFS_PAGE_SIZE as u64 - 1);
+        let in_page = (pos - page) as usize;
+        let n = (VFS_PAGE_SIZE - in_page).min((end - pos) as usize);
+        let res = (page, in_page, (pos - offset) as usize, n);
+        pos += n as u64;
+        Some(res)
+    })
+}
+
+struct VfsCachedPage {
+    frame: Arc<dyn VfsFrame>,
+    dirty: bool,
+    /// The page is being read from the file, it is a placeholder until then
+    filling: bool,
+    /// Changed by each write to the file while the page is being read
+    version: u64,
+}
+
+/// The page cache of a file on a block device.
+///
+/// The cache doesn't access the device itself, the filesystem passes functions which read or
+/// write the file directly. To keep mmap coherent with `read_at`/`write_at`, the filesystem
+/// should:
+/// - call [`VfsPageCache::update`] after writing the file,
+/// - call [`VfsPageCache::read_cached`] after reading the file, the cached pages may be newer,
+/// - call [`VfsPageCache::truncate`] after changing the size of the file,
+/// - write dirty pages back with [`VfsPageCache::writeback`] in `fsync`.
+pub struct VfsPageCache<R: RawMutex> {
+    allocator: Arc<dyn VfsFrameAllocator>,
+    /// page offset -> page
+    pages: Mutex<R, BTreeMap<u64, VfsCachedPage>>,
+}
+
+impl<R: RawMutex> VfsPageCache<R> {
+    pub fn new(allocator: Arc<dyn VfsFrameAllocator>) -> Self {
+        Self {
+            allocator,
+            pages: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Return the page at `offset`, the page is read with `fill` if it is not cached.
+    ///
+    /// `fill` reads the file at the given offset into the buffer, it is called without holding the
+    /// cache lock. A placeholder is cached while the page is read, and if the file is written
+    /// meanwhile, the page is read again, so the write is not lost.
+    pub fn get_page<F>(&self, offset: u64, mut fill: F) -> VfsResult<Arc<dyn VfsFrame>>
+    where
+        F: FnMut(u64, &mut [u8]) -> VfsResult<()>,
+    {
+        if !offset.is_multiple_of(VFS_PAGE_SIZE as u64) {
+            return Err(VfsError::Invalid);
+        }
+        let mut buf = vec![0; VFS_PAGE_SIZE];
+        loop {
+            let (frame, version) = {
+                let mut pages = self.pages.lock();
+                match pages.get(&offset) {
+                    Some(page) if !page.filling => return Ok(page.frame.clone()),
+                    // another thread is reading it, read it too
+                    Some(page) => (page.frame.clone(), page.version),
+                    None => {
+                        let frame = self.allocator.alloc_frame()?;
+                        let page = VfsCachedPage {
+                            frame: frame.clone(),
+                            dirty: false,
+                            filling: true,
+                            version: 0,
+                        };
+                        pages.insert(offset, page);
+                        (frame, 0)
+                    }
+                }
+            };
+            let res = fill(offset, &mut buf);
+            let mut pages = self.pages.lock();
+            if let Err(e) = res {
+                // drop the placeholder, so the page is read again by the next call
+                if pages
+                    .get(&offset)
+                    .is_some_and(|page| page.filling && Arc::ptr_eq(&page.frame, &frame))
+                {
+                    pages.remove(&offset);
+                }
+                return Err(e);
+            }
+            match pages.get_mut(&offset) {
+                Some(page) if !page.filling => return Ok(page.frame.clone()),
+                // the placeholder was neither written nor dropped by truncate
+                Some(page) if Arc::ptr_eq(&page.frame, &frame) && page.version == version => {
+                    page.frame.write_at(0, &buf);
+                    page.filling = false;
+                    return Ok(page.frame.clone());
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Mark the page at `offset` dirty
+    pub fn set_page_dirty(&self, offset: u64) -> VfsResult<()> {
+        let offset = offset & !(VFS_PAGE_SIZE as u64 - 1);
+        let mut pages = self.pages.lock();
+        let page = pages
+            .get_mut(&offset)
+            .filter(|page| !page.filling)
+            .ok_or(VfsError::Invalid)?;
+        page.dirty = true;
+        Ok(())
+    }
+
+    /// Write the dirty pages in `[offset, offset + len)` back with `flush`, `len` 0 means all pages
+    /// from `offset`.
+    ///
+    /// `flush` writes a whole page to the file at the given offset, it is called without holding
+    /// the cache lock. If it fails, the page stays dirty.
+    pub fn writeback<F>(&self, offset: u64, len: u64, mut flush: F) -> VfsResult<()>
+    where
+        F: FnMut(u64, &[u8]) -> VfsResult<()>,
+    {
+        let start = offset & !(VFS_PAGE_SIZE as u64 - 1);
+        let end = if len == 0 {
+            u64::MAX
+        } else {
+            offset.saturating_add(len)
+        };
+        let dirty = {
+            let mut pages = self.pages.lock();
+            let mut dirty = Vec::new();
+            for (page_offset, page) in pages.range_mut(start..end) {
+                if page.dirty {
+                    page.dirty = false;
+                    dirty.push((*page_offset, page.frame.clone()));
+                }
+            }
+            dirty
+        };
+        let mut buf = vec![0; VFS_PAGE_SIZE];
+        for (i, (page_offset, frame)) in dirty.iter().enumerate() {
+            frame.read_at(0, &mut buf);
+            if let Err(e) = flush(*page_offset, &buf) {
+                let mut pages = self.pages.lock();
+                dirty[i..].iter().for_each(|(o, _)| {
+                    if let Some(page) = pages.get_mut(o) {
+                        page.dirty = true;
+                    }
+                });
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy the data written to the file at `offset` into the cached pages
+    pub fn update(&self, offset: u64, buf: &[u8]) {
+        let mut pages = self.pages.lock();
+        for (page_offset, in_page, in_buf, len) in page_chunks(offset, buf.len()) {
+            match pages.get_mut(&page_offset) {
+                // the page being read may miss this write, it is read again
+                Some(page) if page.filling => page.version += 1,
+                Some(page) => page.frame.write_at(in_page, &buf[in_buf..in_buf + len]),
+                None => {}
+            }
+        }
+    }
+
+    /// Copy the cached pages over the data read from the file at `offset`
+    pub fn read_cached(&self, offset: u64, buf: &mut [u8]) {
+        let pages = self.pages.lock();
+        for (page_offset, in_page, in_buf, len) in page_chunks(offset, buf.len()) {
+            if let Some(page) = pages.get(&page_offset).filter(|page| !page.filling) {
+                page.frame.read_at(in_page, &mut buf[in_buf..in_buf + len]);
+            }
+        }
+    }
+
+    /// Drop the pages after `len`, and zero the part of the last page after `len`
+    pub fn truncate(&self, len: u64) {
+        let mut pages = self.pages.lock();
+        let in_page = (len % VFS_PAGE_SIZE as u64) as usize;
+        let last = len - in_page as u64;
+        if in_page != 0 {
+            match pages.get_mut(&last) {
+                Some(page) if page.filling => page.version += 1,
+                Some(page) => page
+                    .frame
+                    .write_at(in_page, &[0; VFS_PAGE_SIZE][..VFS_PAGE_SIZE - in_page]),
+                None => {}
+            }
+        }
+        let first_dropped = if in_page == 0 {
+            last
+        } else {
+            last + VFS_PAGE_SIZE as u64
+        };
+        let _ = pages.split_off(&first_dropped);
+    }
+
+    /// Whether any page is dirty
+    pub fn is_dirty(&self) -> bool {
+        self.pages.lock().values().any(|p| p.dirty)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use spin::Mutex;
+
+    use super::*;
+
+    struct FakeFrameAllocator(AtomicUsize);
+    impl VfsFrameAllocator for FakeFrameAllocator {
+        fn alloc_frame(&self) -> VfsResult<Arc<dyn VfsFrame>> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(Arc::new(VfsHeapFrame::new()))
+        }
+    }
+
+    #[test]
+    fn test_page_chunks() {
+        let chunks = page_chunks(4000, 5000).collect::<Vec<_>>();
+        assert_eq!(
+            chunks,
+            vec![(0, 4000, 0, 96), (4096, 0, 96, 4096), (8192, 0, 4192, 808)]
+        );
+        assert_eq!(page_chunks(0, 0).count(), 0);
+    }
+
+    #[test]
+    fn test_page_cache() {
+        let allocator = Arc::new(FakeFrameAllocator(AtomicUsize::new(0)));
+        let cache = VfsPageCache::<Mutex<()>>::new(allocator.clone());
+        // the "disk" content of the file
+        let disk = core::cell::RefCell::new(vec![1u8; 6000]);
+        let fill = |offset: u64, buf: &mut [u8]| {
+            let disk = disk.borrow();
+            let offset = offset as usize;
+            let len = disk.len().saturating_sub(offset).min(buf.len());
+            buf[..len].copy_from_slice(&disk[offset..offset + len]);
+            Ok(())
+        };
+        assert_eq!(cache.get_page(1, fill).err(), Some(VfsError::Invalid));
+        let frame = cache.get_page(4096, fill).unwrap();
+        let again = cache.get_page(4096, fill).unwrap();
+        assert!(Arc::ptr_eq(&frame, &again));
+        assert_eq!(allocator.0.load(Ordering::SeqCst), 1);
+
+        // a write through the file interface is visible in the mapping
+        cache.update(4100, &[2; 4]);
+        let mut buf = [0; 8];
+        frame.read_at(0, &mut buf);
+        assert_eq!(buf, [1, 1, 1, 1, 2, 2, 2, 2]);
+
+        // a write through the mapping is visible to read_at before writeback
+        frame.write_at(0, &[3; 4]);
+        cache.set_page_dirty(4096).unwrap();
+        let mut buf = [1; 8];
+        cache.read_cached(4092, &mut buf);
+        assert_eq!(buf, [1, 1, 1, 1, 3, 3, 3, 3]);
+
+        cache
+            .writeback(0, 0, |offset, data| {
+                let mut disk = disk.borrow_mut();
+                let offset = offset as usize;
+                let len = disk.len().saturating_sub(offset).min(data.len());
+                disk[offset..offset + len].copy_from_slice(&data[..len]);
+                Ok(())
+            })
+            .unwrap();
+        assert!(!cache.is_dirty());
+        assert_eq!(disk.borrow()[4096..4104], [3, 3, 3, 3, 2, 2, 2, 2]);
+
+        cache.truncate(4098);
+        let mut buf = [0; 4];
+        frame.read_at(0, &mut buf);
+        assert_eq!(buf, [3, 3, 0, 0]);
+        cache.truncate(4096);
+        assert_eq!(cache.set_page_dirty(4096), Err(VfsError::Invalid));
+    }
+
+    #[test]
+    fn test_page_cache_write_while_filling() {
+        let allocator = Arc::new(FakeFrameAllocator(AtomicUsize::new(0)));
+        let cache = VfsPageCache::<Mutex<()>>::new(allocator);
+        let disk = core::cell::RefCell::new(vec![1u8; 4096]);
+        let reads = AtomicUsize::new(0);
+        let frame = cache
+            .get_page(0, |_, buf| {
+                buf.copy_from_slice(&disk.borrow());
+                // the file is written after the old data was read, like by another thread
+                if reads.fetch_add(1, Ordering::SeqCst) == 0 {
+                    disk.borrow_mut()[..4].copy_from_slice(&[2; 4]);
+                    cache.update(0, &[2; 4]);
+                    // the placeholder isn't read
+                    let mut buf = [0; 4];
+                    cache.read_cached(0, &mut buf);
+                    assert_eq!(buf, [0; 4]);
+                }
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(reads.load(Ordering::SeqCst), 2);
+        let mut buf = [0; 8];
+        frame.read_at(0, &mut buf);
+        assert_eq!(buf, [2, 2, 2, 2, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn test_page_cache_fill_error() {
+        let allocator = Arc::new(FakeFrameAllocator(AtomicUsize::new(0)));
+        let cache = VfsPageCache::<Mutex<()>>::new(allocator);
+        let res = cache.get_page(0, |_, _| Err(VfsError::IoError));
+        assert_eq!(res.err(), Some(VfsError::IoError));
+        // the placeholder is not left behind
+        assert!(cache.pages.lock().is_empty());
+        let frame = cache
+            .get_page(0, |_, buf| {
+                buf.fill(1);
+                Ok(())
+            })
+            .unwrap();
+        let mut buf = [0; 4];
+        frame.read_at(0, &mut buf);
+        assert_eq!(buf, [1; 4]);
+    }
+}
@@ -5,6 +5,7 @@ use alloc::{
     format,
     string::{String, ToString},
     sync::Arc,
+    vec,
     vec::Vec,
 };
 use core::{
@@ -15,32 +16,56 @@ use core::{
 use log::{error, trace};
 
 use crate::{
+    acl::{
+        acl_enabled, get_acl, VfsAcl, VfsAclPerm, XATTR_POSIX_ACL_ACCESS, XATTR_POSIX_ACL_DEFAULT,
+    },
+    copy::{copy_file_range, VFS_COPY_CHUNK_SIZE},
+    cred::{VfsCapabilities, VfsCred},
     dentry::VfsDentry,
     error::VfsError,
-    inode::VfsInode,
-    utils::{VfsDirEntry, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag},
+    glob::VfsGlob,
+    inode::{InodeAttr, VfsInode},
+    security::{security_hooks, VfsSecurityHooks},
+    utils::{
+        VfsDirEntry, VfsFileStat, VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag,
+        VfsTimeSet, VfsTimeSpec, VfsTimes,
+    },
+    walk::VfsWalker,
     VfsResult,
 };
 
 /// The context of system call
 ///
 /// In VfsPath, we need to check the permission of the user, so we need the context of system call
+#[derive(Clone)]
 pub struct SysContext {
     pub pid: u64,
-    pub uid: u64,
-    pub gid: u64,
+    /// The credentials which decide the owner of the new inodes and the access to the others
+    pub cred: VfsCred,
+    /// The permissions which are cleared from the new inodes, like `umask` on Linux
+    pub umask: VfsNodePerm,
     pub cwd: Arc<dyn VfsDentry>,
     pub root: Arc<dyn VfsDentry>,
 }
 
+/// The default limit of the total length of a path, like `PATH_MAX` on Linux
+pub const PATH_MAX: usize = 4096;
+
+/// The maximum number of symlinks followed to open a path, like `MAXSYMLINKS` on Linux
+pub const MAX_SYMLINKS: usize = 40;
+
 #[derive(Clone)]
 pub struct VfsPath {
-    /// The root of the file system
+    /// The root of the process, `..` never walks above it
     root: Arc<dyn VfsDentry>,
     /// The directory to start searching from
     fs: Arc<dyn VfsDentry>,
     /// The path to search for
     path: String,
+    /// The maximum length of the path
+    path_max: usize,
+    /// The caller, which decides the owner and the permissions of the new inodes
+    context: Option<SysContext>,
 }
 
 impl PartialEq for VfsPath {
@@ -63,30 +88,63 @@ impl VfsPath {
             root,
             fs: start,
             path: "".to_string(),
+            path_max: PATH_MAX,
+            context: None,
         }
     }
+    /// Change the maximum length of the path, the default is [`PATH_MAX`]
+    pub fn with_path_max(mut self, path_max: usize) -> Self {
+        self.path_max = path_max;
+        self
+    }
+    /// Create the new inodes for the caller
+    ///
+    /// The umask of the caller is applied, the new inodes are owned by the caller, and the access
+    /// is checked with the credentials of the caller. Without a context, the permissions are used
+    /// as they are, the owner is left to the filesystem and nothing is denied.
+    ///
+    /// The root of the path is replaced by the root of the caller, so a chrooted caller can't
+    /// escape it.
+    pub fn with_context(mut self, context: SysContext) -> Self {
+        self.root = context.root.clone();
+        self.context = Some(context);
+        self
+    }
     pub fn as_str(&self) -> &str {
         &self.path
     }
     /// Appends a path segment to this path, returning the result
     pub fn join(&self, path: impl AsRef<str>) -> VfsResult<Self> {
+        let path = self.path.clone() + "/" + path.as_ref();
+        if path.len() > self.path_max {
+            return Err(VfsError::NameTooLong);
+        }
         Ok(VfsPath {
             root: self.root.clone(),
-            path: self.path.clone() + "/" + path.as_ref(),
+            path,
             fs: self.fs.clone(),
+            path_max: self.path_max,
+            context: self.context.clone(),
         })
     }
     pub fn root(&self) -> Self {
+        self.with_start(self.fs.clone())
+    }
+    pub fn is_root(&self) -> bool {
+        self.path.is_empty()
+    }
+    /// An empty path starting from `start`, with the same limits and context
+    fn with_start(&self, start: Arc<dyn VfsDentry>) -> Self {
         VfsPath {
             root: self.root.clone(),
             path: "".to_string(),
-            fs: self.fs.clone(),
+            fs: start,
+            path_max: self.path_max,
+            context: self.context.clone(),
         }
     }
-    pub fn is_root(&self) -> bool {
-        self.path.is_empty()
-    }
 
+    /// Find the target of `symlink`, which may be another symlink
     fn to_symlink(&self, symlink: Arc<dyn VfsDentry>) -> VfsResult<Arc<dyn VfsDentry>> {
         let inode = symlink.inode()?;
         let mut buf = [0; 255];
@@ -98,14 +156,14 @@ impl VfsPath {
         if path.starts_with("/") {
             trace!("[to_symlink] absolute path: {}", path);
             // absolute path
-            let new_path = Self::new(self.root.clone(), self.root.clone()).join(path)?;
-            new_path.open(None)
+            let new_path = self.with_start(self.root.clone()).join(path)?;
+            new_path.exists()
         } else {
             trace!("[to_symlink] relative path: {}", path);
             // relative path
             let p = symlink.parent().unwrap();
-            let new_path = Self::new(self.root.clone(), p).join(path)?;
-            new_path.open(None)
+            let new_path = self.with_start(p).join(path)?;
+            new_path.exists()
         }
     }
     /// It same as [`open`], but it will follow the symlink according to the flag
@@ -141,15 +199,9 @@ impl VfsPath {
         let exist = self.exists();
         match exist {
             Ok(d) => {
-                if symlink {
-                    let inode = d.inode()?;
-                    match inode.inode_type() {
-                        VfsNodeType::SymLink => self.to_symlink(d),
-                        _ => Ok(d),
-                    }
-                } else {
-                    Ok(d)
-                }
+                let d = self.follow(d, symlink)?;
+                self.call_hook(|hooks, cred| hooks.open(cred, &d))?;
+                Ok(d)
             }
             Err(e) => match e {
                 VfsError::NoEntry if mode.is_some() => {
@@ -168,12 +220,74 @@ impl VfsPath {
         }
     }
 
+    /// Find the dentry like [`VfsPath::open`], but without calling the open hook
+    ///
+    /// It is used by the other operations, which call their own hooks.
+    pub(crate) fn resolve(&self, symlink: bool) -> VfsResult<Arc<dyn VfsDentry>> {
+        let d = self.exists()?;
+        self.follow(d, symlink)
+    }
+
+    /// Follow the symlinks if `symlink`, [`VfsError::ELOOP`] is returned after [`MAX_SYMLINKS`]
+    fn follow(&self, mut d: Arc<dyn VfsDentry>, symlink: bool) -> VfsResult<Arc<dyn VfsDentry>> {
+        if !symlink {
+            return Ok(d);
+        }
+        let mut links = 0;
+        while d.inode()?.inode_type() == VfsNodeType::SymLink {
+            if links == MAX_SYMLINKS {
+                return Err(VfsError::ELOOP);
+            }
+            links += 1;
+            d = self.to_symlink(d)?;
+        }
+        Ok(d)
+    }
+
+    /// Call a security hook before an operation, see [`crate::security`]
+    fn call_hook(
+        &self,
+        hook: impl FnOnce(&dyn VfsSecurityHooks, Option<&VfsCred>) -> VfsResult<()>,
+    ) -> VfsResult<()> {
+        match security_hooks() {
+            Some(hooks) => hook(hooks, self.context.as_ref().map(|context| &context.cred)),
+            None => Ok(()),
+        }
+    }
+
+    /// Create a regular file or a special file, like `mknod`
+    ///
+    /// `rdev` is the device number of a device file. With a context, the umask is cleared from
+    /// the permissions and the new inode is owned by the caller, see [`VfsPath::with_context`].
+    /// Device files can only be created with [`VfsCapabilities::MKNOD`].
+    pub fn mknod(&self, mode: VfsInodeMode, rdev: u64) -> VfsResult<Arc<dyn VfsDentry>> {
+        let ty = match mode & VfsInodeMode::TYPE_MASK {
+            VfsInodeMode::FILE => VfsNodeType::File,
+            ty if ty.is_empty() => VfsNodeType::File,
+            ty @ (VfsInodeMode::FIFO
+            | VfsInodeMode::CHAR
+            | VfsInodeMode::BLOCK
+            | VfsInodeMode::SOCKET) => VfsNodeType::from(ty),
+            _ => return Err(VfsError::Invalid),
+        };
+        let rdev = match ty {
+            VfsNodeType::CharDevice | VfsNodeType::BlockDevice => Some(rdev),
+            _ => None,
+        };
+        if let Some(context) = &self.context {
+            if rdev.is_some() && !context.cred.capable(VfsCapabilities::MKNOD) {
+                return Err(VfsError::PermissionDenied);
+            }
+        }
+        self.create(ty, mode.into(), rdev, "mknod")
+    }
+
     fn create_file(&self, perm: VfsNodePerm) -> VfsResult<Arc<dyn VfsDentry>> {
-        self.create(VfsNodeType::File, perm, "create file")
+        self.create(VfsNodeType::File, perm, None, "create file")
     }
 
     fn create_dir(&self, perm: VfsNodePerm) -> VfsResult<Arc<dyn VfsDentry>> {
-        self.create(VfsNodeType::Dir, perm, "create dir")
+        self.create(VfsNodeType::Dir, perm, None, "create dir")
     }
 
     // todo! create flags
@@ -181,6 +295,7 @@ impl VfsPath {
         &self,
         ty: VfsNodeType,
         perm: VfsNodePerm,
+        rdev: Option<u64>,
         action: &str,
     ) -> VfsResult<Arc<dyn VfsDentry>> {
         let parent = self.get_parent(action)?;
@@ -191,6 +306,7 @@ impl VfsPath {
             return Err(VfsError::Invalid);
         }
         let file_name = file_name.unwrap();
+        check_name_len(&dentry, file_name)?;
         // first, we find in dentry cache
         let file = dentry.find(file_name);
         if file.is_none() {
@@ -204,7 +320,17 @@ impl VfsPath {
                 Err(e) => {
                     if e == VfsError::NoEntry {
                         // if we can't find the inode, we create a new inode and insert it into dentry cache
-                        let file_inode = dentry.inode()?.create(file_name, ty, perm, None)?;
+                        if let Some(context) = &self.context {
+                            check_dir_write(&dentry, context)?;
+                        }
+                        let (perm, default) = self.new_perm(&dentry, ty, perm)?;
+                        let mode = VfsInodeMode::from(perm, ty);
+                        self.call_hook(|hooks, cred| hooks.create(cred, &dentry, file_name, mode))?;
+                        let file_inode = dentry.inode()?.create(file_name, ty, perm, rdev)?;
+                        self.set_owner(&dentry, &file_inode)?;
+                        if let Some(default) = default {
+                            inherit_acl(&file_inode, default, perm)?;
+                        }
                         let file = dentry.insert(file_name, file_inode)?;
                         Ok(file)
                     } else {
@@ -217,6 +343,59 @@ impl VfsPath {
         }
     }
 
+    /// The permissions of a new inode, with the umask of the context cleared, and the default ACL
+    /// of the parent which the inode inherits
+    ///
+    /// If the parent has a default ACL, the permissions are limited by it instead of the umask.
+    /// A new directory in a setgid directory is setgid too, like on Linux.
+    fn new_perm(
+        &self,
+        parent: &Arc<dyn VfsDentry>,
+        ty: VfsNodeType,
+        perm: VfsNodePerm,
+    ) -> VfsResult<(VfsNodePerm, Option<VfsAcl>)> {
+        let parent_inode = parent.inode()?;
+        let default = get_acl(&parent_inode, XATTR_POSIX_ACL_DEFAULT)?;
+        let mut perm = match (&default, &self.context) {
+            (Some(default), _) => {
+                let mode = default.clone().create_masq(perm.bits() as u32);
+                VfsNodePerm::from_bits_truncate(mode as u16)
+            }
+            (None, Some(context)) => perm - context.umask,
+            (None, None) => perm,
+        };
+        if ty == VfsNodeType::Dir && parent_inode.node_perm().contains(VfsNodePerm::SET_GID) {
+            perm |= VfsNodePerm::SET_GID;
+        }
+        Ok((perm, default))
+    }
+
+    /// Give a new inode to the caller
+    ///
+    /// The group is inherited from the parent instead if the parent has the setgid bit, like on
+    /// Linux.
+    fn set_owner(&self, parent: &Arc<dyn VfsDentry>, inode: &Arc<dyn VfsInode>) -> VfsResult<()> {
+        let context = match &self.context {
+            Some(context) => context,
+            None => return Ok(()),
+        };
+        let parent_stat = parent.inode()?.get_attr()?;
+        let stat = inode.get_attr()?;
+        let mut gid = context.cred.fsgid;
+        if parent_stat.st_mode & VfsInodeMode::SET_GID.bits() != 0 {
+            gid = parent_stat.st_gid;
+        }
+        let uid = context.cred.fsuid;
+        if (stat.st_uid, stat.st_gid) == (uid, gid) {
+            return Ok(());
+        }
+        let attr = InodeAttr::new(stat.st_ctime).with_owner(uid, gid);
+        match inode.set_attr(attr) {
+            Err(VfsError::NoSys) => Ok(()),
+            res => res,
+        }
+    }
+
     /// Checks whether parent is a directory
     fn get_parent(&self, action: &str) -> VfsResult<Arc<dyn VfsDentry>> {
         let parent = self.parent();
@@ -234,11 +413,16 @@ impl VfsPath {
                 root: self.root.clone(),
                 path: self.path[..idx].to_string(),
                 fs: self.fs.clone(),
+                path_max: self.path_max,
+                context: self.context.clone(),
             })
             .unwrap_or_else(|| self.root())
     }
 
     pub fn exists(&self) -> VfsResult<Arc<dyn VfsDentry>> {
+        if self.path.len() > self.path_max {
+            return Err(VfsError::NameTooLong);
+        }
         let mut parent = self.fs.clone();
         let mut path = self.path.as_str();
         loop {
@@ -253,14 +437,21 @@ impl VfsPath {
             match name {
                 "." => {}
                 ".." => {
-                    let real_parent = real_dentry_up(parent.clone());
-                    if let Some(p) = real_parent.parent() {
-                        parent = p;
+                    // the root of a chrooted process behaves like the global root
+                    if !is_same_dentry(&parent, &self.root) {
+                        let real_parent = real_dentry_up(parent.clone());
+                        if let Some(p) = real_parent.parent() {
+                            parent = p;
+                        }
                     }
                 }
                 _ => {
                     // resolve mount point
                     let dentry = real_dentry_down(parent.clone());
+                    if let Some(context) = &self.context {
+                        check_permission(&dentry.inode()?, context, VfsAclPerm::EXECUTE)?;
+                    }
+                    self.call_hook(|hooks, cred| hooks.lookup(cred, &dentry, name))?;
                     // first, we find in dentry cache
                     let sub_dentry = dentry.find(name);
                     if sub_dentry.is_none() {
@@ -300,10 +491,13 @@ impl VfsPath {
         }
     }
 
-    // todo! permission check
+    /// Mount `root` on this directory
+    ///
+    /// With a context, the caller needs [`VfsCapabilities::SYS_ADMIN`].
     pub fn mount(&self, root: Arc<dyn VfsDentry>, mount_flag: u32) -> VfsResult<()> {
         assert!(root.parent().is_none());
-        let dir = self.open(None)?;
+        self.check_capable(VfsCapabilities::SYS_ADMIN)?;
+        let dir = self.resolve(true)?;
         let inode = dir.inode()?;
         if !inode
             .node_perm()
@@ -314,40 +508,178 @@ impl VfsPath {
         if inode.inode_type() != VfsNodeType::Dir {
             return Err(VfsError::NotDir);
         }
+        self.call_hook(|hooks, cred| hooks.mount(cred, &dir, &root, mount_flag))?;
         root.set_parent(&dir);
         dir.to_mount_point(root, mount_flag)?;
         Ok(())
     }
 
+    /// Unmount the filesystem which is mounted on this directory
+    ///
+    /// With a context, the caller needs [`VfsCapabilities::SYS_ADMIN`].
     // todo! check much things
     pub fn umount(&self) -> VfsResult<()> {
-        let dir = self.open(None)?;
+        self.check_capable(VfsCapabilities::SYS_ADMIN)?;
+        let mut dir = self.resolve(true)?;
+        // the path is resolved to the root of the filesystem which is mounted on it
+        if dir.name() == "/" {
+            if let Some(parent) = dir.parent().filter(|parent| parent.is_mount_point()) {
+                dir = parent;
+            }
+        }
         if !dir.is_mount_point() {
             return Err(VfsError::Invalid);
         }
+        self.call_hook(|hooks, cred| hooks.umount(cred, &dir))?;
         let mnt = dir.mount_point().unwrap();
         dir.clear_mount_point();
         mnt.root.inode()?.get_super_block()?.sync_fs(false)?;
         Ok(())
     }
 
+    /// Check whether the caller has the capability, a path without a context has all of them
+    fn check_capable(&self, cap: VfsCapabilities) -> VfsResult<()> {
+        match &self.context {
+            Some(context) if !context.cred.capable(cap) => Err(VfsError::PermissionDenied),
+            _ => Ok(()),
+        }
+    }
+
     pub fn truncate(&self, len: u64) -> VfsResult<()> {
-        let dt = self.open(None).expect("truncate open failed");
+        let dt = self.resolve(true)?;
         let inode = dt.inode()?;
         if inode.inode_type() == VfsNodeType::Dir {
             return Err(VfsError::IsDir);
         }
-        // let fs = inode.get_super_block()?;
-        // todo! access check
-        if inode.node_perm().contains(VfsNodePerm::OTHER_WRITE) {
-            return Err(VfsError::Access);
+        match &self.context {
+            Some(context) => check_permission(&inode, context, VfsAclPerm::WRITE)?,
+            // todo! access check
+            None if inode.node_perm().contains(VfsNodePerm::OTHER_WRITE) => {
+                return Err(VfsError::Access);
+            }
+            None => {}
+        }
+        self.call_hook(|hooks, cred| {
+            let attr = InodeAttr::new(inode.get_attr()?.st_ctime).with_size(len);
+            hooks.setattr(cred, &dt, &attr)
+        })?;
+        if let Some(context) = &self.context {
+            file_remove_privs(&inode, context)?;
         }
         inode.truncate(len)?;
         Ok(())
     }
 
+    /// Write `buf` to the file at `offset`, like `pwrite`
+    ///
+    /// With a context, the caller needs the write permission, and the setuid and setgid bits are
+    /// cleared like [`file_remove_privs`].
+    pub fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let dt = self.resolve(true)?;
+        let inode = dt.inode()?;
+        if inode.inode_type() == VfsNodeType::Dir {
+            return Err(VfsError::IsDir);
+        }
+        if let Some(context) = &self.context {
+            check_permission(&inode, context, VfsAclPerm::WRITE)?;
+            file_remove_privs(&inode, context)?;
+        }
+        inode.write_at(offset, buf)
+    }
+
+    /// Change the permissions, like `chmod`
+    ///
+    /// With a context, only the owner and the callers with [`VfsCapabilities::FOWNER`] can change
+    /// them, and the setgid bit is cleared if the caller is not in the group of the file and
+    /// doesn't have [`VfsCapabilities::FSETID`], like on Linux.
+    pub fn chmod(&self, perm: VfsNodePerm) -> VfsResult<()> {
+        let dentry = self.resolve(true)?;
+        let inode = dentry.inode()?;
+        let stat = inode.get_attr()?;
+        let mut perm = perm;
+        if let Some(context) = &self.context {
+            let cred = &context.cred;
+            check_owner(cred, stat.st_uid)?;
+            if !cred.in_group(stat.st_gid) && !cred.capable(VfsCapabilities::FSETID) {
+                perm -= VfsNodePerm::SET_GID;
+            }
+        }
+        let mode = stat.st_mode & !0o7777 | perm.bits() as u32;
+        let attr = InodeAttr::new(stat.st_ctime).with_mode(mode);
+        self.call_hook(|hooks, cred| hooks.setattr(cred, &dentry, &attr))?;
+        inode.set_attr(attr)?;
+        // the access ACL follows the permission bits
+        if let Some(mut acl) = get_acl(&inode, XATTR_POSIX_ACL_ACCESS)? {
+            acl.chmod(mode);
+            inode.set_xattr(XATTR_POSIX_ACL_ACCESS, &acl.to_xattr())?;
+        }
+        Ok(())
+    }
+
+    /// Change the access and the modification times, like `utimensat`
+    ///
+    /// With a context, setting both times to the current time needs the write permission or the
+    /// ownership, and setting them to other times needs the ownership or
+    /// [`VfsCapabilities::FOWNER`].
+    pub fn set_times(&self, times: VfsTimes, now: VfsTimeSpec) -> VfsResult<()> {
+        let dentry = self.resolve(true)?;
+        let inode = dentry.inode()?;
+        if times.is_omit() {
+            return Ok(());
+        }
+        if let Some(context) = &self.context {
+            let owner = inode.get_attr()?.st_uid;
+            if times == VfsTimes::now() {
+                if check_owner(&context.cred, owner).is_err() {
+                    check_permission(&inode, context, VfsAclPerm::WRITE)?;
+                }
+            } else {
+                check_owner(&context.cred, owner)?;
+            }
+        }
+        let attr = InodeAttr::new(now).with_times(times);
+        self.call_hook(|hooks, cred| hooks.setattr(cred, &dentry, &attr))?;
+        inode.set_times(times, now)
+    }
+
+    /// Change the owner and the group, `None` leaves it unchanged
+    ///
+    /// Changing the owner needs [`VfsCapabilities::CHOWN`]. Without it, the owner can only
+    /// change the group to one of their groups, and the setuid and setgid bits of a regular file
+    /// are cleared.
+    pub fn chown(&self, uid: Option<u32>, gid: Option<u32>) -> VfsResult<()> {
+        let dentry = self.resolve(true)?;
+        let inode = dentry.inode()?;
+        let stat = inode.get_attr()?;
+        let uid = uid.unwrap_or(stat.st_uid);
+        let gid = gid.unwrap_or(stat.st_gid);
+        let mut mode = stat.st_mode;
+        let cred = self.context.as_ref().map(|context| &context.cred);
+        if let Some(cred) = cred.filter(|cred| !cred.capable(VfsCapabilities::CHOWN)) {
+            if uid != stat.st_uid
+                || cred.fsuid != stat.st_uid
+                || (gid != stat.st_gid && !cred.in_group(gid))
+            {
+                return Err(VfsError::PermissionDenied);
+            }
+            if inode.inode_type() == VfsNodeType::File {
+                mode &= !privs_to_remove(stat.st_mode);
+            }
+        }
+        let mut attr = InodeAttr::new(stat.st_ctime).with_owner(uid, gid);
+        if mode != stat.st_mode {
+            attr = attr.with_mode(mode);
+        }
+        self.call_hook(|hooks, cred| hooks.setattr(cred, &dentry, &attr))?;
+        inode.set_attr(attr)
+    }
+
+    /// Create a symlink to `target`
+    ///
+    /// The permissions of a symlink are always `rwxrwxrwx`, the umask of the context is not
+    /// applied, but the owner is set like [`VfsPath::mknod`].
     pub fn symlink(&self, target: &str) -> VfsResult<()> {
-        let this = self.open(None);
+        let this = self.resolve(true);
         match this {
             Ok(_) => Err(VfsError::EExist),
             Err(e) => match e {
@@ -356,7 +688,14 @@ impl VfsPath {
                     let parent_inode = parent.inode()?;
                     let name = self.filename();
                     assert!(!name.is_empty());
+                    check_name_len(&parent, &name)?;
+                    if let Some(context) = &self.context {
+                        check_dir_write(&parent, context)?;
+                    }
+                    let mode = VfsInodeMode::LINK | VfsInodeMode::from_bits_truncate(0o777);
+                    self.call_hook(|hooks, cred| hooks.create(cred, &parent, &name, mode))?;
                     let inode = parent_inode.symlink(&name, target)?;
+                    self.set_owner(&parent, &inode)?;
                     let _ = parent.insert(&name, inode)?;
                     Ok(())
                 }
@@ -368,22 +707,21 @@ impl VfsPath {
         if old_dt.inode()?.inode_type() == VfsNodeType::Dir {
             return Err(VfsError::PermissionDenied);
         }
-        let this = self.open(None);
+        let this = self.resolve(true);
         match this {
             Ok(_) => Err(VfsError::EExist),
             Err(e) => match e {
                 VfsError::NoEntry => {
                     let parent = self.get_parent("create hard link")?;
                     let parent_inode = parent.inode()?;
-
-                    let old_fs = old_dt.inode()?.get_super_block()?;
-                    let this_fs = parent_inode.get_super_block()?;
-                    if !Arc::ptr_eq(&old_fs, &this_fs) {
-                        return Err(VfsError::Invalid);
+                    check_same_fs(&old_dt, &parent)?;
+                    if let Some(context) = &self.context {
+                        check_dir_write(&parent, context)?;
                     }
-                    // todo! access check
                     let name = self.filename();
                     assert!(!name.is_empty());
+                    check_name_len(&parent, &name)?;
+                    self.call_hook(|hooks, cred| hooks.link(cred, &old_dt, &parent, &name))?;
                     let inode = parent_inode.link(&name, old_dt.inode()?)?;
                     let _ = parent.insert(&name, inode)?;
                     Ok(())
@@ -394,24 +732,23 @@ impl VfsPath {
     }
 
     pub fn rmdir(&self) -> VfsResult<()> {
-        let dt = self.open(None)?;
+        let dt = self.resolve(false)?;
         let inode = dt.inode()?;
         if inode.inode_type() != VfsNodeType::Dir {
             return Err(VfsError::NotDir);
         }
         let parent = self.get_parent("rmdir")?;
         let parent_inode = parent.inode()?;
-        let perm = parent_inode.node_perm();
-
-        if !perm.contains(VfsNodePerm::OTHER_WRITE)
-            && !perm.contains(VfsNodePerm::GROUP_WRITE)
-            && !perm.contains(VfsNodePerm::OWNER_WRITE)
-        {
-            return Err(VfsError::Access);
+        match &self.context {
+            Some(context) => {
+                check_dir_write(&parent, context)?;
+                check_sticky(&parent, &dt, context)?;
+            }
+            None => checkout_write_perm(&parent)?,
         }
+        self.call_hook(|hooks, cred| hooks.unlink(cred, &parent, &dt))?;
         let name = self.filename();
         assert!(!name.is_empty());
-        // todo! access check
         parent_inode.rmdir(&name)?;
         // remove the dentry from cache
         parent.remove(&name);
@@ -419,7 +756,7 @@ impl VfsPath {
     }
 
     pub fn unlink(&self) -> VfsResult<()> {
-        let dt = self.open(None)?;
+        let dt = self.resolve(false)?;
         let inode = dt.inode()?;
         if inode.inode_type() == VfsNodeType::Dir {
             return Err(VfsError::IsDir);
@@ -427,17 +764,16 @@ impl VfsPath {
         let parent = self.get_parent("unlink")?;
         let parent_inode = parent.inode()?;
 
-        let perm = parent_inode.node_perm();
-
-        if !perm.contains(VfsNodePerm::OTHER_WRITE)
-            && !perm.contains(VfsNodePerm::GROUP_WRITE)
-            && !perm.contains(VfsNodePerm::OWNER_WRITE)
-        {
-            return Err(VfsError::Access);
+        match &self.context {
+            Some(context) => {
+                check_dir_write(&parent, context)?;
+                check_sticky(&parent, &dt, context)?;
+            }
+            None => checkout_write_perm(&parent)?,
         }
+        self.call_hook(|hooks, cred| hooks.unlink(cred, &parent, &dt))?;
         let name = self.filename();
         assert!(!name.is_empty());
-        // todo! access check
         parent_inode.unlink(&name)?;
 
         // remove the dentry from cache
@@ -451,9 +787,10 @@ impl VfsPath {
         new_vfs_path: VfsPath,
         flag: VfsRenameFlag,
     ) -> VfsResult<()> {
-        let old_dt = self.open(None)?;
+        // like `unlink`, a trailing symlink is renamed instead of its target
+        let old_dt = self.resolve(false)?;
         checkout_busy(&old_dt, &context)?;
-        let new_dt = new_vfs_path.open(None);
+        let new_dt = new_vfs_path.resolve(false);
         if new_dt.is_err() {
             let err = new_dt.err().unwrap();
             if err != VfsError::NoEntry {
@@ -471,8 +808,11 @@ impl VfsPath {
                 .expect("get parent of old path failed, this should not happen");
 
             check_same_fs(&new_parent, &old_parent)?;
-            checkout_write_perm(&new_parent)?;
-            checkout_write_perm(&old_parent)?;
+            check_name_len(&new_parent, &new_vfs_path.filename())?;
+            check_dir_write(&new_parent, &context)?;
+            check_dir_write(&old_parent, &context)?;
+            check_sticky(&old_parent, &old_dt, &context)?;
+            rename_hook(&context, &old_parent, &old_dt, &new_parent, &new_vfs_path)?;
 
             let old_parent_inode = old_parent.inode()?;
 
@@ -507,8 +847,12 @@ impl VfsPath {
                 .expect("get parent of old path failed, this should not happen");
 
             check_same_fs(&new_parent, &old_parent)?;
-            checkout_write_perm(&new_parent)?;
-            checkout_write_perm(&old_parent)?;
+            check_name_len(&new_parent, &new_vfs_path.filename())?;
+            check_dir_write(&new_parent, &context)?;
+            check_dir_write(&old_parent, &context)?;
+            check_sticky(&old_parent, &old_dt, &context)?;
+            check_sticky(&new_parent, &new_dt, &context)?;
+            rename_hook(&context, &old_parent, &old_dt, &new_parent, &new_vfs_path)?;
             let old_parent_inode = old_parent.inode()?;
             let new_parent_inode = new_parent.inode()?;
             old_parent_inode.rename_to(
@@ -529,11 +873,304 @@ impl VfsPath {
         }
         Ok(())
     }
-    pub fn set_xattr(&self, _key: &str, _value: &[u8]) -> VfsResult<()> {
-        unimplemented!();
+    /// Move this file or directory tree to `dst` like `mv`, even if they are on different
+    /// filesystems
+    ///
+    /// It is a rename if possible. Otherwise the tree is copied to a temporary entry next to `dst`
+    /// like [`VfsPath::copy_tree`], which is renamed to `dst` after the whole tree is copied, and
+    /// then the source is removed. If the copy fails, the temporary entry is removed and `dst` is
+    /// left unchanged. A trailing symlink is moved instead of its target.
+    pub fn move_path(&self, context: SysContext, dst: &VfsPath) -> VfsResult<()> {
+        match self.rename_to(context.clone(), dst.clone(), VfsRenameFlag::empty()) {
+            Err(VfsError::EXDEV) => {}
+            res => return res,
+        }
+        let tmp = dst.parent().join(format!(".{}.move", dst.filename()))?;
+        match tmp.resolve(false) {
+            Ok(_) => return Err(VfsError::EExist),
+            Err(VfsError::NoEntry) => {}
+            Err(e) => return Err(e),
+        }
+        // the source and `dst` are on different filesystems, so `dst` can't be inside the source
+        let mut errors = Vec::new();
+        let res = self
+            .copy_entry(&tmp, &mut errors)
+            .and_then(|_| match errors.first() {
+                Some(e) => Err(e.error),
+                None => tmp.rename_to(context, dst.clone(), VfsRenameFlag::empty()),
+            });
+        if let Err(e) = res {
+            // the temporary entry may be partly copied
+            let _ = tmp.remove_all();
+            return Err(e);
+        }
+        self.remove_all()
+    }
+
+    /// Remove a file, a symlink or a directory tree
+    fn remove_all(&self) -> VfsResult<()> {
+        let dentry = self.resolve(false)?;
+        if dentry.inode()?.inode_type() == VfsNodeType::Dir {
+            self.remove_dir_all()
+        } else {
+            self.unlink()
+        }
+    }
+
+    /// Expand a glob pattern below this path, like `ls a/*/b*.rs`
+    ///
+    /// The pattern can have `*`, `?`, `[...]` and `**`, see [`crate::glob`]. An absolute pattern
+    /// starts from the root instead of this path. It fails only if the pattern is invalid, and the
+    /// errors of listing directories are returned by the iterator.
+    pub fn glob(&self, pattern: &str) -> VfsResult<VfsGlob> {
+        let start = if pattern.starts_with('/') {
+            self.with_start(self.root.clone())
+        } else {
+            self.clone()
+        };
+        VfsGlob::new(start, pattern)
+    }
+
+    /// Set an xattr of the inode
+    ///
+    /// The ACL xattrs are checked before they are set, and the permission bits are updated from
+    /// an access ACL. With a context, only the owner and the callers with
+    /// [`VfsCapabilities::FOWNER`] can set them, and the other xattrs are checked by
+    /// [`xattr_permission`].
+    pub fn set_xattr(&self, key: &str, value: &[u8]) -> VfsResult<()> {
+        let dentry = self.resolve(true)?;
+        let inode = dentry.inode()?;
+        if let Some(context) = &self.context {
+            xattr_permission(&inode, context, key)?;
+        }
+        let chmod = if key == XATTR_POSIX_ACL_ACCESS || key == XATTR_POSIX_ACL_DEFAULT {
+            check_acl_xattr(&inode, self.context.as_ref(), key, value)?
+        } else {
+            None
+        };
+        self.call_hook(|hooks, cred| hooks.setxattr(cred, &dentry, key, value))?;
+        if let Some(attr) = chmod {
+            inode.set_attr(attr)?;
+        }
+        inode.set_xattr(key, value)
+    }
+    pub fn get_xattr(&self, key: &str) -> VfsResult<Vec<u8>> {
+        self.resolve(true)?.inode()?.get_xattr(key)
+    }
+
+    /// Create a directory and all of its missing parents, like `mkdir -p`
+    ///
+    /// The existing directories are left as they are, the new ones are created with `perm`.
+    pub fn create_dir_all(&self, perm: VfsNodePerm) -> VfsResult<Arc<dyn VfsDentry>> {
+        let mut path = self.root();
+        for name in self.path.split('/').filter(|name| !name.is_empty()) {
+            path = path.join(name)?;
+            match path.resolve(true) {
+                Ok(dentry) => {
+                    if !dentry.inode()?.inode_type().is_dir() {
+                        return Err(VfsError::NotDir);
+                    }
+                }
+                Err(VfsError::NoEntry) => {
+                    path.create_dir(perm)?;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        path.resolve(true)
+    }
+
+    /// Remove a directory and everything in it, like `rm -r`
+    ///
+    /// The children are removed before their parent, and symlinks are removed instead of being
+    /// followed.
+    pub fn remove_dir_all(&self) -> VfsResult<()> {
+        if self.is_root() {
+            return Err(VfsError::EBUSY);
+        }
+        let dentry = self.resolve(false)?;
+        let inode = dentry.inode()?;
+        if inode.inode_type() != VfsNodeType::Dir {
+            return Err(VfsError::NotDir);
+        }
+        for entry in dir_entries(&inode)? {
+            let path = self.join(&entry.name)?;
+            if entry.ty == VfsNodeType::Dir {
+                path.remove_dir_all()?;
+            } else {
+                path.unlink()?;
+            }
+        }
+        self.rmdir()
     }
-    pub fn get_xattr(&self, _key: &str) -> VfsResult<Vec<u8>> {
-        unimplemented!();
+
+    /// Copy this file or directory tree to `dst`, like `cp -a`
+    ///
+    /// The permissions, timestamps, symlinks and extended attributes are preserved, and so are the
+    /// owners unless `dst` has the context of a user. `dst` can be on another filesystem. Symlinks
+    /// are copied instead of being followed, and hard links are copied as separate files. An entry
+    /// which can't be copied doesn't stop the others, the failed entries are returned. It fails
+    /// only if `self` can't be copied at all.
+    pub fn copy_tree(&self, dst: &VfsPath) -> VfsResult<Vec<VfsCopyError>> {
+        let src = self.resolve(false)?;
+        if src.inode()?.inode_type() == VfsNodeType::Dir {
+            // a directory can't be copied into itself
+            let dst_parent = dst.get_parent("copy tree")?;
+            if is_ancestor(&src, dst_parent) {
+                return Err(VfsError::Invalid);
+            }
+        }
+        let mut errors = Vec::new();
+        self.copy_entry(dst, &mut errors)?;
+        Ok(errors)
+    }
+
+    fn copy_entry(&self, dst: &VfsPath, errors: &mut Vec<VfsCopyError>) -> VfsResult<()> {
+        let src = self.resolve(false)?;
+        let src_inode = src.inode()?;
+        let stat = src_inode.get_attr()?;
+        let perm = VfsNodePerm::from_bits_truncate(stat.st_mode as u16);
+        let ty = src_inode.inode_type();
+        let dst_dentry = match ty {
+            VfsNodeType::Dir => match dst.resolve(false) {
+                Ok(dentry) => {
+                    if dentry.inode()?.inode_type() != VfsNodeType::Dir {
+                        return Err(VfsError::EExist);
+                    }
+                    dentry
+                }
+                Err(VfsError::NoEntry) => dst.create(ty, perm, None, "copy tree")?,
+                Err(e) => return Err(e),
+            },
+            VfsNodeType::SymLink => {
+                let mut buf = vec![0; self.path_max];
+                let len = src_inode.readlink(&mut buf)?;
+                let target = core::str::from_utf8(&buf[..len]).map_err(|_| VfsError::Invalid)?;
+                dst.symlink(target)?;
+                dst.resolve(false)?
+            }
+            VfsNodeType::File => {
+                let dentry = dst.create(ty, perm, None, "copy tree")?;
+                let dst_inode = dentry.inode()?;
+                let mut offset = 0;
+                while offset < stat.st_size {
+                    let len = (stat.st_size - offset).min(usize::MAX as u64) as usize;
+                    let copied = copy_file_range(
+                        src_inode.as_ref(),
+                        offset,
+                        dst_inode.as_ref(),
+                        offset,
+                        len,
+                        VFS_COPY_CHUNK_SIZE,
+                    )?;
+                    if copied == 0 {
+                        break;
+                    }
+                    offset += copied as u64;
+                }
+                dentry
+            }
+            _ => dst.create(ty, perm, Some(stat.st_rdev), "copy tree")?,
+        };
+        if ty == VfsNodeType::Dir {
+            for entry in dir_entries(&src_inode)? {
+                let path = self.join(&entry.name)?;
+                let res = dst
+                    .join(&entry.name)
+                    .and_then(|dst| path.copy_entry(&dst, errors));
+                if let Err(error) = res {
+                    errors.push(VfsCopyError {
+                        path: path.path,
+                        error,
+                    });
+                }
+            }
+        }
+        // like `cp -a`, only root keeps the owners
+        let keep_owner = dst
+            .context
+            .as_ref()
+            .is_none_or(|context| context.cred.capable(VfsCapabilities::CHOWN));
+        copy_metadata(&src_inode, &dst_dentry.inode()?, &stat, keep_owner)
+    }
+}
+
+/// An entry which can't be copied by [`VfsPath::copy_tree`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsCopyError {
+    /// The path of the source entry
+    pub path: String,
+    pub error: VfsError,
+}
+
+/// Copy the extended attributes, the permissions, the timestamps and the owner if `keep_owner`
+/// of `src` to `dst`
+///
+/// The permissions were changed by the umask or a default ACL when `dst` was created, so they are
+/// set again.
+fn copy_metadata(
+    src: &Arc<dyn VfsInode>,
+    dst: &Arc<dyn VfsInode>,
+    stat: &VfsFileStat,
+    keep_owner: bool,
+) -> VfsResult<()> {
+    match src.list_xattr() {
+        Ok(names) => {
+            for name in names {
+                dst.set_xattr(&name, &src.get_xattr(&name)?)?;
+            }
+        }
+        Err(VfsError::NoSys) => {}
+        Err(e) => return Err(e),
+    }
+    // the copy was changed when it was created, so its ctime is the current time
+    let now = dst.get_attr()?.st_ctime;
+    let mut attr = InodeAttr::new(now);
+    if keep_owner {
+        attr = attr.with_owner(stat.st_uid, stat.st_gid);
+    }
+    // the permissions of a symlink can't be changed
+    if src.inode_type() != VfsNodeType::SymLink {
+        attr = attr.with_mode(stat.st_mode);
+    }
+    match dst.set_attr(attr) {
+        Ok(()) | Err(VfsError::NoSys) => {}
+        Err(e) => return Err(e),
+    }
+    let times = VfsTimes::new(
+        VfsTimeSet::Set(stat.st_atime),
+        VfsTimeSet::Set(stat.st_mtime),
+    );
+    match dst.set_times(times, now) {
+        Err(VfsError::NoSys) => Ok(()),
+        res => res,
+    }
+}
+
+/// Read all entries of a directory except `.` and `..`
+fn dir_entries(inode: &Arc<dyn VfsInode>) -> VfsResult<Vec<VfsDirEntry>> {
+    let mut entries = Vec::new();
+    let mut cookie = 0;
+    while let Some(entry) = inode.readdir(cookie)? {
+        cookie = entry.off;
+        if entry.name != "." && entry.name != ".." {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Whether `ancestor` is `dentry` or one of its parents
+fn is_ancestor(ancestor: &Arc<dyn VfsDentry>, dentry: Arc<dyn VfsDentry>) -> bool {
+    let mut current = dentry;
+    loop {
+        if is_same_dentry(&current, ancestor) {
+            return true;
+        }
+        match real_dentry_up(current).parent() {
+            Some(parent) => current = parent,
+            None => return false,
+        }
     }
 }
 
@@ -550,6 +1187,207 @@ fn checkout_write_perm(dentry: &Arc<dyn VfsDentry>) -> VfsResult<()> {
     }
 }
 
+/// Check whether the caller can access the inode, like `inode_permission` on Linux
+///
+/// The access ACL is checked if the filesystem supports ACLs, otherwise the permission bits.
+/// [`VfsCapabilities::DAC_OVERRIDE`] bypasses the checks, but a file is only executed if it has
+/// an execute bit.
+pub fn check_permission(
+    inode: &Arc<dyn VfsInode>,
+    context: &SysContext,
+    want: VfsAclPerm,
+) -> VfsResult<()> {
+    let stat = inode.get_attr()?;
+    let cred = &context.cred;
+    if cred.capable(VfsCapabilities::DAC_OVERRIDE) {
+        let exec = VfsInodeMode::OWNER_EXEC | VfsInodeMode::GROUP_EXEC | VfsInodeMode::OTHER_EXEC;
+        if !want.contains(VfsAclPerm::EXECUTE)
+            || inode.inode_type() == VfsNodeType::Dir
+            || stat.st_mode & exec.bits() != 0
+        {
+            return Ok(());
+        }
+        return Err(VfsError::Access);
+    }
+    let acl = match get_acl(inode, XATTR_POSIX_ACL_ACCESS)? {
+        // the mode may be changed by set_attr after the ACL is set
+        Some(mut acl) => {
+            acl.chmod(stat.st_mode);
+            acl
+        }
+        None => VfsAcl::from_mode(stat.st_mode),
+    };
+    let groups = cred.all_groups();
+    if acl.permission(stat.st_uid, stat.st_gid, cred.fsuid, &groups, want) {
+        Ok(())
+    } else {
+        Err(VfsError::Access)
+    }
+}
+
+/// Check whether the caller can add and remove the entries of the directory
+fn check_dir_write(dir: &Arc<dyn VfsDentry>, context: &SysContext) -> VfsResult<()> {
+    check_permission(
+        &dir.inode()?,
+        context,
+        VfsAclPerm::WRITE | VfsAclPerm::EXECUTE,
+    )
+}
+
+/// Give the ACLs inherited from the default ACL of the parent to a new inode
+///
+/// A new directory gets the default ACL too, so that its entries inherit it.
+fn inherit_acl(inode: &Arc<dyn VfsInode>, default: VfsAcl, perm: VfsNodePerm) -> VfsResult<()> {
+    if inode.inode_type() == VfsNodeType::Dir {
+        inode.set_xattr(XATTR_POSIX_ACL_DEFAULT, &default.to_xattr())?;
+    }
+    let mut access = default;
+    access.create_masq(perm.bits() as u32);
+    if !access.is_minimal() {
+        inode.set_xattr(XATTR_POSIX_ACL_ACCESS, &access.to_xattr())?;
+    }
+    Ok(())
+}
+
+/// Check an ACL before it is set as the xattr `key`
+///
+/// Return the change of the permission bits if an access ACL changes them.
+fn check_acl_xattr(
+    inode: &Arc<dyn VfsInode>,
+    context: Option<&SysContext>,
+    key: &str,
+    value: &[u8],
+) -> VfsResult<Option<InodeAttr>> {
+    if !acl_enabled(inode) {
+        return Err(VfsError::EOPNOTSUPP);
+    }
+    let acl = VfsAcl::from_xattr(value)?;
+    let stat = inode.get_attr()?;
+    if let Some(context) = context {
+        check_owner(&context.cred, stat.st_uid)?;
+    }
+    if key == XATTR_POSIX_ACL_DEFAULT {
+        if inode.inode_type() != VfsNodeType::Dir {
+            return Err(VfsError::Access);
+        }
+        return Ok(None);
+    }
+    let mode = stat.st_mode & !0o777 | acl.mode();
+    if mode == stat.st_mode {
+        return Ok(None);
+    }
+    Ok(Some(InodeAttr::new(stat.st_ctime).with_mode(mode)))
+}
+
+/// Call the rename hook, the caller of a rename is given by its own context
+fn rename_hook(
+    context: &SysContext,
+    old_parent: &Arc<dyn VfsDentry>,
+    old_dt: &Arc<dyn VfsDentry>,
+    new_parent: &Arc<dyn VfsDentry>,
+    new_path: &VfsPath,
+) -> VfsResult<()> {
+    match security_hooks() {
+        Some(hooks) => hooks.rename(
+            Some(&context.cred),
+            old_parent,
+            old_dt,
+            new_parent,
+            &new_path.filename(),
+        ),
+        None => Ok(()),
+    }
+}
+
+/// Check whether the caller can remove or rename an entry of the directory
+///
+/// The entries of a sticky directory can only be removed or renamed by the owner of the
+/// entry, the owner of the directory and the callers with [`VfsCapabilities::FOWNER`].
+fn check_sticky(
+    dir: &Arc<dyn VfsDentry>,
+    entry: &Arc<dyn VfsDentry>,
+    context: &SysContext,
+) -> VfsResult<()> {
+    let dir_stat = dir.inode()?.get_attr()?;
+    if dir_stat.st_mode & VfsInodeMode::STICKY.bits() == 0 || context.cred.fsuid == dir_stat.st_uid
+    {
+        return Ok(());
+    }
+    check_owner(&context.cred, entry.inode()?.get_attr()?.st_uid)
+}
+
+/// Check whether the caller can set the xattr `key`, like `xattr_permission` on Linux
+///
+/// The `trusted.` and `security.` xattrs need [`VfsCapabilities::SYS_ADMIN`], and the `user.`
+/// xattrs need the write permission of a regular file or a directory. The others are left to
+/// the filesystem.
+pub fn xattr_permission(
+    inode: &Arc<dyn VfsInode>,
+    context: &SysContext,
+    key: &str,
+) -> VfsResult<()> {
+    if key.starts_with("trusted.") || key.starts_with("security.") {
+        if !context.cred.capable(VfsCapabilities::SYS_ADMIN) {
+            return Err(VfsError::PermissionDenied);
+        }
+    } else if key.starts_with("user.") {
+        if !matches!(inode.inode_type(), VfsNodeType::File | VfsNodeType::Dir) {
+            return Err(VfsError::PermissionDenied);
+        }
+        check_permission(inode, context, VfsAclPerm::WRITE)?;
+    }
+    Ok(())
+}
+
+/// Check whether the caller owns the inode or has [`VfsCapabilities::FOWNER`], like
+/// `inode_owner_or_capable` on Linux
+fn check_owner(cred: &VfsCred, owner: u32) -> VfsResult<()> {
+    if cred.fsuid == owner || cred.capable(VfsCapabilities::FOWNER) {
+        Ok(())
+    } else {
+        Err(VfsError::PermissionDenied)
+    }
+}
+
+/// The setuid and setgid bits of `mode` which are cleared when a file is changed by a caller
+/// without [`VfsCapabilities::FSETID`]
+///
+/// The setgid bit without the group execute bit means mandatory locking instead, so it is kept.
+fn privs_to_remove(mode: u32) -> u32 {
+    let mut privs = mode & VfsInodeMode::SET_UID.bits();
+    let group_exec = VfsInodeMode::SET_GID | VfsInodeMode::GROUP_EXEC;
+    if mode & group_exec.bits() == group_exec.bits() {
+        privs |= VfsInodeMode::SET_GID.bits();
+    }
+    privs
+}
+
+/// Clear the setuid and setgid bits of a regular file before it is written by the caller, like
+/// `file_remove_privs` on Linux
+///
+/// The callers with [`VfsCapabilities::FSETID`] keep the bits. [`VfsPath::write_at`] calls it, a
+/// write through an open file should call it too.
+pub fn file_remove_privs(inode: &Arc<dyn VfsInode>, context: &SysContext) -> VfsResult<()> {
+    if context.cred.capable(VfsCapabilities::FSETID) || inode.inode_type() != VfsNodeType::File {
+        return Ok(());
+    }
+    let stat = inode.get_attr()?;
+    let privs = privs_to_remove(stat.st_mode);
+    if privs == 0 {
+        return Ok(());
+    }
+    inode.set_attr(InodeAttr::new(stat.st_ctime).with_mode(stat.st_mode & !privs))
+}
+
+/// Check the length of a new name against the limit of the filesystem
+fn check_name_len(parent: &Arc<dyn VfsDentry>, name: &str) -> VfsResult<()> {
+    let stat = parent.inode()?.get_super_block()?.stat_fs()?;
+    if stat.f_namelen > 0 && name.len() > stat.f_namelen as usize {
+        return Err(VfsError::NameTooLong);
+    }
+    Ok(())
+}
+
 /// Check whether the dentry is busy which means it is cwd or root
 fn checkout_busy(dentry: &Arc<dyn VfsDentry>, context: &SysContext) -> VfsResult<()> {
     let b = dentry.inode()?.inode_type() == VfsNodeType::Dir && Arc::ptr_eq(dentry, &context.cwd)
@@ -559,17 +1397,17 @@ fn checkout_busy(dentry: &Arc<dyn VfsDentry>, context: &SysContext) -> VfsResult
     }
     Ok(())
 }
-/// Check whether the two dentry is in the same fs
+/// Check whether the two dentry is in the same fs, [`VfsError::EXDEV`] is returned if not
 fn check_same_fs(dentry1: &Arc<dyn VfsDentry>, dentry2: &Arc<dyn VfsDentry>) -> VfsResult<()> {
     let fs1 = dentry1.inode()?.get_super_block()?;
     let fs2 = dentry2.inode()?.get_super_block()?;
     if !Arc::ptr_eq(&fs1, &fs2) {
-        return Err(VfsError::Invalid);
+        return Err(VfsError::EXDEV);
     }
     Ok(())
 }
 
-fn real_dentry_down(dentry: Arc<dyn VfsDentry>) -> Arc<dyn VfsDentry> {
+pub(crate) fn real_dentry_down(dentry: Arc<dyn VfsDentry>) -> Arc<dyn VfsDentry> {
     if dentry.is_mount_point() {
         let mnt = dentry.mount_point().unwrap();
         real_dentry_down(mnt.root)
@@ -591,6 +1429,51 @@ fn real_dentry_up(dentry: Arc<dyn VfsDentry>) -> Arc<dyn VfsDentry> {
     }
 }
 
+/// Whether the two dentries refer to the same directory, a mount point and
+/// the root of the filesystem mounted on it are treated as the same one
+pub(crate) fn is_same_dentry(dentry1: &Arc<dyn VfsDentry>, dentry2: &Arc<dyn VfsDentry>) -> bool {
+    Arc::ptr_eq(
+        &real_dentry_down(dentry1.clone()),
+        &real_dentry_down(dentry2.clone()),
+    )
+}
+
+/// Render the path of the dentry relative to the root of the process
+///
+/// This is what getcwd and /proc/<pid>/fd see. If the dentry is not below the
+/// root of the process, the path from the global root is prefixed with
+/// `(unreachable)` like Linux.
+pub fn d_path(dentry: &Arc<dyn VfsDentry>, context: &SysContext) -> String {
+    let mut names = Vec::new();
+    let mut current = dentry.clone();
+    let reachable = loop {
+        if is_same_dentry(&current, &context.root) {
+            break true;
+        }
+        // the root of a mounted filesystem, go to the mount point
+        let real = real_dentry_up(current);
+        match real.parent() {
+            Some(parent) => {
+                names.push(real.name());
+                current = parent;
+            }
+            None => break false,
+        }
+    };
+    let mut path = String::new();
+    if !reachable {
+        path.push_str("(unreachable)");
+    }
+    for name in names.iter().rev() {
+        path.push('/');
+        path.push_str(name);
+    }
+    if names.is_empty() {
+        path.push('/');
+    }
+    path
+}
+
 fn split_path(path: &str) -> (&str, Option<&str>) {
     let trimmed_path = path.trim_start_matches('/');
     trimmed_path.find('/').map_or((trimmed_path, None), |n| {
@@ -697,51 +1580,34 @@ pub fn print_fs_tree(
     prefix: String,
     recursive: bool,
 ) -> Result<(), Box<dyn Error>> {
-    let mut children = root.inode()?.children();
-    let mut child = children.next();
-    while let Some(c) = child {
-        let name = c.name;
-        let inode_type = c.ty;
-        let inode = root.inode()?.lookup(&name)?;
-        let stat = inode.get_attr()?;
-        let perm = VfsNodePerm::from_bits_truncate(stat.st_mode as u16);
+    let max_depth = if recursive { usize::MAX } else { 1 };
+    for entry in VfsWalker::new(root).max_depth(max_depth) {
+        let entry = entry.map_err(|e| e.error)?;
+        if entry.depth == 0 {
+            continue;
+        }
+        let perm = VfsNodePerm::from_bits_truncate(entry.stat.st_mode as u16);
         let rwx_buf = perm.rwx_buf();
         let rwx = core::str::from_utf8(&rwx_buf)?;
 
-        let mut buf = [0u8; 20];
-        let option = if inode_type == VfsNodeType::SymLink {
-            let r = inode.readlink(&mut buf)?;
-            let content = core::str::from_utf8(&buf[..r])?;
-            "-> ".to_string() + content
+        let option = if entry.ty == VfsNodeType::SymLink {
+            "-> ".to_string() + &entry.read_link()?
         } else {
             "".to_string()
         };
         write(
             output,
             format_args!(
-                "{}{}{} {:>8} {} {}\n",
+                "{}{}{}{} {:>8} {} {}\n",
                 prefix,
-                inode_type.as_char(),
+                "  ".repeat(entry.depth - 1),
+                entry.ty.as_char(),
                 rwx,
-                size_to_str(stat.st_size),
-                name,
+                size_to_str(entry.stat.st_size),
+                entry.name,
                 option
             ),
-        )
-        .unwrap();
-
-        if inode_type == VfsNodeType::Dir && name != "." && name != ".." && recursive {
-            let d = root.find(&name);
-            let sub_dt = if let Some(d) = d {
-                d
-            } else {
-                let d = root.inode()?.lookup(&name)?;
-                root.i_insert(&name, d)?
-            };
-            let sub_dt = real_dentry_down(sub_dt);
-            print_fs_tree(output, sub_dt, prefix.clone() + "  ", recursive)?;
-        }
-        child = children.next();
+        )?;
     }
     Ok(())
 }
@@ -752,14 +1618,14 @@ pub trait DirIter {
 
 struct DirIterImpl {
     inode: Arc<dyn VfsInode>,
-    index: usize,
+    cookie: u64,
 }
 impl Iterator for DirIterImpl {
     type Item = VfsDirEntry;
     fn next(&mut self) -> Option<Self::Item> {
-        let x = self.inode.readdir(self.index).unwrap();
+        let x = self.inode.readdir(self.cookie).unwrap();
         if let Some(x) = x {
-            self.index += 1;
+            self.cookie = x.off;
             Some(x)
         } else {
             None
@@ -771,7 +1637,7 @@ impl DirIter for Arc<dyn VfsInode> {
     fn children(&self) -> Box<dyn Iterator<Item = VfsDirEntry>> {
         Box::new(DirIterImpl {
             inode: self.clone(),
-            index: 0,
+            cookie: 0,
         })
     }
 }
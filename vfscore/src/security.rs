@@ -0,0 +1,150 @@
+//! Hooks for a security module, like the LSM hooks of Linux.
+//!
+//! A mandatory access control can be plugged in by [`set_security_hooks`] at boot. [`VfsPath`]
+//! calls the hooks before the operations, after the permission checks pass, and an error returned
+//! by a hook denies the operation. The hooks are called on every filesystem, a filesystem can't
+//! opt out of them.
+//!
+//! The credentials are `None` if the [`VfsPath`] has no context, which means the kernel itself.
+//!
+//! [`VfsPath`]: crate::path::VfsPath
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use crate::{
+    cred::VfsCred, dentry::VfsDentry, error::VfsError, fstype::FileSystemFlags, inode::InodeAttr,
+    utils::VfsInodeMode, VfsResult,
+};
+
+/// The hooks of a security module, all of them allow everything by default
+pub trait VfsSecurityHooks: Send + Sync {
+    /// Called before `name` is looked up in `dir`
+    fn lookup(
+        &self,
+        _cred: Option<&VfsCred>,
+        _dir: &Arc<dyn VfsDentry>,
+        _name: &str,
+    ) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Called before an existing file is opened
+    fn open(&self, _cred: Option<&VfsCred>, _dentry: &Arc<dyn VfsDentry>) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Called before a file, a directory, a special file or a symlink is created in `dir`
+    fn create(
+        &self,
+        _cred: Option<&VfsCred>,
+        _dir: &Arc<dyn VfsDentry>,
+        _name: &str,
+        _mode: VfsInodeMode,
+    ) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Called before a hard link to `old` is created in `dir`
+    fn link(
+        &self,
+        _cred: Option<&VfsCred>,
+        _old: &Arc<dyn VfsDentry>,
+        _dir: &Arc<dyn VfsDentry>,
+        _name: &str,
+    ) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Called before a file or a directory is removed from `dir`
+    fn unlink(
+        &self,
+        _cred: Option<&VfsCred>,
+        _dir: &Arc<dyn VfsDentry>,
+        _dentry: &Arc<dyn VfsDentry>,
+    ) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Called before `old` is renamed to `new_name` in `new_dir`
+    fn rename(
+        &self,
+        _cred: Option<&VfsCred>,
+        _old_dir: &Arc<dyn VfsDentry>,
+        _old: &Arc<dyn VfsDentry>,
+        _new_dir: &Arc<dyn VfsDentry>,
+        _new_name: &str,
+    ) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Called before the attributes are changed, the fields in the mask of `attr` are changed
+    fn setattr(
+        &self,
+        _cred: Option<&VfsCred>,
+        _dentry: &Arc<dyn VfsDentry>,
+        _attr: &InodeAttr,
+    ) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Called before an xattr is set
+    fn setxattr(
+        &self,
+        _cred: Option<&VfsCred>,
+        _dentry: &Arc<dyn VfsDentry>,
+        _name: &str,
+        _value: &[u8],
+    ) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Called before `root` is mounted on `mount_point`
+    fn mount(
+        &self,
+        _cred: Option<&VfsCred>,
+        _mount_point: &Arc<dyn VfsDentry>,
+        _root: &Arc<dyn VfsDentry>,
+        _flags: u32,
+    ) -> VfsResult<()> {
+        Ok(())
+    }
+    /// Called before the filesystem mounted on `mount_point` is unmounted
+    fn umount(&self, _cred: Option<&VfsCred>, _mount_point: &Arc<dyn VfsDentry>) -> VfsResult<()> {
+        Ok(())
+    }
+}
+
+static HOOKS: AtomicPtr<&'static dyn VfsSecurityHooks> = AtomicPtr::new(ptr::null_mut());
+
+/// Register the security hooks, they can only be registered once
+///
+/// Return [`VfsError::EBUSY`] if hooks are registered already.
+pub fn set_security_hooks(hooks: &'static dyn VfsSecurityHooks) -> VfsResult<()> {
+    let new = Box::into_raw(Box::new(hooks));
+    match HOOKS.compare_exchange(ptr::null_mut(), new, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => Ok(()),
+        Err(_) => {
+            // SAFETY: `new` was not published, so it is only owned here
+            drop(unsafe { Box::from_raw(new) });
+            Err(VfsError::EBUSY)
+        }
+    }
+}
+
+/// The registered security hooks
+pub fn security_hooks() -> Option<&'static dyn VfsSecurityHooks> {
+    // SAFETY: the pointer is either null or set once to a leaked box which is never freed
+    unsafe { HOOKS.load(Ordering::Acquire).as_ref() }.copied()
+}
+
+/// Whether fanotify-like permission events can be sent for `dentry`
+///
+/// They are not sent on the filesystems with [`FileSystemFlags::DISALLOW_NOTIFY_PERM`]. This
+/// doesn't affect the hooks of the security module.
+pub fn perm_events_allowed(dentry: &Arc<dyn VfsDentry>) -> bool {
+    let disallowed = dentry
+        .inode()
+        .and_then(|inode| inode.get_super_block())
+        .map(|sb| {
+            sb.fs_type()
+                .fs_flag()
+                .contains(FileSystemFlags::DISALLOW_NOTIFY_PERM)
+        })
+        .unwrap_or(false);
+    !disallowed
+}
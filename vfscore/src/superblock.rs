@@ -2,7 +2,12 @@ use alloc::sync::Arc;
 
 use downcast_rs::{impl_downcast, DowncastSync};
 
-use crate::{fstype::VfsFsType, inode::VfsInode, utils::VfsFsStat, VfsResult};
+use crate::{
+    fstype::VfsFsType,
+    inode::VfsInode,
+    utils::{VfsFsStat, VfsMountFlags},
+    VfsResult,
+};
 
 /// Type of superblock keying.
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -39,6 +44,11 @@ pub trait VfsSuperBlock: Send + Sync + DowncastSync {
 
     /// Get the root inode of this super block
     fn root_inode(&self) -> VfsResult<Arc<dyn VfsInode>>;
+
+    /// Get the flags this super block was mounted with
+    fn mount_flags(&self) -> VfsMountFlags {
+        VfsMountFlags::empty()
+    }
 }
 
 impl_downcast!(sync  VfsSuperBlock);
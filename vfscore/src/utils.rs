@@ -3,6 +3,8 @@ use alloc::string::String;
 use bitflags::bitflags;
 #[cfg(feature = "linux_error")]
 use pconst::io::FileStat;
+
+use crate::{error::VfsError, VfsResult};
 bitflags! {
     pub struct VfsInodeMode: u32 {
         /// Type
@@ -185,6 +187,13 @@ bitflags::bitflags! {
         const OTHER_WRITE = 0o2;
         /// Others have execute permission.
         const OTHER_EXEC = 0o1;
+
+        /// Set-user-ID on execution.
+        const SET_UID = 0o4000;
+        /// Set-group-ID on execution, or the new inodes inherit the group of a directory.
+        const SET_GID = 0o2000;
+        /// Only the owners can remove or rename the entries of a directory.
+        const STICKY = 0o1000;
     }
 }
 
@@ -199,34 +208,44 @@ impl From<&str> for VfsNodePerm {
         if bytes[1] == b'w' {
             perm |= VfsNodePerm::OWNER_WRITE;
         }
-        if bytes[2] == b'x' {
+        if matches!(bytes[2], b'x' | b's') {
             perm |= VfsNodePerm::OWNER_EXEC;
         }
+        if matches!(bytes[2], b's' | b'S') {
+            perm |= VfsNodePerm::SET_UID;
+        }
         if bytes[3] == b'r' {
             perm |= VfsNodePerm::GROUP_READ;
         }
         if bytes[4] == b'w' {
             perm |= VfsNodePerm::GROUP_WRITE;
         }
-        if bytes[5] == b'x' {
+        if matches!(bytes[5], b'x' | b's') {
             perm |= VfsNodePerm::GROUP_EXEC;
         }
+        if matches!(bytes[5], b's' | b'S') {
+            perm |= VfsNodePerm::SET_GID;
+        }
         if bytes[6] == b'r' {
             perm |= VfsNodePerm::OTHER_READ;
         }
         if bytes[7] == b'w' {
             perm |= VfsNodePerm::OTHER_WRITE;
         }
-        if bytes[8] == b'x' {
+        if matches!(bytes[8], b'x' | b't') {
             perm |= VfsNodePerm::OTHER_EXEC;
         }
+        if matches!(bytes[8], b't' | b'T') {
+            perm |= VfsNodePerm::STICKY;
+        }
         perm
     }
 }
 impl VfsNodePerm {
     /// Returns a 9-bytes string representation of the permission.
     ///
-    /// For example, `0o755` is represented as `rwxr-xr-x`.
+    /// For example, `0o755` is represented as `rwxr-xr-x`. The special bits are shown in the
+    /// execute columns like `ls`, so `0o4755` is `rwsr-xr-x` and `0o1777` is `rwxrwxrwt`.
     pub const fn rwx_buf(&self) -> [u8; 9] {
         let mut perm = [b'-'; 9];
         if self.contains(Self::OWNER_READ) {
@@ -256,6 +275,15 @@ impl VfsNodePerm {
         if self.contains(Self::OTHER_EXEC) {
             perm[8] = b'x';
         }
+        if self.contains(Self::SET_UID) {
+            perm[2] = if perm[2] == b'x' { b's' } else { b'S' };
+        }
+        if self.contains(Self::SET_GID) {
+            perm[5] = if perm[5] == b'x' { b's' } else { b'S' };
+        }
+        if self.contains(Self::STICKY) {
+            perm[8] = if perm[8] == b'x' { b't' } else { b'T' };
+        }
         perm
     }
     /// Returns the default permission for a file.
@@ -290,6 +318,11 @@ fn test_perm_from_str() {
     assert_eq!(perm, VfsNodePerm::from_bits_truncate(0o444));
     let perm: VfsNodePerm = "r--------".into();
     assert_eq!(perm, VfsNodePerm::from_bits_truncate(0o400));
+    let perm: VfsNodePerm = "rwsr-Sr-t".into();
+    assert_eq!(perm, VfsNodePerm::from_bits_truncate(0o7745));
+    assert_eq!(&perm.rwx_buf(), b"rwsr-Sr-t");
+    let perm: VfsNodePerm = "rwxrwxrwT".into();
+    assert_eq!(perm, VfsNodePerm::from_bits_truncate(0o1776));
 }
 
 impl From<VfsInodeMode> for VfsNodeType {
@@ -332,6 +365,95 @@ impl VfsInodeMode {
 
 #[cfg(test)]
 mod tests {
+    #[test]
+    fn relatime() {
+        use super::*;
+        let t = |sec| VfsTimeSpec::new(sec, 0);
+        let relatime = VfsMountFlags::MS_RELATIME;
+        assert!(relatime.atime_needs_update(false, t(10), t(20), t(20), t(30)));
+        assert!(!relatime.atime_needs_update(false, t(30), t(20), t(20), t(40)));
+        assert!(relatime.atime_needs_update(false, t(30), t(20), t(20), t(30 + 86400)));
+        let strict = VfsMountFlags::MS_STRICTATIME;
+        assert!(strict.atime_needs_update(false, t(30), t(20), t(20), t(40)));
+        let noatime = VfsMountFlags::MS_NOATIME | VfsMountFlags::MS_STRICTATIME;
+        assert!(!noatime.atime_needs_update(false, t(10), t(20), t(20), t(30)));
+        let nodiratime = VfsMountFlags::MS_NODEIRATIME;
+        assert!(!nodiratime.atime_needs_update(true, t(10), t(20), t(20), t(30)));
+    }
+    #[test]
+    fn utimensat_times() {
+        use super::*;
+        let now = VfsTimeSpec::new(10, 0);
+        let times = VfsTimes::from_utimensat([
+            VfsTimeSpec::new(0, UTIME_OMIT),
+            VfsTimeSpec::new(0, UTIME_NOW),
+        ])
+        .unwrap();
+        let (mut atime, mut mtime, mut ctime) = (
+            VfsTimeSpec::new(1, 0),
+            VfsTimeSpec::default(),
+            VfsTimeSpec::default(),
+        );
+        times.apply(&mut atime, &mut mtime, &mut ctime, now);
+        assert_eq!((atime, mtime, ctime), (VfsTimeSpec::new(1, 0), now, now));
+
+        let omit = VfsTimes::default();
+        assert!(omit.is_omit());
+        omit.apply(&mut atime, &mut mtime, &mut ctime, VfsTimeSpec::new(20, 0));
+        assert_eq!(ctime, now);
+        assert_eq!(
+            VfsTimeSet::from_utimensat(VfsTimeSpec::new(0, 1_000_000_000)),
+            Err(VfsError::Invalid)
+        );
+    }
+    #[test]
+    fn falloc_zero_fill() {
+        use super::*;
+        let fill = |mode: VfsFallocFlags, offset, len, size| {
+            let mut writes = Vec::new();
+            mode.zero_fill(offset, len, size, |pos, buf| {
+                writes.push((pos, buf.len()));
+                Ok(buf.len())
+            })
+            .map(|_| writes)
+        };
+        let empty = VfsFallocFlags::empty();
+        assert_eq!(
+            fill(empty, 0, 5000, 100),
+            Ok(vec![(100, 4096), (4196, 804)])
+        );
+        assert_eq!(fill(empty, 0, 100, 100), Ok(vec![]));
+        let keep = VfsFallocFlags::KEEP_SIZE;
+        assert_eq!(fill(keep, 0, 200, 100), Err(VfsError::EOPNOTSUPP));
+        let zero = VfsFallocFlags::ZERO_RANGE;
+        assert_eq!(fill(zero, 10, 20, 100), Ok(vec![(10, 20)]));
+        assert_eq!(fill(zero | keep, 50, 100, 100), Ok(vec![(50, 50)]));
+        let punch = VfsFallocFlags::PUNCH_HOLE | keep;
+        assert_eq!(fill(punch, 0, 10, 100), Err(VfsError::EOPNOTSUPP));
+        let full = empty.zero_fill(0, 10, 0, |_, _| Ok(0));
+        assert_eq!(full, Err(VfsError::NoSpace));
+    }
+    #[test]
+    fn statx_from_stat() {
+        use super::*;
+        assert_eq!(core::mem::size_of::<VfsStatx>(), 256);
+        let stat = VfsFileStat {
+            st_rdev: (8 << 8) | 1,
+            st_size: 10,
+            st_mtime: VfsTimeSpec::new(1, 2),
+            ..Default::default()
+        };
+        let mut statx = VfsStatx::from(stat);
+        assert_eq!((statx.stx_rdev_major, statx.stx_rdev_minor), (8, 1));
+        assert_eq!(statx.stx_mtime.tv_nsec, 2);
+        statx.set_btime(VfsTimeSpec::new(3, 0));
+        assert!(VfsStatxMask::from_bits_truncate(statx.stx_mask).contains(VfsStatxMask::BTIME));
+        statx.set_attributes(
+            VfsStatxAttr::IMMUTABLE | VfsStatxAttr::NODUMP,
+            VfsStatxAttr::IMMUTABLE | VfsStatxAttr::APPEND,
+        );
+        assert_eq!(statx.stx_attributes, VfsStatxAttr::IMMUTABLE.bits());
+    }
     #[test]
     fn inode_mode2node_type() {
         use super::*;
@@ -358,10 +480,87 @@ impl VfsTimeSpec {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
-pub enum VfsTime {
-    AccessTime(VfsTimeSpec),
-    ModifiedTime(VfsTimeSpec),
+/// The `nsec` of utimensat(2) which sets the time to the current time
+pub const UTIME_NOW: u64 = (1 << 30) - 1;
+/// The `nsec` of utimensat(2) which leaves the time unchanged
+pub const UTIME_OMIT: u64 = (1 << 30) - 2;
+
+/// The new value of a timestamp
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum VfsTimeSet {
+    /// Leave the time unchanged
+    #[default]
+    Omit,
+    /// Set the time to the current time
+    Now,
+    Set(VfsTimeSpec),
+}
+
+impl VfsTimeSet {
+    /// Parse a timespec of utimensat(2), whose `nsec` may be [`UTIME_NOW`] or [`UTIME_OMIT`]
+    pub fn from_utimensat(time: VfsTimeSpec) -> VfsResult<Self> {
+        match time.nsec {
+            UTIME_NOW => Ok(Self::Now),
+            UTIME_OMIT => Ok(Self::Omit),
+            nsec if nsec < 1_000_000_000 => Ok(Self::Set(time)),
+            _ => Err(VfsError::Invalid),
+        }
+    }
+    /// Return the new time, or none if the time is unchanged
+    pub fn resolve(self, now: VfsTimeSpec) -> Option<VfsTimeSpec> {
+        match self {
+            Self::Omit => None,
+            Self::Now => Some(now),
+            Self::Set(time) => Some(time),
+        }
+    }
+}
+
+/// The access and modification times to set, see utimensat(2)
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct VfsTimes {
+    pub atime: VfsTimeSet,
+    pub mtime: VfsTimeSet,
+}
+
+impl VfsTimes {
+    pub fn new(atime: VfsTimeSet, mtime: VfsTimeSet) -> Self {
+        Self { atime, mtime }
+    }
+    /// Set both times to the current time, like utimensat(2) with null `times`
+    pub fn now() -> Self {
+        Self::new(VfsTimeSet::Now, VfsTimeSet::Now)
+    }
+    /// Parse `times[0]` (atime) and `times[1]` (mtime) of utimensat(2)
+    pub fn from_utimensat(times: [VfsTimeSpec; 2]) -> VfsResult<Self> {
+        Ok(Self::new(
+            VfsTimeSet::from_utimensat(times[0])?,
+            VfsTimeSet::from_utimensat(times[1])?,
+        ))
+    }
+    /// Whether both times are left unchanged, then ctime is not updated either
+    pub fn is_omit(&self) -> bool {
+        self.atime == VfsTimeSet::Omit && self.mtime == VfsTimeSet::Omit
+    }
+    /// Update the times of an inode, ctime is set to `now` if anything changes
+    pub fn apply(
+        &self,
+        atime: &mut VfsTimeSpec,
+        mtime: &mut VfsTimeSpec,
+        ctime: &mut VfsTimeSpec,
+        now: VfsTimeSpec,
+    ) {
+        if self.is_omit() {
+            return;
+        }
+        if let Some(t) = self.atime.resolve(now) {
+            *atime = t;
+        }
+        if let Some(t) = self.mtime.resolve(now) {
+            *mtime = t;
+        }
+        *ctime = now;
+    }
 }
 
 #[repr(C)]
@@ -460,6 +659,142 @@ impl From<VfsFileStat> for FileStat {
     }
 }
 
+bitflags! {
+    /// The fields of [`VfsStatx`] which are requested or valid, STATX_*
+    pub struct VfsStatxMask: u32 {
+        const TYPE = 0x0001;
+        const MODE = 0x0002;
+        const NLINK = 0x0004;
+        const UID = 0x0008;
+        const GID = 0x0010;
+        const ATIME = 0x0020;
+        const MTIME = 0x0040;
+        const CTIME = 0x0080;
+        const INO = 0x0100;
+        const SIZE = 0x0200;
+        const BLOCKS = 0x0400;
+        /// The fields of [`VfsFileStat`]
+        const BASIC_STATS = 0x07ff;
+        const BTIME = 0x0800;
+        const MNT_ID = 0x1000;
+    }
+}
+
+bitflags! {
+    /// The attributes of a file, STATX_ATTR_*
+    pub struct VfsStatxAttr: u64 {
+        const COMPRESSED = 0x0004;
+        /// The file can't be modified
+        const IMMUTABLE = 0x0010;
+        /// The file can only be opened in append mode for writing
+        const APPEND = 0x0020;
+        const NODUMP = 0x0040;
+        const ENCRYPTED = 0x0800;
+        const AUTOMOUNT = 0x1000;
+        const MOUNT_ROOT = 0x2000;
+        const VERITY = 0x100000;
+        const DAX = 0x200000;
+    }
+}
+
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct VfsStatxTimestamp {
+    pub tv_sec: i64,
+    pub tv_nsec: u32,
+    pub __reserved: i32,
+}
+
+impl From<VfsTimeSpec> for VfsStatxTimestamp {
+    fn from(value: VfsTimeSpec) -> Self {
+        Self {
+            tv_sec: value.sec as i64,
+            tv_nsec: value.nsec as u32,
+            __reserved: 0,
+        }
+    }
+}
+
+/// The result of statx(2), the layout is the same as `struct statx` of Linux
+#[repr(C)]
+#[derive(Default, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct VfsStatx {
+    /// The valid fields, see [`VfsStatxMask`]
+    pub stx_mask: u32,
+    pub stx_blksize: u32,
+    /// See [`VfsStatxAttr`]
+    pub stx_attributes: u64,
+    pub stx_nlink: u32,
+    pub stx_uid: u32,
+    pub stx_gid: u32,
+    pub stx_mode: u16,
+    pub __spare0: u16,
+    pub stx_ino: u64,
+    pub stx_size: u64,
+    pub stx_blocks: u64,
+    /// The attributes supported by the filesystem
+    pub stx_attributes_mask: u64,
+    pub stx_atime: VfsStatxTimestamp,
+    pub stx_btime: VfsStatxTimestamp,
+    pub stx_ctime: VfsStatxTimestamp,
+    pub stx_mtime: VfsStatxTimestamp,
+    pub stx_rdev_major: u32,
+    pub stx_rdev_minor: u32,
+    pub stx_dev_major: u32,
+    pub stx_dev_minor: u32,
+    pub stx_mnt_id: u64,
+    pub stx_dio_mem_align: u32,
+    pub stx_dio_offset_align: u32,
+    pub __spare3: [u64; 12],
+} //256
+
+impl VfsStatx {
+    /// Set the birth time and mark it valid
+    pub fn set_btime(&mut self, btime: VfsTimeSpec) {
+        self.stx_btime = btime.into();
+        self.stx_mask |= VfsStatxMask::BTIME.bits();
+    }
+    /// Set the attributes and the attributes supported by the filesystem
+    pub fn set_attributes(&mut self, attributes: VfsStatxAttr, supported: VfsStatxAttr) {
+        self.stx_attributes = (attributes & supported).bits();
+        self.stx_attributes_mask = supported.bits();
+    }
+}
+
+/// The major number of a device number, like `major()` of glibc
+fn dev_major(dev: u64) -> u32 {
+    (((dev >> 32) & 0xffff_f000) | ((dev >> 8) & 0x0000_0fff)) as u32
+}
+
+/// The minor number of a device number, like `minor()` of glibc
+fn dev_minor(dev: u64) -> u32 {
+    (((dev >> 12) & 0xffff_ff00) | (dev & 0x0000_00ff)) as u32
+}
+
+impl From<VfsFileStat> for VfsStatx {
+    fn from(value: VfsFileStat) -> Self {
+        Self {
+            stx_mask: VfsStatxMask::BASIC_STATS.bits(),
+            stx_blksize: value.st_blksize,
+            stx_nlink: value.st_nlink,
+            stx_uid: value.st_uid,
+            stx_gid: value.st_gid,
+            stx_mode: value.st_mode as u16,
+            stx_ino: value.st_ino,
+            stx_size: value.st_size,
+            stx_blocks: value.st_blocks,
+            stx_atime: value.st_atime.into(),
+            stx_ctime: value.st_ctime.into(),
+            stx_mtime: value.st_mtime.into(),
+            stx_rdev_major: dev_major(value.st_rdev),
+            stx_rdev_minor: dev_minor(value.st_rdev),
+            stx_dev_major: dev_major(value.st_dev),
+            stx_dev_minor: dev_minor(value.st_dev),
+            ..Default::default()
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct VfsDirEntry {
     /// ino is an inode number
@@ -468,6 +803,8 @@ pub struct VfsDirEntry {
     pub ty: VfsNodeType,
     /// filename (null-terminated)
     pub name: String,
+    /// the cookie of the next entry, like d_off of linux_dirent64
+    pub off: u64,
 }
 
 bitflags! {
@@ -546,6 +883,34 @@ bitflags! {
 
 }
 
+impl VfsMountFlags {
+    /// Whether reading an inode should update its atime, like `atime_needs_update` of Linux.
+    ///
+    /// Without [`VfsMountFlags::MS_STRICTATIME`], the mount is relatime: atime is only updated
+    /// if it is not newer than mtime or ctime, or it is more than one day old.
+    pub fn atime_needs_update(
+        &self,
+        is_dir: bool,
+        atime: VfsTimeSpec,
+        mtime: VfsTimeSpec,
+        ctime: VfsTimeSpec,
+        now: VfsTimeSpec,
+    ) -> bool {
+        if self.intersects(Self::MS_NOATIME | Self::MS_RDONLY)
+            || (is_dir && self.contains(Self::MS_NODEIRATIME))
+        {
+            return false;
+        }
+        if self.contains(Self::MS_STRICTATIME) {
+            return true;
+        }
+        let key = |t: VfsTimeSpec| (t.sec, t.nsec);
+        key(atime) <= key(mtime)
+            || key(atime) <= key(ctime)
+            || now.sec.saturating_sub(atime.sec) >= 24 * 60 * 60
+    }
+}
+
 bitflags! {
      /// renameat flag
     pub struct VfsRenameFlag: u32 {
@@ -558,3 +923,78 @@ bitflags! {
         const RENAME_WHITEOUT = 1 << 2;
     }
 }
+
+bitflags! {
+    /// The mode of fallocate(2)
+    pub struct VfsFallocFlags: u32 {
+        /// Don't change the file size, even if the range extends beyond the end of file
+        const KEEP_SIZE = 0x01;
+        /// Deallocate the range, it reads as zeros after that. Must be used with KEEP_SIZE
+        const PUNCH_HOLE = 0x02;
+        /// Zero the range, the space is allocated
+        const ZERO_RANGE = 0x10;
+    }
+}
+
+impl VfsFallocFlags {
+    /// Check the mode and range like Linux vfs_fallocate
+    pub fn check(&self, offset: u64, len: u64) -> VfsResult<()> {
+        if len == 0 || offset.checked_add(len).is_none() {
+            return Err(VfsError::Invalid);
+        }
+        if self.contains(VfsFallocFlags::PUNCH_HOLE) && !self.contains(VfsFallocFlags::KEEP_SIZE) {
+            return Err(VfsError::EOPNOTSUPP);
+        }
+        if self.contains(VfsFallocFlags::PUNCH_HOLE | VfsFallocFlags::ZERO_RANGE) {
+            return Err(VfsError::EOPNOTSUPP);
+        }
+        Ok(())
+    }
+    /// Allocate the range by writing zeros with `write`, for the filesystems without sparse files
+    ///
+    /// `size` is the size of the file. Holes can't be punched, and the space after the end of
+    /// file can't be allocated with [`VfsFallocFlags::KEEP_SIZE`] unless it is zeroed.
+    pub fn zero_fill<W>(&self, offset: u64, len: u64, size: u64, mut write: W) -> VfsResult<()>
+    where
+        W: FnMut(u64, &[u8]) -> VfsResult<usize>,
+    {
+        self.check(offset, len)?;
+        if self.contains(VfsFallocFlags::PUNCH_HOLE) {
+            return Err(VfsError::EOPNOTSUPP);
+        }
+        let end = offset + len;
+        let (start, end) = if self.contains(VfsFallocFlags::ZERO_RANGE) {
+            if self.contains(VfsFallocFlags::KEEP_SIZE) {
+                (offset, end.min(size))
+            } else {
+                (offset, end)
+            }
+        } else if end <= size {
+            return Ok(());
+        } else if self.contains(VfsFallocFlags::KEEP_SIZE) {
+            return Err(VfsError::EOPNOTSUPP);
+        } else {
+            (size, end)
+        };
+        let zeros = [0; 4096];
+        let mut pos = start;
+        while pos < end {
+            let n = ((end - pos) as usize).min(zeros.len());
+            let n = write(pos, &zeros[..n])?;
+            if n == 0 {
+                return Err(VfsError::NoSpace);
+            }
+            pos += n as u64;
+        }
+        Ok(())
+    }
+}
+
+/// SEEK_DATA / SEEK_HOLE of lseek(2)
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VfsSeekDataHole {
+    /// Find the next data region at or after the offset
+    Data,
+    /// Find the next hole at or after the offset, the end of file is an implicit hole
+    Hole,
+}
@@ -0,0 +1,151 @@
+//! Wait queue for poll.
+//!
+//! A file which may become ready later (pipe, tty, device) keeps a [`VfsWaitQueue`]. When
+//! [`VfsFile::poll_register`](crate::file::VfsFile::poll_register) finds the file not ready, the
+//! waker of the caller is registered, and the file calls [`VfsWaitQueue::wake`] when its readiness
+//! changes. ppoll and epoll can be built on top of it by the kernel.
+use alloc::vec::Vec;
+use core::task::Waker;
+
+use lock_api::{Mutex, RawMutex};
+
+use crate::utils::VfsPollEvents;
+
+/// The key of a registered waiter, used to remove it before it is woken
+pub type VfsWaiterKey = u64;
+
+struct VfsWaiter {
+    key: VfsWaiterKey,
+    event: VfsPollEvents,
+    waker: Waker,
+}
+
+pub struct VfsWaitQueue<R: RawMutex> {
+    inner: Mutex<R, VfsWaitQueueInner>,
+}
+
+struct VfsWaitQueueInner {
+    next_key: VfsWaiterKey,
+    waiters: Vec<VfsWaiter>,
+}
+
+impl<R: RawMutex> Default for VfsWaitQueue<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: RawMutex> VfsWaitQueue<R> {
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::const_new(
+                R::INIT,
+                VfsWaitQueueInner {
+                    next_key: 0,
+                    waiters: Vec::new(),
+                },
+            ),
+        }
+    }
+
+    /// Register a waiter which is interested in `event`.
+    ///
+    /// If the same waker is registered already, its events are merged.
+    pub fn register(&self, event: VfsPollEvents, waker: &Waker) -> VfsWaiterKey {
+        let mut inner = self.inner.lock();
+        if let Some(waiter) = inner.waiters.iter_mut().find(|w| w.waker.will_wake(waker)) {
+            waiter.event |= event;
+            return waiter.key;
+        }
+        let key = inner.next_key;
+        inner.next_key += 1;
+        inner.waiters.push(VfsWaiter {
+            key,
+            event,
+            waker: waker.clone(),
+        });
+        key
+    }
+
+    /// Remove a waiter, return false if it has been woken or removed already
+    pub fn unregister(&self, key: VfsWaiterKey) -> bool {
+        let mut inner = self.inner.lock();
+        let len = inner.waiters.len();
+        inner.waiters.retain(|w| w.key != key);
+        inner.waiters.len() != len
+    }
+
+    /// Wake the waiters interested in `event` and remove them from the queue.
+    ///
+    /// [`VfsPollEvents::ERR`] and [`VfsPollEvents::HUP`] wake all waiters, like Linux which always
+    /// reports them. Return the number of waiters woken.
+    pub fn wake(&self, event: VfsPollEvents) -> usize {
+        let all = event.intersects(VfsPollEvents::ERR | VfsPollEvents::HUP);
+        let woken = {
+            let mut inner = self.inner.lock();
+            let mut woken = Vec::new();
+            let mut i = 0;
+            while i < inner.waiters.len() {
+                if all || inner.waiters[i].event.intersects(event) {
+                    woken.push(inner.waiters.swap_remove(i));
+                } else {
+                    i += 1;
+                }
+            }
+            woken
+        };
+        // wake outside the lock, the waker may poll the file again
+        let count = woken.len();
+        woken.into_iter().for_each(|w| w.waker.wake());
+        count
+    }
+
+    /// Wake all waiters
+    pub fn wake_all(&self) -> usize {
+        self.wake(VfsPollEvents::all())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().waiters.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{sync::Arc, task::Wake};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use spin::Mutex;
+
+    use super::*;
+
+    struct CountWaker(AtomicUsize);
+    impl Wake for CountWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_wait_queue() {
+        let queue = VfsWaitQueue::<Mutex<()>>::new();
+        let reader = Arc::new(CountWaker(AtomicUsize::new(0)));
+        let writer = Arc::new(CountWaker(AtomicUsize::new(0)));
+        let reader_waker = Waker::from(reader.clone());
+        let writer_waker = Waker::from(writer.clone());
+        let key = queue.register(VfsPollEvents::IN, &reader_waker);
+        assert_eq!(queue.register(VfsPollEvents::IN, &reader_waker), key);
+        queue.register(VfsPollEvents::OUT, &writer_waker);
+
+        assert_eq!(queue.wake(VfsPollEvents::IN), 1);
+        assert_eq!(reader.0.load(Ordering::SeqCst), 1);
+        assert_eq!(writer.0.load(Ordering::SeqCst), 0);
+        // the reader is removed after it is woken
+        assert!(!queue.unregister(key));
+
+        queue.register(VfsPollEvents::IN, &reader_waker);
+        assert_eq!(queue.wake(VfsPollEvents::HUP), 2);
+        assert_eq!(writer.0.load(Ordering::SeqCst), 1);
+        assert!(queue.is_empty());
+    }
+}
@@ -0,0 +1,320 @@
+//! Walk a directory tree, like `find` or the walkdir crate.
+//!
+//! [`VfsWalker`] is an iterator over the entries below a dentry. The entries are yielded with
+//! their metadata, and an entry which can't be read is yielded as a [`VfsWalkError`] instead of
+//! stopping the walk.
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    dentry::VfsDentry,
+    error::VfsError,
+    path::{is_same_dentry, real_dentry_down, VfsPath, PATH_MAX},
+    superblock::VfsSuperBlock,
+    utils::{VfsFileStat, VfsNodeType},
+    VfsResult,
+};
+
+/// The order of [`VfsWalker`]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum VfsWalkOrder {
+    /// A directory is followed by its subtree, like `find`
+    #[default]
+    DepthFirst,
+    /// All entries at one depth are yielded before the deeper ones
+    BreadthFirst,
+}
+
+/// An entry yielded by [`VfsWalker`]
+pub struct VfsWalkEntry {
+    /// The dentry of the entry, which is the target if it is a followed symlink
+    pub dentry: Arc<dyn VfsDentry>,
+    /// The path relative to the start of the walk, it is empty for the start
+    pub path: String,
+    pub name: String,
+    /// The depth below the start of the walk, the start is 0
+    pub depth: usize,
+    pub ty: VfsNodeType,
+    pub stat: VfsFileStat,
+}
+
+impl VfsWalkEntry {
+    /// Read the target of the symlink
+    pub fn read_link(&self) -> VfsResult<String> {
+        let mut buf = vec![0; PATH_MAX];
+        let len = self.dentry.inode()?.readlink(&mut buf)?;
+        let target = core::str::from_utf8(&buf[..len]).map_err(|_| VfsError::Invalid)?;
+        Ok(target.to_string())
+    }
+}
+
+/// An entry which can't be read by [`VfsWalker`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsWalkError {
+    /// The path relative to the start of the walk
+    pub path: String,
+    pub depth: usize,
+    pub error: VfsError,
+}
+
+type VfsWalkFilter = Box<dyn FnMut(&VfsWalkEntry) -> bool>;
+
+struct Pending {
+    dentry: Arc<dyn VfsDentry>,
+    path: String,
+    name: String,
+    depth: usize,
+    /// The directories above this entry, used to find symlink loops
+    ancestors: Vec<Arc<dyn VfsDentry>>,
+}
+
+/// A directory tree iterator
+///
+/// # Example
+/// ```compile_fail
+/// use vfscore::walk::VfsWalker;
+/// for entry in VfsWalker::new(root).max_depth(2) {
+///     let entry = entry?;
+///     println!("{}", entry.path);
+/// }
+/// ```
+pub struct VfsWalker {
+    start: Arc<dyn VfsDentry>,
+    root: Arc<dyn VfsDentry>,
+    order: VfsWalkOrder,
+    max_depth: usize,
+    follow_symlinks: bool,
+    same_fs: Option<Arc<dyn VfsSuperBlock>>,
+    filter: Option<VfsWalkFilter>,
+    queue: VecDeque<Result<Pending, VfsWalkError>>,
+}
+
+impl VfsWalker {
+    /// Walk the tree below `start`, the start itself is the first entry
+    pub fn new(start: Arc<dyn VfsDentry>) -> Self {
+        let mut queue = VecDeque::new();
+        queue.push_back(Ok(Pending {
+            dentry: start.clone(),
+            path: String::new(),
+            name: start.name(),
+            depth: 0,
+            ancestors: Vec::new(),
+        }));
+        Self {
+            start: start.clone(),
+            root: start,
+            order: VfsWalkOrder::DepthFirst,
+            max_depth: usize::MAX,
+            follow_symlinks: false,
+            same_fs: None,
+            filter: None,
+            queue,
+        }
+    }
+    /// The root to resolve absolute symlinks from, the default is the start of the walk
+    pub fn root(mut self, root: Arc<dyn VfsDentry>) -> Self {
+        self.root = root;
+        self
+    }
+    pub fn order(mut self, order: VfsWalkOrder) -> Self {
+        self.order = order;
+        self
+    }
+    /// Don't descend into the directories at `max_depth`, 0 yields only the start
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+    /// Yield the targets of symlinks instead of the symlinks, and descend into them if they are
+    /// directories. A symlink to one of its parents is yielded as [`VfsError::ELOOP`].
+    pub fn follow_symlinks(mut self, follow: bool) -> Self {
+        self.follow_symlinks = follow;
+        self
+    }
+    /// Don't descend into the filesystems mounted below the start, like `find -xdev`
+    ///
+    /// The mount points are still yielded.
+    pub fn same_fs(mut self, same_fs: bool) -> VfsResult<Self> {
+        self.same_fs = if same_fs {
+            let start = real_dentry_down(self.start.clone());
+            Some(start.inode()?.get_super_block()?)
+        } else {
+            None
+        };
+        Ok(self)
+    }
+    /// Skip the entries for which `filter` returns false, the skipped directories are not
+    /// descended into
+    pub fn filter<F>(mut self, filter: F) -> Self
+    where
+        F: FnMut(&VfsWalkEntry) -> bool + 'static,
+    {
+        self.filter = Some(Box::new(filter));
+        self
+    }
+
+    fn pop(&mut self) -> Option<Result<Pending, VfsWalkError>> {
+        match self.order {
+            VfsWalkOrder::DepthFirst => self.queue.pop_back(),
+            VfsWalkOrder::BreadthFirst => self.queue.pop_front(),
+        }
+    }
+
+    fn resolve(&self, pending: &Pending) -> VfsResult<VfsWalkEntry> {
+        let mut dentry = real_dentry_down(pending.dentry.clone());
+        let mut inode = dentry.inode()?;
+        if self.follow_symlinks && inode.inode_type() == VfsNodeType::SymLink {
+            let parent = dentry.parent().ok_or(VfsError::Invalid)?;
+            let mut buf = vec![0; PATH_MAX];
+            let len = inode.readlink(&mut buf)?;
+            let target = core::str::from_utf8(&buf[..len]).map_err(|_| VfsError::Invalid)?;
+            let path = if target.starts_with('/') {
+                VfsPath::new(self.root.clone(), self.root.clone())
+            } else {
+                VfsPath::new(self.root.clone(), parent)
+            };
+            dentry = path.join(target)?.resolve(true)?;
+            inode = dentry.inode()?;
+        }
+        let ty = inode.inode_type();
+        if ty == VfsNodeType::Dir
+            && pending
+                .ancestors
+                .iter()
+                .any(|ancestor| is_same_dentry(ancestor, &dentry))
+        {
+            return Err(VfsError::ELOOP);
+        }
+        let stat = inode.get_attr()?;
+        Ok(VfsWalkEntry {
+            dentry,
+            path: pending.path.clone(),
+            name: pending.name.clone(),
+            depth: pending.depth,
+            ty,
+            stat,
+        })
+    }
+
+    /// Queue the children of a directory
+    fn expand(&mut self, entry: &VfsWalkEntry, ancestors: Vec<Arc<dyn VfsDentry>>) {
+        let mut children = Vec::new();
+        let error = |error| VfsWalkError {
+            path: entry.path.clone(),
+            depth: entry.depth,
+            error,
+        };
+        let inode = match entry.dentry.inode() {
+            Ok(inode) => inode,
+            Err(e) => {
+                children.push(Err(error(e)));
+                return self.push(children);
+            }
+        };
+        let mut ancestors = ancestors;
+        ancestors.push(entry.dentry.clone());
+        let mut cookie = 0;
+        loop {
+            let dir_entry = match inode.readdir(cookie) {
+                Ok(Some(dir_entry)) => dir_entry,
+                Ok(None) => break,
+                Err(e) => {
+                    children.push(Err(error(e)));
+                    break;
+                }
+            };
+            cookie = dir_entry.off;
+            let name = dir_entry.name;
+            if name == "." || name == ".." {
+                continue;
+            }
+            let path = if entry.path.is_empty() {
+                name.clone()
+            } else {
+                entry.path.clone() + "/" + &name
+            };
+            let child = match entry.dentry.find(&name) {
+                Some(child) => Ok(child),
+                None => inode
+                    .lookup(&name)
+                    .and_then(|child| entry.dentry.i_insert(&name, child)),
+            };
+            children.push(match child {
+                Ok(dentry) => Ok(Pending {
+                    dentry,
+                    path,
+                    name,
+                    depth: entry.depth + 1,
+                    ancestors: ancestors.clone(),
+                }),
+                Err(error) => Err(VfsWalkError {
+                    path,
+                    depth: entry.depth + 1,
+                    error,
+                }),
+            });
+        }
+        self.push(children);
+    }
+
+    fn push(&mut self, children: Vec<Result<Pending, VfsWalkError>>) {
+        match self.order {
+            // the first child is popped first
+            VfsWalkOrder::DepthFirst => self.queue.extend(children.into_iter().rev()),
+            VfsWalkOrder::BreadthFirst => self.queue.extend(children),
+        }
+    }
+
+    /// Whether the directory is on the filesystem of the start
+    fn on_same_fs(&self, entry: &VfsWalkEntry) -> bool {
+        match &self.same_fs {
+            Some(sb) => entry
+                .dentry
+                .inode()
+                .and_then(|inode| inode.get_super_block())
+                .map(|entry_sb| Arc::ptr_eq(sb, &entry_sb))
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+}
+
+impl Iterator for VfsWalker {
+    type Item = Result<VfsWalkEntry, VfsWalkError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let pending = match self.pop()? {
+                Ok(pending) => pending,
+                Err(e) => return Some(Err(e)),
+            };
+            let entry = match self.resolve(&pending) {
+                Ok(entry) => entry,
+                Err(error) => {
+                    return Some(Err(VfsWalkError {
+                        path: pending.path,
+                        depth: pending.depth,
+                        error,
+                    }))
+                }
+            };
+            if let Some(filter) = self.filter.as_mut() {
+                if !filter(&entry) {
+                    continue;
+                }
+            }
+            if entry.ty == VfsNodeType::Dir
+                && entry.depth < self.max_depth
+                && self.on_same_fs(&entry)
+            {
+                self.expand(&entry, pending.ancestors);
+            }
+            return Some(Ok(entry));
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use ramfs::{RamFs, RamFsProvider};
+use spin::{Lazy, Mutex};
+use vfscore::{
+    dentry::VfsDentry,
+    error::VfsError,
+    fstype::VfsFsType,
+    path::VfsPath,
+    utils::{VfsInodeMode, VfsNodePerm, VfsTimeSpec},
+    VfsResult,
+};
+
+static FS: Lazy<Mutex<Arc<dyn VfsFsType>>> =
+    Lazy::new(|| Mutex::new(Arc::new(RamFs::<_, Mutex<()>>::new(RamFsProviderImpl))));
+
+#[derive(Clone)]
+struct RamFsProviderImpl;
+impl RamFsProvider for RamFsProviderImpl {
+    fn current_time(&self) -> VfsTimeSpec {
+        Default::default()
+    }
+}
+
+fn make_ramfs() -> VfsResult<Arc<dyn VfsDentry>> {
+    FS.lock().clone().mount(0, "/", None, &[])
+}
+
+/// /
+/// |-- .hidden
+/// |-- bin
+/// |   |-- cat
+/// |   `-- ls
+/// |-- etc
+/// |   |-- .profile
+/// |   `-- init.d
+/// |       `-- rc.sh
+/// `-- mnt (another ramfs)
+///     |-- a.sh
+///     `-- .git
+///         `-- b.sh
+fn make_tree() -> VfsPath {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root);
+    let perm = VfsNodePerm::from_bits_truncate(0o755);
+    let file = VfsInodeMode::from_bits_truncate(0o644) | VfsInodeMode::FILE;
+    for dir in ["bin", "etc/init.d", "mnt"] {
+        path.join(dir).unwrap().create_dir_all(perm).unwrap();
+    }
+    path.join("mnt")
+        .unwrap()
+        .mount(make_ramfs().unwrap(), 0)
+        .unwrap();
+    path.join("mnt/.git").unwrap().create_dir_all(perm).unwrap();
+    // created out of order, the results are sorted
+    for f in [
+        ".hidden",
+        "bin/ls",
+        "bin/cat",
+        "etc/.profile",
+        "etc/init.d/rc.sh",
+        "mnt/a.sh",
+        "mnt/.git/b.sh",
+    ] {
+        path.join(f).unwrap().open(Some(file)).unwrap();
+    }
+    path
+}
+
+fn glob(path: &VfsPath, pattern: &str) -> Vec<String> {
+    path.glob(pattern)
+        .unwrap()
+        .map(|path| path.unwrap().as_str().to_string())
+        .collect()
+}
+
+#[test]
+fn test_glob() {
+    let path = make_tree();
+    assert_eq!(glob(&path, "*"), ["/bin", "/etc", "/mnt"]);
+    assert_eq!(glob(&path, ".*"), ["/.hidden"]);
+    assert_eq!(glob(&path, "bin/*"), ["/bin/cat", "/bin/ls"]);
+    assert_eq!(glob(&path, "bin/?s"), ["/bin/ls"]);
+    assert_eq!(glob(&path, "bin/[a-k]*"), ["/bin/cat"]);
+    assert_eq!(glob(&path, "bin/[!a-k]*"), ["/bin/ls"]);
+    assert_eq!(glob(&path, "*/"), ["/bin", "/etc", "/mnt"]);
+    assert_eq!(glob(&path, "*/*.sh"), ["/mnt/a.sh"]);
+    assert_eq!(glob(&path, "**/*.sh"), ["/etc/init.d/rc.sh", "/mnt/a.sh"]);
+    assert_eq!(glob(&path, "mnt/.*/*.sh"), ["/mnt/.git/b.sh"]);
+    assert_eq!(glob(&path, "etc/init.d/rc.sh"), ["/etc/init.d/rc.sh"]);
+    assert!(glob(&path, "etc/none/*").is_empty());
+    assert!(glob(&path, "bin/cat/*").is_empty());
+
+    // relative to a directory, or from the root
+    let etc = path.join("etc").unwrap();
+    assert_eq!(glob(&etc, "*/*"), ["/etc/init.d/rc.sh"]);
+    assert_eq!(glob(&etc, "/bin/c*"), ["/bin/cat"]);
+    assert_eq!(path.glob("bin/[a").err(), Some(VfsError::Invalid));
+
+    // the matches of `**` are sorted by their whole paths, not by the depth
+    let perm = VfsNodePerm::from_bits_truncate(0o755);
+    let file = VfsInodeMode::from_bits_truncate(0o644) | VfsInodeMode::FILE;
+    path.join("a/b").unwrap().create_dir_all(perm).unwrap();
+    path.join("a/x").unwrap().open(Some(file)).unwrap();
+    path.join("a/b/x").unwrap().open(Some(file)).unwrap();
+    assert_eq!(glob(&path, "a/**/x"), ["/a/b/x", "/a/x"]);
+    assert_eq!(glob(&path, "**/x"), ["/a/b/x", "/a/x"]);
+    assert_eq!(glob(&path, "a/**"), ["/a", "/a/b"]);
+    assert_eq!(glob(&path, "**/b/**/x"), ["/a/b/x"]);
+}
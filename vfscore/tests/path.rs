@@ -3,10 +3,14 @@ use std::sync::Arc;
 use ramfs::{RamFs, RamFsProvider};
 use spin::{Lazy, Mutex};
 use vfscore::{
+    cred::{VfsCapabilities, VfsCred},
     dentry::VfsDentry,
+    error::VfsError,
     fstype::VfsFsType,
-    path::VfsPath,
-    utils::{VfsInodeMode, VfsNodeType, VfsTimeSpec},
+    path::{d_path, SysContext, VfsPath, MAX_SYMLINKS},
+    utils::{
+        VfsInodeMode, VfsNodePerm, VfsNodeType, VfsRenameFlag, VfsTimeSet, VfsTimeSpec, VfsTimes,
+    },
     VfsResult,
 };
 
@@ -88,14 +92,466 @@ fn test_dentry_path() {
     assert_eq!(path, "/d1/dd1");
 }
 
+#[test]
+fn test_chroot() {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let dir = VfsInodeMode::from_bits_truncate(0o777) | VfsInodeMode::DIR;
+    let jail = path.join("jail").unwrap().open(Some(dir)).unwrap();
+    let sub = path.join("jail/sub").unwrap().open(Some(dir)).unwrap();
+    let other = path.join("other").unwrap().open(Some(dir)).unwrap();
+
+    // `..` stops at the root of the process
+    let jail_path = VfsPath::new(jail.clone(), sub.clone());
+    let res = jail_path.join("../../..").unwrap().open(None).unwrap();
+    assert!(Arc::ptr_eq(&res, &jail));
+    let res = jail_path.join("../../sub").unwrap().open(None).unwrap();
+    assert!(Arc::ptr_eq(&res, &sub));
+
+    let context = SysContext {
+        pid: 0,
+        cred: VfsCred::root(),
+        umask: VfsNodePerm::empty(),
+        cwd: sub.clone(),
+        root: jail.clone(),
+    };
+    assert_eq!(d_path(&sub, &context), "/sub");
+    assert_eq!(d_path(&jail, &context), "/");
+    assert_eq!(d_path(&other, &context), "(unreachable)/other");
+    assert_eq!(d_path(&root, &context), "(unreachable)/");
+
+    // a path with the context is jailed in the root of the context
+    let user_path = VfsPath::new(root.clone(), sub.clone()).with_context(context.clone());
+    let res = user_path.join("../../..").unwrap().open(None).unwrap();
+    assert!(Arc::ptr_eq(&res, &jail));
+    path.join("jail/abs").unwrap().symlink("/sub").unwrap();
+    let res = user_path.join("../abs").unwrap().open(None).unwrap();
+    assert!(Arc::ptr_eq(&res, &sub));
+
+    // mount points are crossed in both directions
+    let new_root = make_ramfs().unwrap();
+    path.join("jail/sub")
+        .unwrap()
+        .mount(new_root.clone(), 0)
+        .unwrap();
+    let file = jail_path
+        .join("f")
+        .unwrap()
+        .open(Some(
+            VfsInodeMode::from_bits_truncate(0o666) | VfsInodeMode::FILE,
+        ))
+        .unwrap();
+    assert_eq!(d_path(&file, &context), "/sub/f");
+    let res = jail_path.join("../../..").unwrap().open(None).unwrap();
+    assert!(Arc::ptr_eq(&res, &jail));
+    let context = SysContext {
+        root: new_root.clone(),
+        ..context
+    };
+    assert_eq!(d_path(&file, &context), "/f");
+    assert_eq!(d_path(&sub, &context), "/");
+}
+
+#[test]
+fn test_name_too_long() {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let file = VfsInodeMode::from_bits_truncate(0o666) | VfsInodeMode::FILE;
+    let name = "a".repeat(255);
+    let long_name = "a".repeat(256);
+
+    let f = path.join(&name).unwrap().open(Some(file)).unwrap();
+    let res = path.join(&long_name).unwrap().open(Some(file));
+    assert_eq!(res.err(), Some(VfsError::NameTooLong));
+    let res = path.join(&long_name).unwrap().symlink("f");
+    assert_eq!(res, Err(VfsError::NameTooLong));
+    let res = path.join(&long_name).unwrap().link(f);
+    assert_eq!(res, Err(VfsError::NameTooLong));
+    let context = SysContext {
+        pid: 0,
+        cred: VfsCred::root(),
+        umask: VfsNodePerm::empty(),
+        cwd: root.clone(),
+        root: root.clone(),
+    };
+    let res = path.join(&name).unwrap().rename_to(
+        context,
+        path.join(&long_name).unwrap(),
+        VfsRenameFlag::empty(),
+    );
+    assert_eq!(res, Err(VfsError::NameTooLong));
+
+    // the total length of the path
+    let path = path.with_path_max(16);
+    assert!(path.join("0123456789").is_ok());
+    let res = path.join("0123456789").unwrap().join("0123456789");
+    assert_eq!(res.err(), Some(VfsError::NameTooLong));
+    let res = path.join("0123456789abcdef0");
+    assert_eq!(res.err(), Some(VfsError::NameTooLong));
+}
+
 #[test]
 fn test_link() {}
 
 #[test]
-fn test_symlink() {}
+fn test_symlink() {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let file = VfsInodeMode::from_bits_truncate(0o644) | VfsInodeMode::FILE;
+    path.join("f").unwrap().open(Some(file)).unwrap();
+
+    // a chain of MAX_SYMLINKS symlinks can be followed, but not a longer one
+    path.join("l1").unwrap().symlink("f").unwrap();
+    for i in 2..=MAX_SYMLINKS + 1 {
+        let target = format!("l{}", i - 1);
+        path.join(format!("l{i}"))
+            .unwrap()
+            .symlink(&target)
+            .unwrap();
+    }
+    let f = path.join(format!("l{MAX_SYMLINKS}")).unwrap().open(None);
+    assert_eq!(f.unwrap().inode().unwrap().inode_type(), VfsNodeType::File);
+    let res = path
+        .join(format!("l{}", MAX_SYMLINKS + 1))
+        .unwrap()
+        .open(None);
+    assert_eq!(res.err(), Some(VfsError::ELOOP));
+
+    // the loops are found
+    path.join("self").unwrap().symlink("self").unwrap();
+    let res = path.join("self").unwrap().open(None);
+    assert_eq!(res.err(), Some(VfsError::ELOOP));
+    path.join("a").unwrap().symlink("/b").unwrap();
+    path.join("b").unwrap().symlink("a").unwrap();
+    let res = path.join("a").unwrap().open(None);
+    assert_eq!(res.err(), Some(VfsError::ELOOP));
+    let res = path.join("a").unwrap().open(Some(file));
+    assert_eq!(res.err(), Some(VfsError::ELOOP));
+}
 
 #[test]
 fn test_unlink() {}
 
 #[test]
 fn test_rename() {}
+
+#[test]
+fn test_umask() {
+    let root = make_ramfs().unwrap();
+    let context = SysContext {
+        pid: 0,
+        cred: VfsCred::root(),
+        umask: VfsNodePerm::from_bits_truncate(0o027),
+        cwd: root.clone(),
+        root: root.clone(),
+    };
+    let path = VfsPath::new(root.clone(), root.clone()).with_context(context);
+    let file = VfsInodeMode::from_bits_truncate(0o666) | VfsInodeMode::FILE;
+    let dir = VfsInodeMode::from_bits_truncate(0o777) | VfsInodeMode::DIR;
+
+    let f = path.join("f").unwrap().open(Some(file)).unwrap();
+    assert_eq!(f.inode().unwrap().node_perm().bits(), 0o640);
+    let d = path.join("d").unwrap().open(Some(dir)).unwrap();
+    assert_eq!(d.inode().unwrap().node_perm().bits(), 0o750);
+    let d = path
+        .join("a/b")
+        .unwrap()
+        .create_dir_all(VfsNodePerm::from_bits_truncate(0o777))
+        .unwrap();
+    assert_eq!(d.inode().unwrap().node_perm().bits(), 0o750);
+    let f = path
+        .join("a/b/n")
+        .unwrap()
+        .mknod(VfsInodeMode::from_bits_truncate(0o644), 0)
+        .unwrap();
+    assert_eq!(f.inode().unwrap().inode_type(), VfsNodeType::File);
+    assert_eq!(f.inode().unwrap().node_perm().bits(), 0o640);
+    let res = path.join("l").unwrap().mknod(VfsInodeMode::LINK, 0);
+    assert_eq!(res.err(), Some(VfsError::Invalid));
+
+    // the paths without a context keep the permissions
+    let path = VfsPath::new(root.clone(), root.clone());
+    let f = path.join("g").unwrap().open(Some(file)).unwrap();
+    assert_eq!(f.inode().unwrap().node_perm().bits(), 0o666);
+
+    // the new inodes are owned by the caller, or get the group of a setgid directory
+    let context = SysContext {
+        pid: 1,
+        cred: VfsCred::user(1000, 100),
+        umask: VfsNodePerm::from_bits_truncate(0o022),
+        cwd: root.clone(),
+        root: root.clone(),
+    };
+    let user = path.clone().with_context(context);
+    let owner = |dentry: &Arc<dyn VfsDentry>| {
+        let stat = dentry.inode().unwrap().get_attr().unwrap();
+        (stat.st_uid, stat.st_gid, stat.st_mode)
+    };
+    path.join("pub")
+        .unwrap()
+        .open(Some(
+            VfsInodeMode::from_bits_truncate(0o777) | VfsInodeMode::DIR,
+        ))
+        .unwrap();
+    let f = user.join("pub/f").unwrap().open(Some(file)).unwrap();
+    assert_eq!(owner(&f), (1000, 100, 0o100644));
+    let sg = path.join("sg").unwrap();
+    sg.open(Some(
+        VfsInodeMode::from_bits_truncate(0o2777) | VfsInodeMode::DIR,
+    ))
+    .unwrap();
+    sg.chown(None, Some(50)).unwrap();
+    let f = user.join("sg/f").unwrap().open(Some(file)).unwrap();
+    assert_eq!(owner(&f), (1000, 50, 0o100644));
+    let d = user.join("sg/d").unwrap().open(Some(dir)).unwrap();
+    assert_eq!(owner(&d), (1000, 50, 0o42755));
+    let f = user.join("sg/d/f").unwrap().open(Some(file)).unwrap();
+    assert_eq!(owner(&f), (1000, 50, 0o100644));
+}
+
+#[test]
+fn test_special_bits() {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let context = SysContext {
+        pid: 1,
+        cred: VfsCred::user(1000, 1000),
+        umask: VfsNodePerm::empty(),
+        cwd: root.clone(),
+        root: root.clone(),
+    };
+    let user = VfsPath::new(root.clone(), root.clone()).with_context(context.clone());
+    let dir = |perm| VfsInodeMode::from_bits_truncate(perm) | VfsInodeMode::DIR;
+    let file = |perm| VfsInodeMode::from_bits_truncate(perm) | VfsInodeMode::FILE;
+    let mode = |path: &VfsPath| {
+        path.open(None)
+            .unwrap()
+            .inode()
+            .unwrap()
+            .get_attr()
+            .unwrap()
+            .st_mode
+    };
+
+    // the entries of a sticky directory can only be removed by their owners
+    let tmp = path.join("tmp").unwrap();
+    let d = tmp.open(Some(dir(0o1777))).unwrap();
+    assert_eq!(mode(&tmp), 0o41777);
+    assert_eq!(&d.inode().unwrap().node_perm().rwx_buf(), b"rwxrwxrwt");
+    path.join("tmp/f").unwrap().open(Some(file(0o666))).unwrap();
+    path.join("tmp/d").unwrap().open(Some(dir(0o777))).unwrap();
+    let res = user.join("tmp/f").unwrap().unlink();
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    let res = user.join("tmp/d").unwrap().rmdir();
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    let res = path.join("tmp/f").unwrap().rename_to(
+        context.clone(),
+        path.join("tmp/g").unwrap(),
+        VfsRenameFlag::empty(),
+    );
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    // the owner of a symlink is checked, not the owner of its target
+    user.join("tmp/mine")
+        .unwrap()
+        .open(Some(file(0o644)))
+        .unwrap();
+    path.join("tmp/l").unwrap().symlink("mine").unwrap();
+    let res = path.join("tmp/l").unwrap().rename_to(
+        context.clone(),
+        path.join("tmp/m").unwrap(),
+        VfsRenameFlag::empty(),
+    );
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    let root_context = SysContext {
+        cred: VfsCred::root(),
+        ..context
+    };
+    path.join("tmp/l")
+        .unwrap()
+        .rename_to(
+            root_context,
+            path.join("tmp/m").unwrap(),
+            VfsRenameFlag::empty(),
+        )
+        .unwrap();
+    let m = path.join("tmp/m").unwrap().exists().unwrap();
+    assert_eq!(m.inode().unwrap().inode_type(), VfsNodeType::SymLink);
+    assert_eq!(mode(&path.join("tmp/mine").unwrap()), 0o100644);
+    path.join("tmp/f").unwrap().unlink().unwrap();
+    path.join("pub").unwrap().open(Some(dir(0o777))).unwrap();
+    path.join("pub/f").unwrap().open(Some(file(0o666))).unwrap();
+    user.join("pub/f").unwrap().unlink().unwrap();
+
+    // new directories inherit the setgid bit
+    path.join("sg").unwrap().open(Some(dir(0o2775))).unwrap();
+    let sub = path.join("sg/sub").unwrap();
+    sub.open(Some(dir(0o755))).unwrap();
+    assert_eq!(mode(&sub), 0o42755);
+    let f = path.join("sg/f").unwrap();
+    f.open(Some(file(0o644))).unwrap();
+    assert_eq!(mode(&f), 0o100644);
+
+    // changes by other users clear setuid and setgid
+    let bin = path.join("bin").unwrap();
+    bin.open(Some(file(0o6777))).unwrap();
+    assert_eq!(mode(&bin), 0o106777);
+    user.join("bin").unwrap().truncate(0).unwrap();
+    assert_eq!(mode(&bin), 0o100777);
+    let lock = path.join("lock").unwrap();
+    lock.open(Some(file(0o2666))).unwrap();
+    user.join("lock").unwrap().truncate(0).unwrap();
+    assert_eq!(mode(&lock), 0o102666);
+    path.join("root").unwrap().open(Some(file(0o4755))).unwrap();
+    let res = user.join("root").unwrap().chown(None, Some(1000));
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    path.join("root").unwrap().chown(Some(0), Some(0)).unwrap();
+    assert_eq!(mode(&path.join("root").unwrap()), 0o104755);
+}
+
+#[test]
+fn test_cred() {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let with_cred = |cred| {
+        path.clone().with_context(SysContext {
+            pid: 1,
+            cred,
+            umask: VfsNodePerm::empty(),
+            cwd: root.clone(),
+            root: root.clone(),
+        })
+    };
+    let file = |perm| VfsInodeMode::from_bits_truncate(perm) | VfsInodeMode::FILE;
+    let dir = |perm| VfsInodeMode::from_bits_truncate(perm) | VfsInodeMode::DIR;
+    let owner = |path: &VfsPath| {
+        let stat = path
+            .open(None)
+            .unwrap()
+            .inode()
+            .unwrap()
+            .get_attr()
+            .unwrap();
+        (stat.st_uid, stat.st_gid)
+    };
+
+    // the supplementary groups are checked like the fsgid
+    let shared = path.join("shared").unwrap();
+    shared.open(Some(dir(0o770))).unwrap();
+    shared.chown(None, Some(50)).unwrap();
+    let user = with_cred(VfsCred::user(1000, 100));
+    let res = user.join("shared/f").unwrap().open(Some(file(0o644)));
+    assert_eq!(res.err(), Some(VfsError::Access));
+    let member = with_cred(VfsCred::user(1000, 100).with_groups(vec![50]));
+    let f = member.join("shared/f").unwrap();
+    f.open(Some(file(0o644))).unwrap();
+    assert_eq!(owner(&f), (1000, 100));
+    // the owner can only give the file to one of their groups
+    assert_eq!(f.chown(None, Some(60)), Err(VfsError::PermissionDenied));
+    f.chown(None, Some(50)).unwrap();
+    assert_eq!(owner(&f), (1000, 50));
+
+    // uid 0 without capabilities is checked like any other user
+    let weak_root = with_cred(VfsCred::user(0, 0));
+    let res = weak_root.join("shared/f").unwrap().truncate(0);
+    assert_eq!(res, Err(VfsError::Access));
+    let res = weak_root.join("shared/f").unwrap().chown(Some(0), None);
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+
+    // the new inodes are owned by the fsuid and the fsgid
+    let admin = with_cred(
+        VfsCred::user(1000, 100)
+            .with_fs_ids(2000, 200)
+            .with_caps(VfsCapabilities::DAC_OVERRIDE),
+    );
+    let g = admin.join("g").unwrap();
+    g.open(Some(file(0o600))).unwrap();
+    assert_eq!(owner(&g), (2000, 200));
+    admin.join("shared/f").unwrap().truncate(0).unwrap();
+    let chown = with_cred(VfsCred::user(1000, 100).with_caps(VfsCapabilities::CHOWN));
+    chown
+        .join("g")
+        .unwrap()
+        .chown(Some(3000), Some(300))
+        .unwrap();
+    assert_eq!(owner(&g), (3000, 300));
+
+    // device files need MKNOD, mount and umount need SYS_ADMIN
+    let tty = VfsInodeMode::from_bits_truncate(0o600) | VfsInodeMode::CHAR;
+    let res = admin.join("tty").unwrap().mknod(tty, 5);
+    assert_eq!(res.err(), Some(VfsError::PermissionDenied));
+    path.join("mnt").unwrap().open(Some(dir(0o755))).unwrap();
+    let res = admin.join("mnt").unwrap().mount(make_ramfs().unwrap(), 0);
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    let sys_admin = with_cred(VfsCred::user(1000, 100).with_caps(VfsCapabilities::SYS_ADMIN));
+    sys_admin
+        .join("mnt")
+        .unwrap()
+        .mount(make_ramfs().unwrap(), 0)
+        .unwrap();
+    let res = admin.join("mnt").unwrap().umount();
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    sys_admin.join("mnt").unwrap().umount().unwrap();
+}
+
+#[test]
+fn test_chmod() {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let with_cred = |cred| {
+        path.clone().with_context(SysContext {
+            pid: 1,
+            cred,
+            umask: VfsNodePerm::empty(),
+            cwd: root.clone(),
+            root: root.clone(),
+        })
+    };
+    let user = with_cred(VfsCred::user(1000, 100));
+    let other = with_cred(VfsCred::user(1001, 100));
+    let stat = |path: &VfsPath| {
+        path.open(None)
+            .unwrap()
+            .inode()
+            .unwrap()
+            .get_attr()
+            .unwrap()
+    };
+    let file = VfsInodeMode::from_bits_truncate(0o666) | VfsInodeMode::FILE;
+    path.join("pub")
+        .unwrap()
+        .open(Some(
+            VfsInodeMode::from_bits_truncate(0o777) | VfsInodeMode::DIR,
+        ))
+        .unwrap();
+    let f = user.join("pub/f").unwrap();
+    f.open(Some(file)).unwrap();
+
+    // only the owner can chmod, and the setgid bit needs the group of the file
+    f.chmod(VfsNodePerm::from_bits_truncate(0o2640)).unwrap();
+    assert_eq!(stat(&f).st_mode, 0o102640);
+    let res = other
+        .join("pub/f")
+        .unwrap()
+        .chmod(VfsNodePerm::from_bits_truncate(0o777));
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    path.join("pub/f").unwrap().chown(None, Some(50)).unwrap();
+    f.chmod(VfsNodePerm::from_bits_truncate(0o2666)).unwrap();
+    assert_eq!(stat(&f).st_mode, 0o100666);
+
+    // anyone who can write can touch, only the owner can set other times
+    let now = VfsTimeSpec::new(10, 0);
+    other
+        .join("pub/f")
+        .unwrap()
+        .set_times(VfsTimes::now(), now)
+        .unwrap();
+    assert_eq!(stat(&f).st_mtime, now);
+    let times = VfsTimes::new(VfsTimeSet::Set(VfsTimeSpec::new(1, 0)), VfsTimeSet::Omit);
+    let res = other.join("pub/f").unwrap().set_times(times, now);
+    assert_eq!(res, Err(VfsError::PermissionDenied));
+    f.set_times(times, now).unwrap();
+    assert_eq!(stat(&f).st_atime, VfsTimeSpec::new(1, 0));
+    f.chmod(VfsNodePerm::from_bits_truncate(0o644)).unwrap();
+    let res = other.join("pub/f").unwrap().set_times(VfsTimes::now(), now);
+    assert_eq!(res, Err(VfsError::Access));
+}
@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use ramfs::{RamFs, RamFsProvider};
+use spin::{Lazy, Mutex};
+use vfscore::{
+    acl::{VfsAcl, XATTR_POSIX_ACL_ACCESS},
+    cred::VfsCred,
+    dentry::VfsDentry,
+    error::VfsError,
+    fstype::VfsFsType,
+    inode::InodeAttr,
+    path::{SysContext, VfsPath},
+    security::{set_security_hooks, VfsSecurityHooks},
+    utils::{VfsInodeMode, VfsNodePerm, VfsRenameFlag, VfsTimeSpec, VfsTimes},
+    VfsResult,
+};
+
+static FS: Lazy<Mutex<Arc<dyn VfsFsType>>> =
+    Lazy::new(|| Mutex::new(Arc::new(RamFs::<_, Mutex<()>>::new(RamFsProviderImpl))));
+
+#[derive(Clone)]
+struct RamFsProviderImpl;
+impl RamFsProvider for RamFsProviderImpl {
+    fn current_time(&self) -> VfsTimeSpec {
+        Default::default()
+    }
+}
+
+fn make_ramfs() -> VfsResult<Arc<dyn VfsDentry>> {
+    FS.lock().clone().mount(0, "/", None, &[])
+}
+
+/// Records the calls, and denies everything named `secret` to the users
+struct Hooks {
+    calls: Mutex<Vec<String>>,
+}
+
+impl Hooks {
+    fn check(&self, cred: Option<&VfsCred>, call: String, name: &str) -> VfsResult<()> {
+        let uid = cred.map(|cred| cred.fsuid);
+        self.calls.lock().push(format!("{call} {uid:?}"));
+        if uid.is_some_and(|uid| uid != 0) && name == "secret" {
+            return Err(VfsError::Access);
+        }
+        Ok(())
+    }
+}
+
+impl VfsSecurityHooks for Hooks {
+    fn lookup(&self, cred: Option<&VfsCred>, _: &Arc<dyn VfsDentry>, name: &str) -> VfsResult<()> {
+        self.check(cred, format!("lookup {name}"), name)
+    }
+    fn open(&self, cred: Option<&VfsCred>, dentry: &Arc<dyn VfsDentry>) -> VfsResult<()> {
+        self.check(cred, format!("open {}", dentry.name()), &dentry.name())
+    }
+    fn create(
+        &self,
+        cred: Option<&VfsCred>,
+        _: &Arc<dyn VfsDentry>,
+        name: &str,
+        mode: VfsInodeMode,
+    ) -> VfsResult<()> {
+        self.check(cred, format!("create {name} {:o}", mode.bits()), name)
+    }
+    fn link(
+        &self,
+        cred: Option<&VfsCred>,
+        old: &Arc<dyn VfsDentry>,
+        _: &Arc<dyn VfsDentry>,
+        name: &str,
+    ) -> VfsResult<()> {
+        self.check(cred, format!("link {} {name}", old.name()), name)
+    }
+    fn unlink(
+        &self,
+        cred: Option<&VfsCred>,
+        _: &Arc<dyn VfsDentry>,
+        dentry: &Arc<dyn VfsDentry>,
+    ) -> VfsResult<()> {
+        self.check(cred, format!("unlink {}", dentry.name()), &dentry.name())
+    }
+    fn rename(
+        &self,
+        cred: Option<&VfsCred>,
+        _: &Arc<dyn VfsDentry>,
+        old: &Arc<dyn VfsDentry>,
+        _: &Arc<dyn VfsDentry>,
+        new_name: &str,
+    ) -> VfsResult<()> {
+        self.check(cred, format!("rename {} {new_name}", old.name()), new_name)
+    }
+    fn setattr(
+        &self,
+        cred: Option<&VfsCred>,
+        dentry: &Arc<dyn VfsDentry>,
+        attr: &InodeAttr,
+    ) -> VfsResult<()> {
+        let call = format!("setattr {} {:?}", dentry.name(), attr.mask);
+        self.check(cred, call, &dentry.name())
+    }
+    fn setxattr(
+        &self,
+        cred: Option<&VfsCred>,
+        dentry: &Arc<dyn VfsDentry>,
+        name: &str,
+        _: &[u8],
+    ) -> VfsResult<()> {
+        self.check(cred, format!("setxattr {} {name}", dentry.name()), name)
+    }
+    fn mount(
+        &self,
+        cred: Option<&VfsCred>,
+        mount_point: &Arc<dyn VfsDentry>,
+        _: &Arc<dyn VfsDentry>,
+        _: u32,
+    ) -> VfsResult<()> {
+        self.check(cred, format!("mount {}", mount_point.name()), "")
+    }
+    fn umount(&self, cred: Option<&VfsCred>, mount_point: &Arc<dyn VfsDentry>) -> VfsResult<()> {
+        self.check(cred, format!("umount {}", mount_point.name()), "")
+    }
+}
+
+static HOOKS: Lazy<Hooks> = Lazy::new(|| Hooks {
+    calls: Mutex::new(Vec::new()),
+});
+
+fn calls() -> Vec<String> {
+    core::mem::take(&mut *HOOKS.calls.lock())
+}
+
+#[test]
+fn test_security_hooks() {
+    set_security_hooks(&*HOOKS).unwrap();
+    assert_eq!(set_security_hooks(&*HOOKS), Err(VfsError::EBUSY));
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let context = SysContext {
+        pid: 1,
+        cred: VfsCred::user(1000, 100),
+        umask: VfsNodePerm::empty(),
+        cwd: root.clone(),
+        root: root.clone(),
+    };
+    let user = path.clone().with_context(context.clone());
+    let file = VfsInodeMode::from_bits_truncate(0o666) | VfsInodeMode::FILE;
+    let dir = VfsInodeMode::from_bits_truncate(0o777) | VfsInodeMode::DIR;
+
+    // the paths without a context call the hooks without credentials
+    path.join("d").unwrap().open(Some(dir)).unwrap();
+    assert_eq!(calls(), ["lookup d None", "create d 40777 None"]);
+    path.join("d/f").unwrap().open(Some(file)).unwrap();
+    path.join("d/f").unwrap().open(None).unwrap();
+    assert_eq!(
+        calls(),
+        [
+            "lookup d None",
+            "lookup f None",
+            "lookup d None",
+            "create f 100666 None",
+            "lookup d None",
+            "lookup f None",
+            "open f None",
+        ]
+    );
+    path.join("d/secret").unwrap().open(Some(file)).unwrap();
+    calls();
+
+    // the other operations call their own hooks only
+    user.join("d/g")
+        .unwrap()
+        .link(path.join("d/f").unwrap().open(None).unwrap())
+        .unwrap();
+    user.join("d/f").unwrap().truncate(0).unwrap();
+    user.join("d/f").unwrap().set_xattr("user.a", b"1").unwrap();
+    // the permission checks come first, a denied chown, chmod or ACL doesn't reach the hook
+    user.join("d/f")
+        .unwrap()
+        .chown(None, Some(100))
+        .unwrap_err();
+    user.join("d/f")
+        .unwrap()
+        .chmod(VfsNodePerm::from_bits_truncate(0o600))
+        .unwrap_err();
+    let acl = VfsAcl::from_mode(0o600).to_xattr();
+    user.join("d/f")
+        .unwrap()
+        .set_xattr(XATTR_POSIX_ACL_ACCESS, &acl)
+        .unwrap_err();
+    user.join("d/l").unwrap().symlink("f").unwrap();
+    user.join("d/g")
+        .unwrap()
+        .rename_to(context, user.join("d/h").unwrap(), VfsRenameFlag::empty())
+        .unwrap();
+    user.join("d/h").unwrap().unlink().unwrap();
+    let u = user.join("d/u").unwrap();
+    u.open(Some(file)).unwrap();
+    u.chmod(VfsNodePerm::from_bits_truncate(0o600)).unwrap();
+    u.set_times(VfsTimes::now(), VfsTimeSpec::new(1, 0))
+        .unwrap();
+    let user_calls: Vec<_> = calls()
+        .into_iter()
+        .filter(|call| !call.starts_with("lookup") && !call.starts_with("open"))
+        .collect();
+    assert_eq!(
+        user_calls,
+        [
+            "link f g Some(1000)",
+            "setattr f SIZE Some(1000)",
+            "setxattr f user.a Some(1000)",
+            "create l 120777 Some(1000)",
+            "rename g h Some(1000)",
+            "unlink h Some(1000)",
+            "create u 100666 Some(1000)",
+            "setattr u MODE Some(1000)",
+            "setattr u ATIME | MTIME Some(1000)",
+        ]
+    );
+
+    // a hook denies the operations of the users
+    let res = user.join("d/secret").unwrap().open(None);
+    assert_eq!(res.err(), Some(VfsError::Access));
+    assert!(path.join("d/secret").unwrap().open(None).is_ok());
+    path.join("d/secret").unwrap().unlink().unwrap();
+
+    path.join("mnt").unwrap().open(Some(dir)).unwrap();
+    calls();
+    path.join("mnt")
+        .unwrap()
+        .mount(make_ramfs().unwrap(), 0)
+        .unwrap();
+    path.join("mnt").unwrap().umount().unwrap();
+    let mount_calls: Vec<_> = calls()
+        .into_iter()
+        .filter(|call| call.contains("mount"))
+        .collect();
+    assert_eq!(mount_calls, ["mount mnt None", "umount mnt None"]);
+}
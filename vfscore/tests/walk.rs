@@ -0,0 +1,123 @@
+use std::sync::Arc;
+
+use ramfs::{RamFs, RamFsProvider};
+use spin::{Lazy, Mutex};
+use vfscore::{
+    dentry::VfsDentry,
+    error::VfsError,
+    fstype::VfsFsType,
+    path::{print_fs_tree, VfsPath},
+    utils::{VfsInodeMode, VfsNodePerm, VfsTimeSpec},
+    walk::{VfsWalkOrder, VfsWalker},
+    VfsResult,
+};
+
+static FS: Lazy<Mutex<Arc<dyn VfsFsType>>> =
+    Lazy::new(|| Mutex::new(Arc::new(RamFs::<_, Mutex<()>>::new(RamFsProviderImpl))));
+
+#[derive(Clone)]
+struct RamFsProviderImpl;
+impl RamFsProvider for RamFsProviderImpl {
+    fn current_time(&self) -> VfsTimeSpec {
+        Default::default()
+    }
+}
+
+fn make_ramfs() -> VfsResult<Arc<dyn VfsDentry>> {
+    FS.lock().clone().mount(0, "/", None, &[])
+}
+
+/// /
+/// |-- a
+/// |   |-- b
+/// |   |   `-- f
+/// |   `-- l -> /a
+/// |-- c
+/// `-- m (another ramfs)
+///     `-- x
+fn make_tree() -> Arc<dyn VfsDentry> {
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    let perm = VfsNodePerm::from_bits_truncate(0o755);
+    let file = VfsInodeMode::from_bits_truncate(0o644) | VfsInodeMode::FILE;
+    path.join("a/b").unwrap().create_dir_all(perm).unwrap();
+    path.join("a/b/f").unwrap().open(Some(file)).unwrap();
+    path.join("a/l").unwrap().symlink("/a").unwrap();
+    path.join("c").unwrap().open(Some(file)).unwrap();
+    path.join("m").unwrap().create_dir_all(perm).unwrap();
+    let new_root = make_ramfs().unwrap();
+    path.join("m").unwrap().mount(new_root, 0).unwrap();
+    path.join("m/x").unwrap().open(Some(file)).unwrap();
+    root
+}
+
+fn paths(walker: VfsWalker) -> Vec<String> {
+    walker.map(|entry| entry.unwrap().path).collect()
+}
+
+#[test]
+fn test_walk_order() {
+    let root = make_tree();
+    let res = paths(VfsWalker::new(root.clone()));
+    assert_eq!(res, ["", "a", "a/b", "a/b/f", "a/l", "c", "m", "m/x"]);
+    let res = paths(VfsWalker::new(root.clone()).order(VfsWalkOrder::BreadthFirst));
+    assert_eq!(res, ["", "a", "c", "m", "a/b", "a/l", "m/x", "a/b/f"]);
+    let res = paths(VfsWalker::new(root).max_depth(1));
+    assert_eq!(res, ["", "a", "c", "m"]);
+}
+
+#[test]
+fn test_walk_policy() {
+    let root = make_tree();
+    let res = paths(VfsWalker::new(root.clone()).same_fs(true).unwrap());
+    assert_eq!(res, ["", "a", "a/b", "a/b/f", "a/l", "c", "m"]);
+    let res = paths(VfsWalker::new(root.clone()).filter(|entry| entry.name != "a"));
+    assert_eq!(res, ["", "c", "m", "m/x"]);
+
+    // the symlink points to its parent
+    let res = VfsWalker::new(root.clone())
+        .follow_symlinks(true)
+        .filter(|entry| entry.path.starts_with('a') || entry.depth == 0)
+        .map(|entry| entry.map(|entry| entry.path).map_err(|e| (e.path, e.error)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        res,
+        [
+            Ok("".to_string()),
+            Ok("a".to_string()),
+            Ok("a/b".to_string()),
+            Ok("a/b/f".to_string()),
+            Err(("a/l".to_string(), VfsError::ELOOP)),
+        ]
+    );
+
+    // a symlink to itself can't be followed
+    let root = make_ramfs().unwrap();
+    let path = VfsPath::new(root.clone(), root.clone());
+    path.join("l").unwrap().symlink("l").unwrap();
+    let res = VfsWalker::new(root)
+        .follow_symlinks(true)
+        .map(|entry| entry.map(|entry| entry.path).map_err(|e| (e.path, e.error)))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        res,
+        [Ok("".to_string()), Err(("l".to_string(), VfsError::ELOOP))]
+    );
+}
+
+#[test]
+fn test_print_fs_tree() {
+    let root = make_tree();
+    let mut output = String::new();
+    print_fs_tree(&mut output, root, "".to_string(), true).unwrap();
+    let names = output
+        .lines()
+        .map(|line| {
+            let indent = line.len() - line.trim_start().len();
+            let name = line.split_whitespace().nth(2).unwrap();
+            format!("{}{}", " ".repeat(indent), name)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["a", "  b", "    f", "  l", "c", "m", "  x"]);
+    assert!(output.contains("l -> /a"));
+}